@@ -1,39 +1,651 @@
-use std::io::{BufReader, Read};
+use std::{
+    ffi::OsString,
+    io::{BufReader, Read},
+};
 
-use camino::Utf8PathBuf;
-use clap::Parser;
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use env_logger::Builder;
 use fs_err::File;
 use log::LevelFilter;
 use miette::{Context, IntoDiagnostic};
 
-use spc_core::{parse, write_spc};
+use spc_core::{
+    convert_spc, detect_supported, import_csv, import_opus, import_wdf, inspect, metadata,
+    parse_with_options, write_andi, write_envi, write_mat, write_metadata, write_mzml, write_npz,
+    write_spc, write_spc_planes, write_xlsx, xzwType, yType, AndiWriter, CsvLayout, CsvReader,
+    CsvWriter, Delimiter, EnviInterleave, EnviWriter, FloatFormat, HeaderTimezone,
+    InstrumentTechnique, MatWriter, MetadataFormat, MetadataOptions, MzmlWriter, NpzWriter,
+    OpusReader, OpusSpectrum, ParseOptions, SpcVersion, SpcWriter, TextEncoding, WdfReader,
+    XlsxWriter,
+};
+
+#[cfg(feature = "arrow")]
+use spc_core::{write_arrow, ArrowFormat, ArrowLayout, ArrowWriter};
 
 #[derive(Debug, Parser)]
 struct Args {
-    file_path: Utf8PathBuf,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Parse an SPC file and write its data alongside it as CSV, NumPy arrays, a MATLAB file, an
+    /// Excel workbook, an ANDI chromatography file, an mzML document or an ENVI cube. This is
+    /// also run by `spcrs <file>`, without a subcommand
+    Export {
+        file_path: Utf8PathBuf,
+        #[command(flatten)]
+        parse: ParseArgs,
+        /// The format written. The CSV options below only apply to CSV
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Deflate each array of an NPZ archive, as `numpy.savez_compressed` does
+        #[arg(long)]
+        compress: bool,
+        /// Add a line chart of the traces to an Excel workbook
+        #[arg(long)]
+        chart: bool,
+        /// The index of the trace written to an ANDI file, which holds a single chromatogram
+        #[arg(long, default_value_t = 0)]
+        trace: usize,
+        /// Wrap an mzML document in an indexedmzML element, with a spectrum index and checksum
+        #[arg(long)]
+        indexed: bool,
+        /// The MS level of the spectra of an mzML document
+        #[arg(long, default_value_t = 1)]
+        ms_level: u8,
+        /// The order of the values of an ENVI cube
+        #[arg(long, value_enum, default_value_t = Interleave::Bsq)]
+        interleave: Interleave,
+        /// The number of lines of the map in an ENVI cube. By default each w-plane is a line, or
+        /// for files without w-planes each run or cycle of z-values, and otherwise the map is a
+        /// single line
+        #[arg(long, requires = "samples")]
+        lines: Option<usize>,
+        /// The number of samples in each line of the map in an ENVI cube
+        #[arg(long, requires = "lines")]
+        samples: Option<usize>,
+        /// Write each w-plane to its own file, named `<stem>_w<index>.csv`
+        #[arg(long)]
+        split_planes: bool,
+        #[command(flatten)]
+        csv: CsvArgs,
+        /// Write a Parquet or Arrow IPC table rather than CSV, in the layout given by --layout
+        #[cfg(feature = "arrow")]
+        #[arg(long, value_enum, conflicts_with_all = ["split_planes", "format"])]
+        columnar: Option<Columnar>,
+    },
+    /// Read a CSV or TSV file, in any of the layouts written by `export`, a Bruker OPUS file or a
+    /// Renishaw WDF file and write it alongside as an SPC file, named `<stem>_imported.spc`
+    Import {
+        file_path: Utf8PathBuf,
+        #[command(flatten)]
+        import: ImportArgs,
+    },
+    /// Print the decoded header of an SPC file
+    Info {
+        file_path: Utf8PathBuf,
+        #[command(flatten)]
+        parse: ParseArgs,
+        /// Also print the subheader of every trace
+        #[arg(long)]
+        subheaders: bool,
+    },
+    /// Write the header, flags, subheaders, log entries and shape of each SPC file alongside it,
+    /// without its data points
+    Meta {
+        file_paths: Vec<Utf8PathBuf>,
+        #[command(flatten)]
+        parse: ParseArgs,
+        /// The format of the metadata
+        #[arg(long, value_enum, default_value_t = Format::Yaml)]
+        format: Format,
+        /// Also find the range of the y-values, which requires reading every point
+        #[arg(long)]
+        y_ranges: bool,
+        /// Print the metadata rather than writing it to a file
+        #[arg(long)]
+        stdout: bool,
+    },
+    /// Print every structure in an SPC file with its offset, raw bytes and decoded value
+    Inspect {
+        file_path: Utf8PathBuf,
+        /// Only show the structures belonging to this subfile
+        #[arg(long)]
+        subfile: Option<usize>,
+    },
+    /// Write an SPC file alongside itself in another version and byte order, named
+    /// `<stem>_<version>.spc`. Anything the version cannot hold is dropped with a warning
+    Convert {
+        file_path: Utf8PathBuf,
+        #[command(flatten)]
+        parse: ParseArgs,
+        /// The version written: 0x4b for the new little-endian format, 0x4c for the new big-endian
+        /// format or 0x4d for the old format
+        #[arg(long, value_parser = parse_version)]
+        to: SpcVersion,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+struct ParseArgs {
+    /// The encoding of text fields such as the memo and log
+    #[arg(long, value_enum, default_value_t = Encoding::Auto)]
+    encoding: Encoding,
+    /// The timezone the collection time was recorded in: `utc`, `local` or an offset such as
+    /// `+02:00`
+    #[arg(long, value_parser = parse_timezone, default_value = "utc")]
+    timezone: HeaderTimezone,
+}
+
+impl ParseArgs {
+    fn options(&self) -> ParseOptions {
+        ParseOptions::new()
+            .with_encoding(self.encoding.into())
+            .with_timezone(self.timezone)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct CsvArgs {
+    /// The arrangement of the table, wide by default for CSV and tidy for a --columnar table
+    #[arg(long, value_enum)]
+    layout: Option<Layout>,
+    /// Add the subheader fields of each trace as columns, in the tidy layout
+    #[arg(long)]
+    subheader_columns: bool,
+    /// The character separating the fields of each row
+    #[arg(long, value_enum, default_value_t = Separator::Comma)]
+    delimiter: Separator,
+    /// Write a comma before the fractional part of each value
+    #[arg(long)]
+    decimal_comma: bool,
+    /// The number of digits after the decimal point. Values are written in their shortest
+    /// round-trip form if this is not given
+    #[arg(long)]
+    precision: Option<usize>,
+    /// Name the columns after the axis units, rather than x, y and z
+    #[arg(long)]
+    unit_names: bool,
+    /// Start the output with the decoded header as comment lines
+    #[arg(long)]
+    preamble: bool,
+}
+
+impl CsvArgs {
+    #[cfg(feature = "arrow")]
+    fn arrow_layout(&self) -> ArrowLayout {
+        match self.layout {
+            Some(Layout::Wide) => ArrowLayout::Wide,
+            Some(Layout::Tidy) | None => ArrowLayout::Tidy,
+        }
+    }
+
+    fn writer(&self) -> CsvWriter {
+        CsvWriter::new()
+            .with_layout(self.layout.unwrap_or(Layout::Wide).into())
+            .with_subheader_columns(self.subheader_columns)
+            .with_delimiter(self.delimiter.into())
+            .with_decimal_comma(self.decimal_comma)
+            .with_float_format(
+                self.precision
+                    .map_or(FloatFormat::Shortest, FloatFormat::Precision),
+            )
+            .with_unit_column_names(self.unit_names)
+            .with_preamble(self.preamble)
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct ImportArgs {
+    /// The character separating the fields of each row, detected from the first row if not given
+    #[arg(long, value_enum)]
+    delimiter: Option<Separator>,
+    /// Read a comma before the fractional part of each value
+    #[arg(long)]
+    decimal_comma: bool,
+    /// The encoding of a CSV or TSV file
+    #[arg(long, value_enum, default_value_t = Encoding::Auto)]
+    encoding: Encoding,
+    /// The units of the x-axis, such as `wavenumber` or `nanometers`
+    #[arg(long, value_parser = parse_xzw_units)]
+    x_units: Option<xzwType>,
+    /// The units of the y-axis, such as `absorbance` or `counts`
+    #[arg(long, value_parser = parse_y_units)]
+    y_units: Option<yType>,
+    /// The units of the z-axis
+    #[arg(long, value_parser = parse_xzw_units)]
+    z_units: Option<xzwType>,
+    /// The units of the w-axis
+    #[arg(long, value_parser = parse_xzw_units)]
+    w_units: Option<xzwType>,
+    /// The technique of the instrument, such as `raman-spectrum`
+    #[arg(long, value_parser = parse_technique)]
+    technique: Option<InstrumentTechnique>,
+    /// The memo text
+    #[arg(long)]
+    memo: Option<String>,
+    /// The spectrum to read from an OPUS file. The first processed spectrum is read if not given
+    #[arg(long, value_enum)]
+    spectrum: Option<Spectrum>,
+}
+
+impl ImportArgs {
+    fn reader(&self) -> CsvReader {
+        let mut reader = CsvReader::new()
+            .with_decimal_comma(self.decimal_comma)
+            .with_encoding(self.encoding.into());
+        if let Some(delimiter) = self.delimiter {
+            reader = reader.with_delimiter(delimiter.into());
+        }
+        if let Some(unit) = self.x_units {
+            reader = reader.with_x_unit_type(unit);
+        }
+        if let Some(unit) = self.y_units {
+            reader = reader.with_y_unit_type(unit);
+        }
+        if let Some(unit) = self.z_units {
+            reader = reader.with_z_unit_type(unit);
+        }
+        if let Some(unit) = self.w_units {
+            reader = reader.with_w_unit_type(unit);
+        }
+        if let Some(technique) = self.technique {
+            reader = reader.with_instrument_technique(technique);
+        }
+        if let Some(memo) = &self.memo {
+            reader = reader.with_memo(memo);
+        }
+        reader
+    }
+
+    fn opus_reader(&self) -> OpusReader {
+        let mut reader = OpusReader::new();
+        if let Some(spectrum) = self.spectrum {
+            reader = reader.with_spectrum(spectrum.into());
+        }
+        reader
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Spectrum {
+    Absorbance,
+    Transmittance,
+    KubelkaMunk,
+    Reflectance,
+    Raman,
+    Sample,
+    Reference,
+}
+
+impl From<Spectrum> for OpusSpectrum {
+    fn from(spectrum: Spectrum) -> Self {
+        match spectrum {
+            Spectrum::Absorbance => OpusSpectrum::Absorbance,
+            Spectrum::Transmittance => OpusSpectrum::Transmittance,
+            Spectrum::KubelkaMunk => OpusSpectrum::KubelkaMunk,
+            Spectrum::Reflectance => OpusSpectrum::Reflectance,
+            Spectrum::Raman => OpusSpectrum::Raman,
+            Spectrum::Sample => OpusSpectrum::SampleSingleChannel,
+            Spectrum::Reference => OpusSpectrum::ReferenceSingleChannel,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Separator {
+    Comma,
+    Tab,
+    Semicolon,
+}
+
+impl From<Separator> for Delimiter {
+    fn from(separator: Separator) -> Self {
+        match separator {
+            Separator::Comma => Delimiter::Comma,
+            Separator::Tab => Delimiter::Tab,
+            Separator::Semicolon => Delimiter::Semicolon,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Encoding {
+    Auto,
+    Utf8,
+    Windows1252,
+    Latin1,
+    ShiftJis,
+}
+
+impl From<Encoding> for TextEncoding {
+    fn from(encoding: Encoding) -> Self {
+        match encoding {
+            Encoding::Auto => TextEncoding::Auto,
+            Encoding::Utf8 => TextEncoding::Utf8,
+            Encoding::Windows1252 => TextEncoding::Windows1252,
+            Encoding::Latin1 => TextEncoding::Latin1,
+            Encoding::ShiftJis => TextEncoding::ShiftJis,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl From<Format> for MetadataFormat {
+    fn from(format: Format) -> Self {
+        match format {
+            Format::Yaml => MetadataFormat::Yaml,
+            Format::Toml => MetadataFormat::Toml,
+            Format::Json => MetadataFormat::Json,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Layout {
+    /// One column per trace, or one block of rows per trace for XYXY files
+    Wide,
+    /// One row per point, with columns trace_index, z, w, x and y
+    Tidy,
+}
+
+impl From<Layout> for CsvLayout {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::Wide => CsvLayout::Wide,
+            Layout::Tidy => CsvLayout::Tidy,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    /// A NumPy archive holding the arrays x, y, z and metadata, as written by `numpy.savez`
+    Npz,
+    /// A MATLAB Level 5 MAT-file holding the struct `spc`, with the fields x, y, z, the axis
+    /// units and the header
+    Mat,
+    /// An Excel workbook with the traces on the sheet Data and the header on the sheet Metadata
+    Xlsx,
+    /// An ANDI (AIA) chromatography netCDF file, written with the extension `.cdf`, for gas,
+    /// general and HPLC chromatograms
+    Andi,
+    /// An mzML document, with one spectrum per trace, for mass spectra
+    Mzml,
+    /// An ENVI hyperspectral cube of a mapped multifile, with its `.hdr` header
+    Envi,
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Interleave {
+    /// Band sequential
+    Bsq,
+    /// Band interleaved by line
+    Bil,
+    /// Band interleaved by pixel
+    Bip,
+}
+
+impl From<Interleave> for EnviInterleave {
+    fn from(interleave: Interleave) -> Self {
+        match interleave {
+            Interleave::Bsq => EnviInterleave::Bsq,
+            Interleave::Bil => EnviInterleave::Bil,
+            Interleave::Bip => EnviInterleave::Bip,
+        }
+    }
+}
+
+#[cfg(feature = "arrow")]
+#[derive(Copy, Clone, Debug, ValueEnum)]
+enum Columnar {
+    Parquet,
+    /// The Arrow IPC file format, written with the extension `.arrow`
+    Ipc,
+}
+
+#[cfg(feature = "arrow")]
+impl From<Columnar> for ArrowFormat {
+    fn from(columnar: Columnar) -> Self {
+        match columnar {
+            Columnar::Parquet => ArrowFormat::Parquet,
+            Columnar::Ipc => ArrowFormat::Ipc,
+        }
+    }
+}
+
+fn parse_timezone(value: &str) -> Result<HeaderTimezone, String> {
+    match value {
+        "utc" | "UTC" => Ok(HeaderTimezone::Utc),
+        "local" => Ok(HeaderTimezone::Local),
+        offset => offset
+            .parse()
+            .map(HeaderTimezone::Fixed)
+            .map_err(|e| format!("invalid timezone offset '{offset}': {e}")),
+    }
+}
+
+// Names on the command line are written in kebab-case, such as `raman-shift`
+fn parse_xzw_units(value: &str) -> Result<xzwType, String> {
+    xzwType::from_name(&value.replace('-', "")).ok_or_else(|| format!("unknown unit '{value}'"))
+}
+
+fn parse_y_units(value: &str) -> Result<yType, String> {
+    yType::from_name(&value.replace('-', "")).ok_or_else(|| format!("unknown unit '{value}'"))
+}
+
+fn parse_technique(value: &str) -> Result<InstrumentTechnique, String> {
+    InstrumentTechnique::from_name(&value.replace('-', ""))
+        .ok_or_else(|| format!("unknown instrument technique '{value}'"))
+}
+
+fn parse_version(value: &str) -> Result<SpcVersion, String> {
+    u8::from_str_radix(value.trim_start_matches("0x"), 16)
+        .ok()
+        .and_then(SpcVersion::from_code)
+        .ok_or_else(|| format!("unknown SPC version '{value}', expected 0x4b, 0x4c or 0x4d"))
+}
+
+// Read a whole SPC file
+fn read_source(file_path: &Utf8Path) -> miette::Result<Vec<u8>> {
+    let file = File::open(file_path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("opening '{}' failed", file_path))?;
+
+    let source = BufReader::new(file)
+        .bytes()
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("reading '{}' failed", file_path))?;
+
+    // Check the kind of file before any of it is lexed
+    let kind =
+        detect_supported(&source).wrap_err_with(|| format!("'{}' cannot be read", file_path))?;
+    log::info!("reading {kind:?} SPC file '{file_path}'");
+    Ok(source)
+}
+
+// The command line arguments, with `export` inserted when the first argument is a file rather than
+// a subcommand, so `spcrs <file>` exports the file as it did before there were subcommands
+fn args() -> Vec<OsString> {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let mut command = Args::command();
+    command.build();
+    if let Some(first) = args.get(1).and_then(|first| first.to_str()) {
+        if !first.starts_with('-') && command.find_subcommand(first).is_none() {
+            args.insert(1, "export".into());
+        }
+    }
+    args
 }
 
 fn main() -> miette::Result<()> {
     Builder::new().filter(None, LevelFilter::Info).init();
 
-    match Args::try_parse() {
-        Ok(args) => {
-            let file = File::open(&args.file_path)
-                .into_diagnostic()
-                .wrap_err_with(|| format!("opening '{}' failed", args.file_path))?;
+    match Args::try_parse_from(args()) {
+        Ok(args) => match args.command {
+            Command::Export {
+                file_path,
+                parse,
+                format,
+                compress,
+                chart,
+                trace,
+                indexed,
+                ms_level,
+                interleave,
+                lines,
+                samples,
+                split_planes,
+                csv,
+                #[cfg(feature = "arrow")]
+                columnar,
+            } => {
+                let source = read_source(&file_path)?;
 
-            let source = BufReader::new(file)
-                .bytes()
-                .into_iter()
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap();
+                let parsed = parse_with_options(&source[..], &parse.options())?;
 
-            let parsed = parse(&source[..])?;
-            dbg!(&parsed);
+                #[cfg(feature = "arrow")]
+                if let Some(columnar) = columnar {
+                    let writer = ArrowWriter::new()
+                        .with_format(columnar.into())
+                        .with_layout(csv.arrow_layout());
+                    return write_arrow(&file_path, &parsed, &writer);
+                }
 
-            write_spc(&args.file_path, parsed)?;
-        }
+                match format {
+                    ExportFormat::Csv => {}
+                    ExportFormat::Npz => {
+                        let writer = NpzWriter::new().with_compression(compress);
+                        return write_npz(&file_path, &parsed, &writer);
+                    }
+                    ExportFormat::Mat => return write_mat(&file_path, &parsed, &MatWriter::new()),
+                    ExportFormat::Andi => {
+                        let writer = AndiWriter::new().with_trace(trace);
+                        return write_andi(&file_path, &parsed, &writer);
+                    }
+                    ExportFormat::Xlsx => {
+                        let writer = XlsxWriter::new().with_chart(chart);
+                        return write_xlsx(&file_path, &parsed, &writer);
+                    }
+                    ExportFormat::Mzml => {
+                        let writer = MzmlWriter::new()
+                            .with_index(indexed)
+                            .with_ms_level(ms_level);
+                        return write_mzml(&file_path, &parsed, &writer);
+                    }
+                    ExportFormat::Envi => {
+                        let mut writer = EnviWriter::new().with_interleave(interleave.into());
+                        if let (Some(lines), Some(samples)) = (lines, samples) {
+                            writer = writer.with_geometry(lines, samples);
+                        }
+                        return write_envi(&file_path, &parsed, &writer);
+                    }
+                }
+
+                let writer = csv.writer();
+                if split_planes {
+                    write_spc_planes(&file_path, parsed, &writer)?;
+                } else {
+                    write_spc(&file_path, parsed, &writer)?;
+                }
+            }
+            Command::Import { file_path, import } => {
+                let mut start = [0; 4];
+                File::open(&file_path)
+                    .and_then(|mut file| file.read(&mut start))
+                    .into_diagnostic()?;
+                if OpusReader::is_opus(&start) {
+                    import_opus(&file_path, &import.opus_reader())?;
+                } else if WdfReader::is_wdf(&start) {
+                    import_wdf(&file_path, &WdfReader::new())?;
+                } else {
+                    import_csv(&file_path, &import.reader())?;
+                }
+            }
+            Command::Info {
+                file_path,
+                parse,
+                subheaders,
+            } => {
+                let source = read_source(&file_path)?;
+
+                let parsed = parse_with_options(&source[..], &parse.options())?;
+                print!("{}", parsed.header());
+                if let Some(log) = parsed.log() {
+                    println!("log: {} bytes of binary data", log.data().len());
+                    println!("{}", log.text());
+                }
+                if subheaders {
+                    for trace in parsed.traces() {
+                        println!("\ntrace {} (z = {}):", trace.index(), trace.z());
+                        print!("{}", trace.subheader());
+                    }
+                }
+            }
+            Command::Meta {
+                file_paths,
+                parse,
+                format,
+                y_ranges,
+                stdout,
+            } => {
+                let options = MetadataOptions::new()
+                    .with_parse_options(parse.options())
+                    .with_y_ranges(y_ranges);
+                // A file which cannot be read is reported, and the rest of the batch is still read
+                let mut failed = 0;
+                for file_path in &file_paths {
+                    let result = read_source(file_path).and_then(|source| {
+                        let metadata = metadata(&source[..], &options).wrap_err_with(|| {
+                            format!("reading the metadata of '{}' failed", file_path)
+                        })?;
+                        if stdout {
+                            print!("{}", metadata.serialize(format.into())?);
+                            Ok(())
+                        } else {
+                            write_metadata(file_path, &metadata, format.into())
+                        }
+                    });
+                    if let Err(err) = result {
+                        log::error!("{err:?}");
+                        failed += 1;
+                    }
+                }
+                if failed > 0 {
+                    miette::bail!("{failed} of {} files could not be read", file_paths.len());
+                }
+            }
+            Command::Inspect { file_path, subfile } => {
+                let source = read_source(&file_path)?;
+
+                let inspection = inspect(&source[..])?;
+                match subfile {
+                    Some(index) => print!("{}", inspection.subfile(index)),
+                    None => print!("{inspection}"),
+                }
+            }
+            Command::Convert {
+                file_path,
+                parse,
+                to,
+            } => {
+                let source = read_source(&file_path)?;
+
+                let parsed = parse_with_options(&source[..], &parse.options())?;
+                convert_spc(&file_path, &parsed, &SpcWriter::new().with_version(to))?;
+            }
+        },
         Err(err) => {
             eprintln!("Error: {}", err);
         }
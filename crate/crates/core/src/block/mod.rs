@@ -1,30 +1,118 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, ops::Range};
 
 use zerocopy::{
-    byteorder::{F32, I16, U32},
-    ByteOrder, Immutable, KnownLayout, TryFromBytes,
+    byteorder::{F32, I16, U16, U32},
+    ByteOrder, Immutable, IntoBytes, KnownLayout, TryFromBytes, Unaligned,
 };
 
 use crate::{
     header::{LexedSubheader, Subheader, SubheaderParseError},
+    inspect::{field_span, FieldSpan, Inspect},
     lex::Version,
     parse::{Parse, TryParse},
 };
 
-#[derive(Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
+#[repr(C)]
+#[derive(Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes, Unaligned)]
 pub(crate) struct LexedDirectory<E: ByteOrder> {
     ssfposn: U32<E>,
     ssfsize: U32<E>,
     ssftime: F32<E>,
 }
 
-#[derive(Clone, Debug)]
-pub(crate) struct Directory {
+/// An entry in the directory which may follow the subfiles of an XYXY file
+///
+/// The directory allows a reader to seek straight to a subfile without reading the ones before
+/// it. In GC-MS style files the time of each subfile is also stored here.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Directory {
     ssfposn: u32,
     ssfsize: u32,
     ssftime: f32,
 }
 
+impl Directory {
+    pub(crate) fn new(position: u32, size: u32, time: f32) -> Self {
+        Self {
+            ssfposn: position,
+            ssfsize: size,
+            ssftime: time,
+        }
+    }
+
+    // The entry as it is laid out in a file with byte order `E`
+    pub(crate) fn to_lexed<E: ByteOrder>(&self) -> LexedDirectory<E> {
+        LexedDirectory {
+            ssfposn: U32::new(self.ssfposn),
+            ssfsize: U32::new(self.ssfsize),
+            ssftime: F32::new(self.ssftime),
+        }
+    }
+
+    /// The byte offset of the subfile from the start of the file (ssfposn)
+    pub fn position(&self) -> u32 {
+        self.ssfposn
+    }
+
+    /// The size of the subfile in bytes, including its subheader (ssfsize)
+    pub fn size(&self) -> u32 {
+        self.ssfsize
+    }
+
+    /// The z-value, usually a time, of the subfile (ssftime)
+    pub fn time(&self) -> f32 {
+        self.ssftime
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum DirectoryError {
+    #[error("the directory holds {entries} entries, but the file contains {subfiles} subfiles")]
+    WrongLength { entries: usize, subfiles: usize },
+    #[error("directory entry {subfile} gives position {expected:#x}, but the subfile starts at {actual:#x}")]
+    PositionMismatch {
+        subfile: usize,
+        expected: u32,
+        actual: usize,
+    },
+    #[error("directory entry {subfile} gives size {expected}, but the subfile is {actual} bytes")]
+    SizeMismatch {
+        subfile: usize,
+        expected: u32,
+        actual: usize,
+    },
+}
+
+// Check each directory entry against the (offset, size) in bytes of the subfile it describes
+pub(crate) fn validate_directory(
+    directory: &[Directory],
+    subfiles: &[(usize, usize)],
+) -> Result<(), DirectoryError> {
+    if directory.len() != subfiles.len() {
+        return Err(DirectoryError::WrongLength {
+            entries: directory.len(),
+            subfiles: subfiles.len(),
+        });
+    }
+    for (subfile, (entry, &(offset, size))) in directory.iter().zip(subfiles).enumerate() {
+        if entry.ssfposn as usize != offset {
+            return Err(DirectoryError::PositionMismatch {
+                subfile,
+                expected: entry.ssfposn,
+                actual: offset,
+            });
+        }
+        if entry.ssfsize as usize != size {
+            return Err(DirectoryError::SizeMismatch {
+                subfile,
+                expected: entry.ssfsize,
+                actual: size,
+            });
+        }
+    }
+    Ok(())
+}
+
 impl<E: ByteOrder> Parse for LexedDirectory<E> {
     type Parsed = Directory;
     fn parse(&self) -> Self::Parsed {
@@ -36,6 +124,16 @@ impl<E: ByteOrder> Parse for LexedDirectory<E> {
     }
 }
 
+impl<E: ByteOrder> Inspect for LexedDirectory<E> {
+    fn inspect(&self) -> Vec<FieldSpan> {
+        vec![
+            field_span!(self, ssfposn, format!("{:#x}", self.ssfposn.get())),
+            field_span!(self, ssfsize, self.ssfsize.get()),
+            field_span!(self, ssftime, self.ssftime.get()),
+        ]
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct LexedXData<'data, E: ByteOrder> {
     data: &'data [u8],
@@ -58,8 +156,8 @@ impl<'data, E: ByteOrder> LexedXData<'data, E> {
 pub(crate) struct XData(Vec<f32>);
 
 impl XData {
-    pub(crate) fn len(&self) -> usize {
-        self.0.len()
+    pub(crate) fn new(values: Vec<f32>) -> Self {
+        Self(values)
     }
 
     pub(crate) fn iter(&self) -> impl Iterator<Item = &f32> {
@@ -98,6 +196,17 @@ impl YMode {
             Self::IEEEFloat => 4,
         }
     }
+
+    // A subheader can override the data type given in the header. If the subheader exponent is
+    // 0x80 the subfile contains floats, regardless of the header. Returns `None` when the header
+    // declares float data but the subheader does not.
+    pub(crate) fn for_subfile(&self, float_expected_from_subheader: bool) -> Option<Self> {
+        match (self, float_expected_from_subheader) {
+            (_, true) => Some(Self::IEEEFloat),
+            (Self::IEEEFloat, false) => None,
+            (mode, false) => Some(mode.clone()),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -129,6 +238,15 @@ impl<'data, E: ByteOrder> LexedSubfile<'data, E> {
             mode,
         })
     }
+
+    pub(crate) fn subheader(&self) -> &'data LexedSubheader<E> {
+        self.subheader
+    }
+
+    // The number of y-values in the subfile, found without reading them
+    pub(crate) fn number_of_points(&self) -> usize {
+        self.data.len() / self.mode.bytes_per_point()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -139,7 +257,7 @@ pub(crate) enum YData {
 }
 
 impl YData {
-    fn len(&self) -> usize {
+    pub(crate) fn len(&self) -> usize {
         match self {
             Self::SixteenBitInteger(vals) => vals.len(),
             Self::ThirtyTwoBitInteger(vals) => vals.len(),
@@ -174,8 +292,8 @@ impl YData {
 
 #[derive(Clone, Debug)]
 pub(crate) struct Subfile {
-    pub(super) subheader: Subheader,
-    pub(super) data: YData,
+    pub(crate) subheader: Subheader,
+    pub(crate) data: YData,
 }
 
 impl<E: ByteOrder> TryParse for LexedSubfile<'_, E> {
@@ -194,8 +312,10 @@ impl<E: ByteOrder> TryParse for LexedSubfile<'_, E> {
                 self.data
                     .chunks_exact(4)
                     .map(|each| {
+                        // The most significant word comes first, and the least significant word
+                        // is unsigned
                         let first = I16::<E>::from_bytes([each[0], each[1]]);
-                        let second = I16::<E>::from_bytes([each[2], each[3]]);
+                        let second = U16::<E>::from_bytes([each[2], each[3]]);
                         ((first.get() as i32) << 16) + second.get() as i32
                     })
                     .collect(),
@@ -263,6 +383,60 @@ pub(crate) enum Block {
     },
 }
 
+impl Block {
+    // Each subfile in the block, with its x-values if they are stored explicitly
+    pub(crate) fn subfiles(&self) -> Vec<(Option<&XData>, &Subfile)> {
+        match self {
+            Block::Y(subfile) => vec![(None, subfile)],
+            Block::YY(subfiles) => subfiles.iter().map(|subfile| (None, subfile)).collect(),
+            Block::XY { x, y } => vec![(Some(x), y)],
+            Block::XYY { x, ys } => ys.iter().map(|subfile| (Some(x), subfile)).collect(),
+            Block::XYXY { data, .. } => data.iter().map(|(x, y)| (Some(x), y)).collect(),
+        }
+    }
+
+    // A block holding only the subfiles in `range`. Single-subfile blocks are returned unchanged
+    pub(crate) fn select(&self, range: Range<usize>) -> Block {
+        match self {
+            Block::Y(_) | Block::XY { .. } => self.clone(),
+            Block::YY(subfiles) => Block::YY(subfiles[range].to_vec()),
+            Block::XYY { x, ys } => Block::XYY {
+                x: x.clone(),
+                ys: ys[range].to_vec(),
+            },
+            Block::XYXY { data, directory } => Block::XYXY {
+                data: data[range.clone()].to_vec(),
+                directory: directory
+                    .as_ref()
+                    .map(|directory| directory[range].to_vec()),
+            },
+        }
+    }
+}
+
+impl<'data, E: ByteOrder> LexedBlock<'data, E> {
+    // Each subfile in the block, with its x-values if they are stored explicitly
+    pub(crate) fn subfiles(&self) -> Vec<(Option<&LexedXData<'data, E>>, &LexedSubfile<'data, E>)> {
+        match self {
+            LexedBlock::Y(subfile) => vec![(None, subfile)],
+            LexedBlock::YY(subfiles) => subfiles.iter().map(|subfile| (None, subfile)).collect(),
+            LexedBlock::XY { x, y } => vec![(Some(x), y)],
+            LexedBlock::XYY { x, ys } => ys.iter().map(|subfile| (Some(x), subfile)).collect(),
+            LexedBlock::XYXY { data, .. } => data.iter().map(|(x, y)| (Some(x), y)).collect(),
+        }
+    }
+
+    pub(crate) fn directory(&self) -> Option<Vec<Directory>> {
+        match self {
+            LexedBlock::XYXY {
+                directory: Some(directory),
+                ..
+            } => Some(directory.iter().map(|entry| entry.parse()).collect()),
+            _ => None,
+        }
+    }
+}
+
 impl<E: ByteOrder> TryParse for LexedBlock<'_, E> {
     type Parsed = Block;
     type Error = SubheaderParseError;
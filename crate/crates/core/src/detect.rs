@@ -0,0 +1,115 @@
+//! Telling the kinds of file which share the `.spc` extension apart from their first bytes.
+
+// The first bytes of an OLE2 compound document, the container of Shimadzu SPC files
+const OLE2_MAGIC: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+/// The kind of a file, as found by [`detect`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    /// A new-format Galactic SPC file with little-endian numbers (file version 0x4b)
+    NewLittleEndian,
+    /// A new-format Galactic SPC file with big-endian numbers (file version 0x4c)
+    NewBigEndian,
+    /// An old-format Galactic SPC file, written before 1996 (file version 0x4d)
+    Old,
+    /// A Shimadzu SPC file, which is an OLE2 compound document
+    Shimadzu,
+    /// A file which is not an SPC file
+    Unknown,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum DetectError {
+    #[error("this is a Shimadzu SPC file, which has no Galactic header or subfiles")]
+    #[diagnostic(help(
+        "Shimadzu files are OLE2 compound documents. They can be read with `parse` and then \
+         exported"
+    ))]
+    Shimadzu,
+    #[error("the file contained less than two bytes")]
+    TooShort,
+    #[error("not an SPC file: the file type descriptor is {0:#04x}")]
+    #[diagnostic(help(
+        "the second byte of a Galactic SPC file is 0x4b, 0x4c or 0x4d, and Shimadzu SPC files \
+         start with the OLE2 signature"
+    ))]
+    Unknown(u8),
+}
+
+impl FileKind {
+    /// Whether the file can be parsed by this crate
+    pub fn is_supported(self) -> bool {
+        !matches!(self, Self::Unknown)
+    }
+
+    /// Whether the file is a Galactic SPC file, with a header and subfiles which can be inspected
+    pub fn is_galactic(self) -> bool {
+        matches!(self, Self::NewLittleEndian | Self::NewBigEndian | Self::Old)
+    }
+
+    // An error describing why a file of this kind cannot be parsed, if it cannot
+    fn check_supported(self, source: &[u8]) -> Result<Self, DetectError> {
+        match self {
+            Self::Unknown => Err(match source.get(1) {
+                Some(&b) => DetectError::Unknown(b),
+                None => DetectError::TooShort,
+            }),
+            kind => Ok(kind),
+        }
+    }
+}
+
+/// Find the kind of a file from its first bytes.
+///
+/// Galactic SPC files are told apart by their file version, in the second byte. Only the first
+/// eight bytes are needed.
+pub fn detect(source: &[u8]) -> FileKind {
+    if source.starts_with(&OLE2_MAGIC) {
+        return FileKind::Shimadzu;
+    }
+    match source.get(1) {
+        Some(0x4b) => FileKind::NewLittleEndian,
+        Some(0x4c) => FileKind::NewBigEndian,
+        Some(0x4d) => FileKind::Old,
+        _ => FileKind::Unknown,
+    }
+}
+
+/// Find the kind of a file, failing with a description of the file if it cannot be parsed
+pub fn detect_supported(source: &[u8]) -> miette::Result<FileKind> {
+    Ok(detect(source).check_supported(source)?)
+}
+
+// Find the kind of a Galactic SPC file, failing for files whose header and subfiles cannot be read
+// directly
+pub(crate) fn detect_galactic(source: &[u8]) -> Result<FileKind, DetectError> {
+    match detect(source).check_supported(source)? {
+        FileKind::Shimadzu => Err(DetectError::Shimadzu),
+        kind => Ok(kind),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect, detect_galactic, detect_supported, FileKind};
+
+    #[test]
+    fn files_are_told_apart_by_their_first_bytes() {
+        let cases: [(&[u8], FileKind); 6] = [
+            (&[0x00, 0x4b, 0x00], FileKind::NewLittleEndian),
+            (&[0x10, 0x4c], FileKind::NewBigEndian),
+            (&[0x00, 0x4d], FileKind::Old),
+            (
+                &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1, 0x00],
+                FileKind::Shimadzu,
+            ),
+            (b"x,y\n1,2\n", FileKind::Unknown),
+            (&[0x00], FileKind::Unknown),
+        ];
+        for (source, kind) in cases {
+            assert_eq!(detect(source), kind);
+            assert_eq!(detect_supported(source).is_ok(), kind.is_supported());
+            assert_eq!(detect_galactic(source).is_ok(), kind.is_galactic());
+        }
+    }
+}
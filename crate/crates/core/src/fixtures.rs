@@ -0,0 +1,17 @@
+//! Files shared by the tests of the readers and writers.
+
+use crate::{CsvReader, ParsedSPC};
+
+// An XYY multifile with the memo `kinetics`, of two traces sharing the x-values 1, 3 and 7
+pub(crate) fn multifile() -> ParsedSPC {
+    CsvReader::new()
+        .read("# memo: kinetics\nx,y_0,y_1\n1,2,-4\n3,4,8\n7,6,0\n")
+        .unwrap()
+}
+
+// An XYXY file of a trace of two points at z = 0 and a trace of one point at z = 1
+pub(crate) fn xyxy() -> ParsedSPC {
+    CsvReader::new()
+        .read("x,y\n# z = 0\n1,2\n3,4\n# z = 1\n5,6\n")
+        .unwrap()
+}
@@ -0,0 +1,314 @@
+//! Codes stored in the new-format header describing how the file was produced, and what should
+//! happen to it after collection.
+//!
+//! The values of the posting disposition and processing codes are defined in `GRAMSDDE.H`, which
+//! accompanies the SPC specification.
+
+use std::fmt;
+
+/// What the collecting software should do with the data once it has been acquired (fpost)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PostingDisposition {
+    /// Use the default disposition
+    Default,
+    /// Save the result to disk
+    Save,
+    /// Append the result to an existing file
+    Append,
+    /// Merge the result into an existing file
+    Merge,
+    /// Save the result in the background, without displaying it
+    Background,
+    /// Do not save the result
+    DoNotSave,
+    /// A value not defined in the specification
+    Other(u8),
+}
+
+impl From<u8> for PostingDisposition {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::Default,
+            1 => Self::Save,
+            2 => Self::Append,
+            3 => Self::Merge,
+            4 => Self::Background,
+            5 => Self::DoNotSave,
+            v => Self::Other(v),
+        }
+    }
+}
+
+impl From<PostingDisposition> for u8 {
+    fn from(val: PostingDisposition) -> Self {
+        match val {
+            PostingDisposition::Default => 0,
+            PostingDisposition::Save => 1,
+            PostingDisposition::Append => 2,
+            PostingDisposition::Merge => 3,
+            PostingDisposition::Background => 4,
+            PostingDisposition::DoNotSave => 5,
+            PostingDisposition::Other(v) => v,
+        }
+    }
+}
+
+/// The post-processing to apply to the data after collection (fprocs)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProcessingCode {
+    /// No post-processing
+    None,
+    /// Run the compute program (PPCOMP)
+    Compute,
+    /// Run the compute program from a DLL (PPDLLC)
+    ComputeWithDll,
+    /// Convert to transmission (PPTRANS)
+    Transmission,
+    /// Convert to absorbance (PPABS)
+    Absorbance,
+    /// Convert to Kubelka-Munk (PPKMUNK)
+    KubelkaMunk,
+    /// Run the built-in peak picking and reporting (PPPEAK)
+    PeakPick,
+    /// Search the library associated with the experiment (PPSRCH)
+    LibrarySearch,
+    /// Run a user-written post-processing program (PPUSER)
+    User,
+    /// A value not defined in the specification
+    Other(u8),
+}
+
+impl From<u8> for ProcessingCode {
+    fn from(val: u8) -> Self {
+        match val {
+            0 => Self::None,
+            1 => Self::Compute,
+            2 => Self::ComputeWithDll,
+            4 => Self::Transmission,
+            8 => Self::Absorbance,
+            12 => Self::KubelkaMunk,
+            32 => Self::PeakPick,
+            64 => Self::LibrarySearch,
+            128 => Self::User,
+            v => Self::Other(v),
+        }
+    }
+}
+
+impl From<ProcessingCode> for u8 {
+    fn from(val: ProcessingCode) -> Self {
+        match val {
+            ProcessingCode::None => 0,
+            ProcessingCode::Compute => 1,
+            ProcessingCode::ComputeWithDll => 2,
+            ProcessingCode::Transmission => 4,
+            ProcessingCode::Absorbance => 8,
+            ProcessingCode::KubelkaMunk => 12,
+            ProcessingCode::PeakPick => 32,
+            ProcessingCode::LibrarySearch => 64,
+            ProcessingCode::User => 128,
+            ProcessingCode::Other(v) => v,
+        }
+    }
+}
+
+/// An operation which may have modified the data in a file, identified by a letter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Modification {
+    /// A: Averaged from multiple source traces
+    Averaged,
+    /// B: Baseline correction or offset functions
+    BaselineCorrected,
+    /// C: Interferogram to spectrum computation
+    Computed,
+    /// D: Derivative functions
+    Derivative,
+    /// E: Resolution enhancement functions, such as deconvolution
+    ResolutionEnhanced,
+    /// I: Interpolation functions
+    Interpolated,
+    /// N: Noise reduction smoothing
+    Smoothed,
+    /// O: Other functions, such as addition, subtraction or added noise
+    Other,
+    /// S: Spectral subtraction
+    Subtracted,
+    /// T: Truncation, only a portion of the original x-axis remains
+    Truncated,
+    /// W: The collection time has been modified
+    DatetimeModified,
+    /// X: X-axis unit conversion or x-shifting
+    XConverted,
+    /// Y: Y-axis conversion functions
+    YConverted,
+    /// Z: Zap functions, where features were removed or modified
+    Zapped,
+    /// A letter which the specification does not assign an operation to
+    Unassigned(char),
+}
+
+impl Modification {
+    /// The letter identifying the operation
+    pub fn letter(&self) -> char {
+        match self {
+            Self::Averaged => 'A',
+            Self::BaselineCorrected => 'B',
+            Self::Computed => 'C',
+            Self::Derivative => 'D',
+            Self::ResolutionEnhanced => 'E',
+            Self::Interpolated => 'I',
+            Self::Smoothed => 'N',
+            Self::Other => 'O',
+            Self::Subtracted => 'S',
+            Self::Truncated => 'T',
+            Self::DatetimeModified => 'W',
+            Self::XConverted => 'X',
+            Self::YConverted => 'Y',
+            Self::Zapped => 'Z',
+            Self::Unassigned(letter) => *letter,
+        }
+    }
+
+    fn from_letter(letter: char) -> Self {
+        match letter {
+            'A' => Self::Averaged,
+            'B' => Self::BaselineCorrected,
+            'C' => Self::Computed,
+            'D' => Self::Derivative,
+            'E' => Self::ResolutionEnhanced,
+            'I' => Self::Interpolated,
+            'N' => Self::Smoothed,
+            'O' => Self::Other,
+            'S' => Self::Subtracted,
+            'T' => Self::Truncated,
+            'W' => Self::DatetimeModified,
+            'X' => Self::XConverted,
+            'Y' => Self::YConverted,
+            'Z' => Self::Zapped,
+            letter => Self::Unassigned(letter),
+        }
+    }
+
+    // Bit n of the modification flags corresponds to the letter '@' + n, so 'A' is bit 1. Letters
+    // outside '@'..='_' have no bit
+    fn bit(&self) -> Option<u32> {
+        (self.letter() as u32)
+            .checked_sub('@' as u32)
+            .and_then(|n| 1u32.checked_shl(n))
+    }
+}
+
+impl fmt::Display for Modification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Self::Averaged => "averaged",
+            Self::BaselineCorrected => "baseline corrected",
+            Self::Computed => "computed from interferogram",
+            Self::Derivative => "derivative",
+            Self::ResolutionEnhanced => "resolution enhanced",
+            Self::Interpolated => "interpolated",
+            Self::Smoothed => "smoothed",
+            Self::Other => "other",
+            Self::Subtracted => "spectral subtraction",
+            Self::Truncated => "truncated",
+            Self::DatetimeModified => "collection time modified",
+            Self::XConverted => "x-axis converted",
+            Self::YConverted => "y-axis converted",
+            Self::Zapped => "zapped",
+            Self::Unassigned(_) => "unassigned",
+        };
+        write!(f, "{} ({description})", self.letter())
+    }
+}
+
+/// The operations which have modified the data in a file (fmods)
+///
+/// Bit n of the flags is set when the operation with letter `'@' + n` has been applied, so bit 1
+/// is 'A', bit 2 is 'B' and so on up to 'Z'. The remaining bits are the unassigned letters '@'
+/// and '[' to '_', and no other letter has a bit.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModificationFlags(pub(super) u32);
+
+impl ModificationFlags {
+    /// The flags as stored in the file
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    /// Whether the given operation has been applied
+    pub fn contains(&self, modification: Modification) -> bool {
+        modification.bit().is_some_and(|bit| self.0 & bit != 0)
+    }
+
+    /// The operations which have been applied, in the order of their bits
+    pub fn iter(&self) -> impl Iterator<Item = Modification> + '_ {
+        ('@'..='_')
+            .map(Modification::from_letter)
+            .filter(|modification| self.contains(*modification))
+    }
+
+    /// Mark an operation as applied. Letters without a bit leave the flags unchanged
+    pub fn with(mut self, modification: Modification) -> Self {
+        self.0 |= modification.bit().unwrap_or(0);
+        self
+    }
+}
+
+impl fmt::Display for ModificationFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let modifications = self.iter().map(|m| m.to_string()).collect::<Vec<_>>();
+        if modifications.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", modifications.join(", "))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Modification, ModificationFlags, ProcessingCode};
+
+    #[test]
+    fn modification_flags_decode_to_letters() {
+        // A, B and Z
+        let flags = ModificationFlags((1 << 1) | (1 << 2) | (1 << 26));
+        assert_eq!(
+            flags.iter().collect::<Vec<_>>(),
+            [
+                Modification::Averaged,
+                Modification::BaselineCorrected,
+                Modification::Zapped
+            ]
+        );
+        assert!(flags.contains(Modification::Averaged));
+        assert!(!flags.contains(Modification::Smoothed));
+        assert_eq!(
+            ModificationFlags::default()
+                .with(Modification::Averaged)
+                .with(Modification::BaselineCorrected)
+                .with(Modification::Zapped),
+            flags
+        );
+
+        // Unassigned letters with a bit are kept, and those without one are ignored
+        let unassigned = ModificationFlags::default()
+            .with(Modification::Unassigned('_'))
+            .with(Modification::Unassigned('a'))
+            .with(Modification::Unassigned('!'));
+        assert_eq!(unassigned.bits(), 1 << 31);
+        assert!(unassigned.contains(Modification::Unassigned('_')));
+        assert!(!unassigned.contains(Modification::Unassigned('a')));
+        assert_eq!(
+            unassigned.iter().collect::<Vec<_>>(),
+            [Modification::Unassigned('_')]
+        );
+    }
+
+    #[test]
+    fn processing_code_round_trips() {
+        for val in 0..=u8::MAX {
+            assert_eq!(u8::from(ProcessingCode::from(val)), val);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use zerocopy::{Immutable, KnownLayout, TryFromBytes};
+use zerocopy::{Immutable, IntoBytes, KnownLayout, TryFromBytes, Unaligned};
 
 /// The first byte of the SPC file contains flags, describing the data to come
 
@@ -17,8 +17,8 @@ use zerocopy::{Immutable, KnownLayout, TryFromBytes};
 /// - TXYXYS: Each subfile has a unique x-array. This can only be used if TXVALS is also used.
 /// - TXVALS: X-data is not evenly spaced, an x-value array preceeds the y-data blocks
 #[repr(C)]
-#[derive(Copy, Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
-pub(crate) struct FlagParameters(pub(super) u8);
+#[derive(Copy, Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes, Unaligned)]
+pub(crate) struct FlagParameters(pub(crate) u8);
 
 /**
  * The new file format records as:
@@ -62,60 +62,84 @@ impl FlagParameters {
         }
     }
 
-    fn use_fexper_extension(&self) -> bool {
+    pub(crate) fn use_fexper_extension(&self) -> bool {
         ((self.0 >> 1) & 1) == 1
     }
 
-    pub(super) fn multifile(&self) -> bool {
+    pub(crate) fn multifile(&self) -> bool {
         ((self.0 >> 2) & 1) == 1
     }
 
-    fn z_values_are_random(&self) -> bool {
+    pub(crate) fn z_values_are_random(&self) -> bool {
         ((self.0 >> 3) & 1) == 1
     }
 
-    fn z_values_are_uneven(&self) -> bool {
+    pub(crate) fn z_values_are_uneven(&self) -> bool {
         ((self.0 >> 4) & 1) == 1
     }
 
-    fn custom_axis_labels(&self) -> bool {
+    pub(crate) fn custom_axis_labels(&self) -> bool {
         ((self.0 >> 5) & 1) == 1
     }
 
-    fn xyxy(&self) -> bool {
+    pub(crate) fn xyxy(&self) -> bool {
         ((self.0 >> 6) & 1) == 1
     }
 
-    pub(super) fn xy(&self) -> bool {
+    pub(crate) fn xy(&self) -> bool {
         ((self.0 >> 7) & 1) == 1
     }
 
-    pub(crate) fn data_shape(&self) -> DataShape {
+    pub(crate) fn data_shape(&self) -> miette::Result<DataShape> {
         // Single file data
         if !self.multifile() {
             // Data is Y or XY
             if !self.xy() {
-                return DataShape::Y;
+                return Ok(DataShape::Y);
             } else if self.xyxy() {
-                panic!("exception in datashape creation")
+                miette::bail!(
+                    "the flags declare separate x-values per subfile, but only one subfile"
+                )
             } else {
-                return DataShape::XY;
+                return Ok(DataShape::XY);
             }
         }
 
         // multifile data
         if !self.xy() {
             // Even X with equidistant Y
-            DataShape::YY
+            Ok(DataShape::YY)
         } else {
-            // Uneven x
-            if !self.xy() {
-                DataShape::XYY
+            // Uneven x, shared by every subfile unless TXYXYS is set
+            if !self.xyxy() {
+                Ok(DataShape::XYY)
             } else {
-                DataShape::XYXY
+                Ok(DataShape::XYXY)
             }
         }
     }
+
+    // The flags with TMULTI, TXYXYS and TXVALS set to describe `shape`
+    pub(crate) fn with_shape(self, shape: DataShape) -> Self {
+        let bits = match shape {
+            DataShape::Y => 0,
+            DataShape::XY => 0b1000_0000,
+            DataShape::YY => 0b0000_0100,
+            DataShape::XYY => 0b1000_0100,
+            DataShape::XYXY => 0b1100_0100,
+        };
+        Self((self.0 & !0b1100_0100) | bits)
+    }
+
+    // The flags with TORDRD set, so the z-value of each subfile is read from its subheader
+    pub(crate) fn with_uneven_z(self, uneven: bool) -> Self {
+        Self((self.0 & !0b0001_0000) | ((uneven as u8) << 4))
+    }
+
+    // The flags with TALABS set, so the axis labels are read from the header text
+    pub(crate) fn with_custom_axis_labels(self, custom: bool) -> Self {
+        Self((self.0 & !0b0010_0000) | ((custom as u8) << 5))
+    }
 }
 
 impl ::std::fmt::Display for FlagParameters {
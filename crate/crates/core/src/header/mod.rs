@@ -1,25 +1,33 @@
+mod codes;
 mod flags;
 mod subheader;
 
+pub use codes::{Modification, ModificationFlags, PostingDisposition, ProcessingCode};
 pub(crate) use flags::{DataShape, FlagParameters, Precision};
 use miette::Diagnostic;
-pub(crate) use subheader::{LexedSubheader, Subheader, SubheaderParseError};
+pub(crate) use subheader::{LexedSubheader, SubheaderParseError};
+pub use subheader::{SubFlagParameters, Subheader};
 use zerocopy::{
     byteorder::{F32, F64, I16, U16, U32},
-    ByteOrder, Immutable, KnownLayout, TryFromBytes,
+    ByteOrder, Immutable, IntoBytes, KnownLayout, TryFromBytes,
 };
 
 use crate::{
-    block::YMode, lex::Version, parse::TryParse, xzwType, xzwTypeCreationError, yType,
-    yTypeCreationError, InstrumentTechnique, InstrumentTechniqueCreationError,
+    block::YMode,
+    inspect::{describe, field_span, lossy_text, FieldSpan, Inspect},
+    lex::Version,
+    parse::{HeaderTimezone, ParseOptions, TryParseWith},
+    text::Text,
+    xzwType, xzwTypeCreationError, yType, yTypeCreationError, InstrumentTechnique,
+    InstrumentTechniqueCreationError,
 };
 
-use chrono::{DateTime, LocalResult, TimeZone, Utc};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Timelike, Utc};
 
 #[derive(thiserror::Error, Debug, Diagnostic)]
 pub(crate) enum HeaderParseError {
     #[error(
-        "Ambiguous datetime data:\n
+        "Invalid datetime data:\n
                 year = {year},\n
                 month = {month},\n
                 date = {date},\n
@@ -63,7 +71,7 @@ impl<E: ByteOrder> LexedHeader<'_, E> {
         }
     }
 
-    pub(crate) fn data_shape(&self) -> DataShape {
+    pub(crate) fn data_shape(&self) -> miette::Result<DataShape> {
         match self {
             LexedHeader::Old(header) => &header.flags,
             LexedHeader::New(header) => &header.flags,
@@ -165,25 +173,92 @@ impl<E: ByteOrder> LexedHeader<'_, E> {
 }
 
 #[derive(Clone, Debug)]
-pub(crate) enum Header {
+pub enum Header {
     // Headers created by SPC software pre-1996 with file version 0x4b
     Old(OldFormatHeader),
     // Headers created by SPC software post with file versions 0x4c of 0x4d
     New(NewFormatHeader),
 }
 
-impl<E: ByteOrder> TryParse for LexedHeader<'_, E> {
+impl<E: ByteOrder> TryParseWith for LexedHeader<'_, E> {
     type Parsed = Header;
     type Error = HeaderParseError;
-    fn try_parse(&self) -> Result<Self::Parsed, Self::Error> {
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error> {
         Ok(match self {
-            LexedHeader::Old(header) => Header::Old(header.try_parse()?),
-            LexedHeader::New(header) => Header::New(header.try_parse()?),
+            LexedHeader::Old(header) => Header::Old(header.try_parse_with(options)?),
+            LexedHeader::New(header) => Header::New(header.try_parse_with(options)?),
         })
     }
 }
 
 impl Header {
+    /// The memo text
+    pub fn memo(&self) -> &Text {
+        match self {
+            Header::Old(header) => header.memo(),
+            Header::New(header) => header.memo(),
+        }
+    }
+
+    /// The resolution description text
+    pub fn resolution_description(&self) -> &Text {
+        match self {
+            Header::Old(header) => header.resolution_description(),
+            Header::New(header) => header.resolution_description(),
+        }
+    }
+
+    /// The custom axis labels, see [`Text::segments`] for the individual labels
+    pub fn xyz_labels(&self) -> &Text {
+        match self {
+            Header::Old(header) => header.xyz_labels(),
+            Header::New(header) => header.xyz_labels(),
+        }
+    }
+
+    /// The units of the x-axis
+    pub fn x_unit_type(&self) -> xzwType {
+        match self {
+            Header::Old(header) => header.x_unit_type,
+            Header::New(header) => header.x_unit_type,
+        }
+    }
+
+    /// The units of the y-axis
+    pub fn y_unit_type(&self) -> yType {
+        match self {
+            Header::Old(header) => header.y_unit_type,
+            Header::New(header) => header.y_unit_type,
+        }
+    }
+
+    /// The units of the z-axis
+    pub fn z_unit_type(&self) -> xzwType {
+        match self {
+            Header::Old(header) => header.z_unit_type,
+            Header::New(header) => header.z_unit_type,
+        }
+    }
+
+    /// The labels of the x, y and z axes. The custom labels are used if the file flags say they
+    /// are present, falling back to the label of the unit type for any which are empty
+    pub fn axis_labels(&self) -> [String; 3] {
+        let custom = if self.flags().custom_axis_labels() {
+            self.xyz_labels().segments()
+        } else {
+            Vec::new()
+        };
+        let defaults = [
+            self.x_unit_type().label(),
+            self.y_unit_type().label(),
+            self.z_unit_type().label(),
+        ];
+        ::std::array::from_fn(|axis| match custom.get(axis) {
+            Some(label) if !label.is_empty() => label.clone(),
+            _ => defaults[axis].to_owned(),
+        })
+    }
+
     pub(crate) fn exponent_y(&self) -> i32 {
         match self {
             Header::Old(header) => header.exponent_y as i32,
@@ -212,13 +287,64 @@ impl Header {
         }
     }
 
+    pub(crate) fn flags(&self) -> FlagParameters {
+        match self {
+            Header::Old(header) => header.flags,
+            Header::New(header) => header.flags,
+        }
+    }
+
+    // The z-increment between evenly spaced subfiles. The old format does not store one
+    pub(crate) fn z_sub_increment(&self) -> f32 {
+        match self {
+            Header::Old(_) => 0.0,
+            Header::New(header) => header.z_sub_increment,
+        }
+    }
+
+    // The number of w-planes, zero if the subfiles are not grouped into planes
+    pub(crate) fn w_planes(&self) -> usize {
+        match self {
+            Header::Old(_) => 0,
+            Header::New(header) => header.w_planes as usize,
+        }
+    }
+
+    pub(crate) fn w_plane_increment(&self) -> f32 {
+        match self {
+            Header::Old(_) => 0.0,
+            Header::New(header) => header.w_plane_increment,
+        }
+    }
+
+    // A copy of the header describing a single w-plane holding the given number of subfiles
+    pub(crate) fn for_plane(&self, subfiles: usize) -> Header {
+        match self {
+            Header::Old(header) => Header::Old(header.clone()),
+            Header::New(header) => Header::New(NewFormatHeader {
+                spectra: subfiles as u32,
+                w_planes: header.w_planes.min(1),
+                ..header.clone()
+            }),
+        }
+    }
+
     pub(crate) fn x_points(&self) -> Vec<f64> {
-        let step = (self.ending_x() - self.starting_x()) / ((self.number_points() - 1) as f64);
+        even_x_points(self.starting_x(), self.ending_x(), self.number_points())
+    }
+}
 
-        (0..self.number_points())
-            .map(|i| self.starting_x() + i as f64 * step)
-            .collect()
+// The x-values of a file with evenly spaced x, which are not stored explicitly
+pub(crate) fn even_x_points(starting_x: f64, ending_x: f64, number_points: usize) -> Vec<f64> {
+    // There is no spacing to find without a second point
+    if number_points <= 1 {
+        return vec![starting_x; number_points];
     }
+    let step = (ending_x - starting_x) / ((number_points - 1) as f64);
+
+    (0..number_points)
+        .map(|i| starting_x + i as f64 * step)
+        .collect()
 }
 
 /// In the old SPC format, the header is 224 bytes long. The subsequent single sub-header is
@@ -253,7 +379,7 @@ impl Header {
 /// Contrasing the [`NewFormatHeader`], in this format the number of points is stored as a float,
 /// rather than a double and the x-limits are only stored in single precision.
 #[repr(C)]
-#[derive(Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
+#[derive(Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes)]
 pub(crate) struct LexedOldFormatHeader<E: ByteOrder> {
     /// The [`FlagParameters`] for the .SPC
     pub(super) flags: FlagParameters,
@@ -296,30 +422,48 @@ pub(crate) struct LexedOldFormatHeader<E: ByteOrder> {
     pub(super) xyz_labels: [u8; 30],
 }
 
-pub(crate) fn str_from_null_terminated_utf8_safe(s: &[u8]) -> &str {
-    if s.iter().any(|&x| x == 0) {
-        unsafe { str_from_null_terminated_utf8(s) }
-    } else {
-        ::std::str::from_utf8(s).unwrap()
+// Interpret the collection time stored in a header as a wall-clock time in the given timezone.
+//
+// Many instruments write a zero year when they do not record the collection time, in which case
+// there is no datetime.
+fn collection_datetime(
+    timezone: HeaderTimezone,
+    year: u16,
+    month: u8,
+    date: u8,
+    hours: u8,
+    minutes: u8,
+) -> Result<Option<DateTime<Utc>>, HeaderParseError> {
+    if year == 0 {
+        return Ok(None);
     }
+    NaiveDate::from_ymd_opt(year as i32, month as u32, date as u32)
+        .and_then(|date| date.and_hms_opt(hours as u32, minutes as u32, 0))
+        .and_then(|naive| timezone.to_utc(&naive))
+        .map(Some)
+        .ok_or(HeaderParseError::Datetime {
+            year,
+            month,
+            date,
+            hours,
+            minutes,
+        })
 }
 
-// unsafe: s must contain a null byte
-unsafe fn str_from_null_terminated_utf8(s: &[u8]) -> &str {
-    ::std::ffi::CStr::from_ptr(s.as_ptr() as *const _)
-        .to_str()
-        .unwrap()
-}
-
-// unsafe: s must contain a null byte, and be valid utf-8
-unsafe fn str_from_null_terminated_utf8_unchecked(s: &[u8]) -> &str {
-    ::std::str::from_utf8_unchecked(::std::ffi::CStr::from_ptr(s.as_ptr() as *const _).to_bytes())
+// Pack a wall-clock time as a new-format header stores it, see [`NewFormatHeader::packed_datetime`]
+fn pack_datetime(year: u16, month: u8, day: u8, hour: u8, minute: u8) -> u32 {
+    ((year as u32 & 0x0fff) << 20)
+        | ((month as u32 & 0b1111) << 16)
+        | ((day as u32 & 0b11111) << 11)
+        | ((hour as u32 & 0b11111) << 6)
+        | (minute as u32 & 0b111111)
 }
 
-impl<E: ByteOrder> TryParse for LexedOldFormatHeader<E> {
+impl<E: ByteOrder> TryParseWith for LexedOldFormatHeader<E> {
     type Parsed = OldFormatHeader;
     type Error = HeaderParseError;
-    fn try_parse(&self) -> Result<Self::Parsed, Self::Error> {
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error> {
+        let encoding = options.encoding();
         // Check for validity
         if self.spare.iter().any(|&x| x != 0.0) {
             return Err(HeaderParseError::SpareNonZero);
@@ -339,61 +483,75 @@ impl<E: ByteOrder> TryParse for LexedOldFormatHeader<E> {
             },
             datetime: {
                 let z_type_year: u16 = self.year.into();
-                let year = z_type_year & 0x0fff;
-                if year == 0 {
-                    None
-                } else {
-                    match Utc.with_ymd_and_hms(
-                        year as i32,
-                        self.month as u32,
-                        self.day as u32,
-                        self.hour as u32,
-                        self.minute as u32,
-                        0,
-                    ) {
-                        LocalResult::Single(datetime) => Some(datetime),
-                        LocalResult::None => {
-                            return Err(HeaderParseError::Datetime {
-                                year,
-                                month: self.month,
-                                date: self.day,
-                                hours: self.hour,
-                                minutes: self.minute,
-                            });
-                        }
-                        LocalResult::Ambiguous(a, b) => {
-                            dbg!(a, b);
-
-                            return Err(HeaderParseError::Datetime {
-                                year,
-                                month: self.month,
-                                date: self.day,
-                                hours: self.hour,
-                                minutes: self.minute,
-                            });
-                        }
-                    }
-                }
+                collection_datetime(
+                    options.timezone(),
+                    z_type_year & 0x0fff,
+                    self.month,
+                    self.day,
+                    self.hour,
+                    self.minute,
+                )?
             },
-            resolution_description: str_from_null_terminated_utf8_safe(
-                &self.resolution_description,
-            )
-            .trim()
-            .to_owned(),
+            packed_datetime: {
+                let z_type_year: u16 = self.year.into();
+                pack_datetime(
+                    z_type_year & 0x0fff,
+                    self.month,
+                    self.day,
+                    self.hour,
+                    self.minute,
+                )
+            },
+            resolution_description: Text::decode(&self.resolution_description, encoding),
             peak_point_number: self.peak_point_number.into(),
             scans: self.scans.into(),
-            memo: str_from_null_terminated_utf8_safe(&self.memo)
-                .trim()
-                .to_owned(),
-            xyz_labels: str_from_null_terminated_utf8_safe(&self.xyz_labels)
-                .trim()
-                .to_owned(),
+            memo: Text::decode(&self.memo, encoding),
+            xyz_labels: Text::decode(&self.xyz_labels, encoding),
         })
     }
 }
 
+impl<E: ByteOrder> Inspect for LexedOldFormatHeader<E> {
+    fn inspect(&self) -> Vec<FieldSpan> {
+        let z_type_year = self.year.get();
+        vec![
+            field_span!(self, flags, format!("{:#010b}", self.flags.0)),
+            field_span!(self, version, format!("{:#04x}", self.version)),
+            field_span!(self, exponent_y, self.exponent_y.get()),
+            field_span!(self, number_points, self.number_points.get()),
+            field_span!(self, starting_x, self.starting_x.get()),
+            field_span!(self, ending_x, self.ending_x.get()),
+            field_span!(self, x_unit_type, describe(xzwType::new(self.x_unit_type))),
+            field_span!(self, y_unit_type, describe(yType::new(self.y_unit_type))),
+            field_span!(
+                self,
+                year,
+                format!(
+                    "year {}, z-type {}",
+                    z_type_year & 0x0fff,
+                    describe(xzwType::new((z_type_year >> 12) as u8))
+                )
+            ),
+            field_span!(self, month, self.month),
+            field_span!(self, day, self.day),
+            field_span!(self, hour, self.hour),
+            field_span!(self, minute, self.minute),
+            field_span!(
+                self,
+                resolution_description,
+                lossy_text(&self.resolution_description)
+            ),
+            field_span!(self, peak_point_number, self.peak_point_number.get()),
+            field_span!(self, scans, self.scans.get()),
+            field_span!(self, spare, format!("{:?}", self.spare.map(|x| x.get()))),
+            field_span!(self, memo, lossy_text(&self.memo)),
+            field_span!(self, xyz_labels, lossy_text(&self.xyz_labels)),
+        ]
+    }
+}
+
 #[derive(Clone, Debug)]
-pub(crate) struct OldFormatHeader {
+pub struct OldFormatHeader {
     /// The [`FlagParameters`] for the .SPC
     pub(super) flags: FlagParameters,
     pub(super) version: u8,
@@ -424,12 +582,148 @@ pub(crate) struct OldFormatHeader {
     pub(super) y_unit_type: yType,
     pub(super) z_unit_type: xzwType,
     pub(super) datetime: Option<DateTime<Utc>>,
-    pub(super) resolution_description: String,
+    /// The collection time exactly as stored in the file, packed as in the new format
+    pub(super) packed_datetime: u32,
+    pub(super) resolution_description: Text,
     pub(super) peak_point_number: u16,
     pub(super) scans: u16,
     // pub(super) spare: [f32; 7],
-    pub(super) memo: String,
-    pub(super) xyz_labels: String,
+    pub(super) memo: Text,
+    pub(super) xyz_labels: Text,
+}
+
+impl OldFormatHeader {
+    /// The file version, always 0x4d
+    pub fn file_version(&self) -> u8 {
+        self.version
+    }
+
+    /// The exponent used to scale integer y-data, or 128 if the y-data is stored as floats
+    pub fn exponent_y(&self) -> i16 {
+        self.exponent_y
+    }
+
+    /// The number of points in each subfile
+    pub fn number_points(&self) -> f32 {
+        self.number_points
+    }
+
+    /// The first x-coordinate
+    pub fn starting_x(&self) -> f32 {
+        self.starting_x
+    }
+
+    /// The last x-coordinate
+    pub fn ending_x(&self) -> f32 {
+        self.ending_x
+    }
+
+    pub fn x_unit_type(&self) -> xzwType {
+        self.x_unit_type
+    }
+
+    pub fn y_unit_type(&self) -> yType {
+        self.y_unit_type
+    }
+
+    pub fn z_unit_type(&self) -> xzwType {
+        self.z_unit_type
+    }
+
+    /// The peak point number for interferograms
+    pub fn peak_point_number(&self) -> u16 {
+        self.peak_point_number
+    }
+
+    /// The number of co-added scans
+    pub fn scans(&self) -> u16 {
+        self.scans
+    }
+
+    /// The time the data was collected, or `None` if the file does not record it
+    pub fn datetime(&self) -> Option<DateTime<Utc>> {
+        self.datetime
+    }
+
+    /// The collection time exactly as stored in the file, packed as in a new-format header, see
+    /// [`NewFormatHeader::packed_datetime`]
+    pub fn packed_datetime(&self) -> u32 {
+        self.packed_datetime
+    }
+
+    /// The resolution description text
+    pub fn resolution_description(&self) -> &Text {
+        &self.resolution_description
+    }
+
+    /// The memo text
+    pub fn memo(&self) -> &Text {
+        &self.memo
+    }
+
+    /// The custom axis labels, see [`Text::segments`] for the individual labels
+    pub fn xyz_labels(&self) -> &Text {
+        &self.xyz_labels
+    }
+
+    // The fields of a new-format header which the old format can hold
+    pub(crate) fn from_new(header: &NewFormatHeader) -> Self {
+        Self {
+            flags: header.flags,
+            version: 0x4d,
+            // Float data is marked by an exponent of 0x80, which is -128 as a single byte
+            exponent_y: match header.exponent_y {
+                -128 => 0x80,
+                exponent => exponent as i16,
+            },
+            number_points: header.number_points as f32,
+            starting_x: header.starting_x as f32,
+            ending_x: header.ending_x as f32,
+            x_unit_type: header.x_unit_type,
+            y_unit_type: header.y_unit_type,
+            z_unit_type: header.z_unit_type,
+            datetime: header.datetime,
+            packed_datetime: header.packed_datetime,
+            resolution_description: header.resolution_description.clone(),
+            peak_point_number: header.peak_point_number,
+            scans: 0,
+            memo: header.memo.clone(),
+            xyz_labels: header.xyz_labels.clone(),
+        }
+    }
+
+    // The header as it is laid out in a file with byte order `E`
+    pub(crate) fn to_lexed<E: ByteOrder>(&self) -> LexedOldFormatHeader<E> {
+        // The wall-clock time as it was stored, rather than the instant it was read as
+        let packed = self.packed_datetime;
+        let year = (packed >> 20) as u16;
+        // The z-type is stored in four bits, so larger codes are written as arbitrary units
+        let z_type = match self.z_unit_type as u16 {
+            z_type @ 0..=0x0f => z_type,
+            _ => 0,
+        };
+        LexedOldFormatHeader {
+            flags: self.flags,
+            version: self.version,
+            exponent_y: I16::new(self.exponent_y),
+            number_points: F32::new(self.number_points),
+            starting_x: F32::new(self.starting_x),
+            ending_x: F32::new(self.ending_x),
+            x_unit_type: self.x_unit_type as u8,
+            y_unit_type: self.y_unit_type as u8,
+            year: U16::new((z_type << 12) | year),
+            month: ((packed >> 16) & 0b1111) as u8,
+            day: ((packed >> 11) & 0b11111) as u8,
+            hour: ((packed >> 6) & 0b11111) as u8,
+            minute: (packed & 0b111111) as u8,
+            resolution_description: self.resolution_description.to_field(),
+            peak_point_number: U16::new(self.peak_point_number),
+            scans: U16::new(self.scans),
+            spare: [F32::new(0.0); 7],
+            memo: self.memo.to_field(),
+            xyz_labels: self.xyz_labels.to_field(),
+        }
+    }
 }
 
 /// A New format header is always 512 bytes long.
@@ -472,7 +766,7 @@ pub(crate) struct OldFormatHeader {
 /// - Byte: W axis units
 /// - Char[187]: Reserved
 #[repr(C)]
-#[derive(Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
+#[derive(Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes)]
 pub(crate) struct LexedNewFormatHeader<E: ByteOrder> {
     /// Flag parameters are packend into a single byte
     pub(super) flags: FlagParameters,
@@ -523,11 +817,12 @@ pub(crate) struct LexedNewFormatHeader<E: ByteOrder> {
     pub(super) reserved: [u8; 187],
 }
 
-impl<E: ByteOrder> TryParse for LexedNewFormatHeader<E> {
+impl<E: ByteOrder> TryParseWith for LexedNewFormatHeader<E> {
     type Parsed = NewFormatHeader;
     type Error = HeaderParseError;
 
-    fn try_parse(&self) -> Result<Self::Parsed, Self::Error> {
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error> {
+        let encoding = options.encoding();
         // Check for validity
         if self.spare.iter().any(|&x| x != 0.0) {
             return Err(HeaderParseError::SpareNonZero);
@@ -549,100 +844,153 @@ impl<E: ByteOrder> TryParse for LexedNewFormatHeader<E> {
             x_unit_type: xzwType::new(self.x_unit_type)?,
             y_unit_type: yType::new(self.y_unit_type)?,
             z_unit_type: xzwType::new(self.z_unit_type)?,
-            posting_disposition: self.posting_disposition,
+            posting_disposition: self.posting_disposition.into(),
             datetime: {
                 let datetime: u32 = self.datetime.into();
-                // The least significant six bits are the minutes
-                let minutes = (datetime & 0b111111) as u8;
-                // The next five bits are the hour
-                let hours = ((datetime >> 6) & 0b11111) as u8;
-                // The next five bits are the day
-                let date = ((datetime >> 11) & 0b11111) as u8;
-                // The next four bits are the month
-                let month = ((datetime >> 16) & 0b1111) as u8;
-                // And the rest is the year
-                let year = (datetime >> 20) as u16;
-                //
-                println!(
-                    "Year: {}, Month: {}, Date: {}, Hours: {}, Minutes: {}",
-                    year, month, date, hours, minutes
-                );
-
-                match Utc.with_ymd_and_hms(
-                    year as i32,
-                    month as u32,
-                    date as u32,
-                    hours as u32,
-                    minutes as u32,
-                    0,
-                ) {
-                    LocalResult::Single(datetime) => datetime,
-                    LocalResult::None => {
-                        return Err(HeaderParseError::Datetime {
-                            year,
-                            month,
-                            date,
-                            hours,
-                            minutes,
-                        });
-                    }
-                    LocalResult::Ambiguous(_, _) => {
-                        return Err(HeaderParseError::Datetime {
-                            year,
-                            month,
-                            date,
-                            hours,
-                            minutes,
-                        });
-                    }
-                }
+                collection_datetime(
+                    options.timezone(),
+                    // The most significant twelve bits are the year
+                    (datetime >> 20) as u16,
+                    // The next four bits are the month
+                    ((datetime >> 16) & 0b1111) as u8,
+                    // The next five bits are the day
+                    ((datetime >> 11) & 0b11111) as u8,
+                    // The next five bits are the hour
+                    ((datetime >> 6) & 0b11111) as u8,
+                    // And the least significant six bits are the minutes
+                    (datetime & 0b111111) as u8,
+                )?
             },
-            resolution_description: str_from_null_terminated_utf8_safe(
-                &self.resolution_description,
-            )
-            .trim()
-            .to_owned(),
-            source_instrument_description: str_from_null_terminated_utf8_safe(
+            packed_datetime: self.datetime.into(),
+            resolution_description: Text::decode(&self.resolution_description, encoding),
+            source_instrument_description: Text::decode(
                 &self.source_instrument_description,
-            )
-            .trim()
-            .to_owned(),
+                encoding,
+            ),
             peak_point_number: self.peak_point_number.into(),
-            memo: str_from_null_terminated_utf8_safe(&self.memo)
-                .trim()
-                .to_owned(),
-            xyz_labels: str_from_null_terminated_utf8_safe(&self.xyz_labels)
-                .trim()
-                .to_owned(),
+            memo: Text::decode(&self.memo, encoding),
+            xyz_labels: Text::decode(&self.xyz_labels, encoding),
             log_offset: self.log_offset.into(),
-            modified_flag: self.modified_flag.into(),
-            processing_code: self.processing_code,
+            modified_flag: ModificationFlags(self.modified_flag.into()),
+            processing_code: self.processing_code.into(),
             calibration_level: self.calibration_level,
             sub_method_sample_injection_number: self.sub_method_sample_injection_number.into(),
             concentration_factor: self.concentration_factor.into(),
-            method_file: str_from_null_terminated_utf8_safe(&self.method_file)
-                .trim()
-                .to_owned(),
+            method_file: Text::decode(&self.method_file, encoding),
             z_sub_increment: self.z_sub_increment.into(),
             w_planes: self.w_planes.into(),
             w_plane_increment: self.w_plane_increment.into(),
-            w_axis_units: self.w_axis_units,
+            // An unknown w-unit code is read as arbitrary units rather than failing the parse
+            w_axis_units: xzwType::new(self.w_axis_units).unwrap_or_else(|error| {
+                log::warn!("{error}, reading the w-axis as arbitrary units");
+                xzwType::Arbitrary
+            }),
         })
     }
 }
 
+impl<E: ByteOrder> Inspect for LexedNewFormatHeader<E> {
+    fn inspect(&self) -> Vec<FieldSpan> {
+        let datetime = self.datetime.get();
+        vec![
+            field_span!(self, flags, format!("{:#010b}", self.flags.0)),
+            field_span!(self, file_version, format!("{:#04x}", self.file_version)),
+            field_span!(
+                self,
+                instrument_technique,
+                describe(InstrumentTechnique::new(self.instrument_technique))
+            ),
+            field_span!(self, exponent_y, self.exponent_y),
+            field_span!(self, number_points, self.number_points.get()),
+            field_span!(self, starting_x, self.starting_x.get()),
+            field_span!(self, ending_x, self.ending_x.get()),
+            field_span!(self, spectra, self.spectra.get()),
+            field_span!(self, x_unit_type, describe(xzwType::new(self.x_unit_type))),
+            field_span!(self, y_unit_type, describe(yType::new(self.y_unit_type))),
+            field_span!(self, z_unit_type, describe(xzwType::new(self.z_unit_type))),
+            field_span!(
+                self,
+                posting_disposition,
+                format!("{:?}", PostingDisposition::from(self.posting_disposition))
+            ),
+            field_span!(
+                self,
+                datetime,
+                format!(
+                    "{:04}-{:02}-{:02} {:02}:{:02}",
+                    datetime >> 20,
+                    (datetime >> 16) & 0b1111,
+                    (datetime >> 11) & 0b11111,
+                    (datetime >> 6) & 0b11111,
+                    datetime & 0b111111
+                )
+            ),
+            field_span!(
+                self,
+                resolution_description,
+                lossy_text(&self.resolution_description)
+            ),
+            field_span!(
+                self,
+                source_instrument_description,
+                lossy_text(&self.source_instrument_description)
+            ),
+            field_span!(self, peak_point_number, self.peak_point_number.get()),
+            field_span!(self, spare, format!("{:?}", self.spare.map(|x| x.get()))),
+            field_span!(self, memo, lossy_text(&self.memo)),
+            field_span!(self, xyz_labels, lossy_text(&self.xyz_labels)),
+            field_span!(self, log_offset, format!("{:#x}", self.log_offset.get())),
+            field_span!(
+                self,
+                modified_flag,
+                ModificationFlags(self.modified_flag.get())
+            ),
+            field_span!(
+                self,
+                processing_code,
+                format!("{:?}", ProcessingCode::from(self.processing_code))
+            ),
+            field_span!(self, calibration_level, self.calibration_level),
+            field_span!(
+                self,
+                sub_method_sample_injection_number,
+                self.sub_method_sample_injection_number.get()
+            ),
+            field_span!(self, concentration_factor, self.concentration_factor.get()),
+            field_span!(self, method_file, lossy_text(&self.method_file)),
+            field_span!(self, z_sub_increment, self.z_sub_increment.get()),
+            field_span!(self, w_planes, self.w_planes.get()),
+            field_span!(self, w_plane_increment, self.w_plane_increment.get()),
+            field_span!(
+                self,
+                w_axis_units,
+                describe(xzwType::new(self.w_axis_units))
+            ),
+            field_span!(
+                self,
+                reserved,
+                if self.reserved.iter().all(|&x| x == 0) {
+                    "zeroed"
+                } else {
+                    "NON-ZERO"
+                }
+            ),
+        ]
+    }
+}
+
 #[derive(Clone, Debug)]
-pub(crate) struct NewFormatHeader {
+pub struct NewFormatHeader {
     /// Flag parameters are packend into a single byte
-    pub(super) flags: FlagParameters,
+    pub(crate) flags: FlagParameters,
     /// File version for a New Format SPC File.
     ///
     /// This must either be 0x4b or 0x4c. The difference refers to the ordering of data in the
     /// binary file:
     /// - 0x4b Refers to LSB (Least Significant Bit) ordering. Or Little Endian.
     /// - 0x4c Refers to MSB (Most Significant Bit) ordering. Or Big Endian.
-    pub(super) file_version: u8,
-    pub(super) instrument_technique: InstrumentTechnique,
+    pub(crate) file_version: u8,
+    pub(crate) instrument_technique: InstrumentTechnique,
     /// The exponent for the Y values.
     ///
     /// If the exponent is equal to 80h, then the values are to be interpreted directly as floating
@@ -650,34 +998,387 @@ pub(crate) struct NewFormatHeader {
     /// - FloatY = (2^ExponentY) * IntY / (2^32)
     /// - FloatY = (2^ExponentY) * IntY / (2^16)
     /// Depending on the whether the data is 16 or 32 bit according to the flag parameters.
-    pub(super) exponent_y: i8,
+    pub(crate) exponent_y: i8,
     /// If the file is not in XYXY format then this refers to the number of points contained in the
     /// file
-    pub(super) number_points: u32,
-    pub(super) starting_x: f64,
-    pub(super) ending_x: f64,
-    pub(super) spectra: u32,
-    pub(super) x_unit_type: xzwType,
-    pub(super) y_unit_type: yType,
-    pub(super) z_unit_type: xzwType,
-    pub(super) posting_disposition: u8,
-    pub(super) datetime: DateTime<Utc>,
-    pub(super) resolution_description: String,
-    pub(super) source_instrument_description: String,
-    pub(super) peak_point_number: u16,
-    pub(super) memo: String,
-    pub(super) xyz_labels: String,
-    pub(super) log_offset: u32,
-    pub(super) modified_flag: u32,
-    pub(super) processing_code: u8,
-    pub(super) calibration_level: u8,
-    pub(super) sub_method_sample_injection_number: u16,
-    pub(super) concentration_factor: f32,
-    pub(super) method_file: String,
-    pub(super) z_sub_increment: f32,
-    pub(super) w_planes: u32,
-    pub(super) w_plane_increment: f32,
-    pub(super) w_axis_units: u8,
+    pub(crate) number_points: u32,
+    pub(crate) starting_x: f64,
+    pub(crate) ending_x: f64,
+    pub(crate) spectra: u32,
+    pub(crate) x_unit_type: xzwType,
+    pub(crate) y_unit_type: yType,
+    pub(crate) z_unit_type: xzwType,
+    pub(crate) posting_disposition: PostingDisposition,
+    /// The time the data was collected, or `None` if the file does not record it
+    pub(crate) datetime: Option<DateTime<Utc>>,
+    /// The packed collection time exactly as stored in the file
+    pub(crate) packed_datetime: u32,
+    pub(crate) resolution_description: Text,
+    pub(crate) source_instrument_description: Text,
+    pub(crate) peak_point_number: u16,
+    pub(crate) memo: Text,
+    pub(crate) xyz_labels: Text,
+    pub(crate) log_offset: u32,
+    pub(crate) modified_flag: ModificationFlags,
+    pub(crate) processing_code: ProcessingCode,
+    /// The calibration level plus one, where 1 marks data which is not calibration data and 0 is
+    /// left by software which does not set the field
+    pub(crate) calibration_level: u8,
+    pub(crate) sub_method_sample_injection_number: u16,
+    pub(crate) concentration_factor: f32,
+    pub(crate) method_file: Text,
+    pub(crate) z_sub_increment: f32,
+    pub(crate) w_planes: u32,
+    pub(crate) w_plane_increment: f32,
+    pub(crate) w_axis_units: xzwType,
+}
+
+impl NewFormatHeader {
+    /// The file version, 0x4b for little-endian files and 0x4c for big-endian files
+    pub fn file_version(&self) -> u8 {
+        self.file_version
+    }
+
+    /// The technique of the instrument which collected the data
+    pub fn instrument_technique(&self) -> InstrumentTechnique {
+        self.instrument_technique
+    }
+
+    /// The exponent used to scale integer y-data, or -128 if the y-data is stored as floats
+    pub fn exponent_y(&self) -> i8 {
+        self.exponent_y
+    }
+
+    /// The number of points in each subfile, unless the file is XYXY
+    pub fn number_points(&self) -> u32 {
+        self.number_points
+    }
+
+    /// The first x-coordinate
+    pub fn starting_x(&self) -> f64 {
+        self.starting_x
+    }
+
+    /// The last x-coordinate
+    pub fn ending_x(&self) -> f64 {
+        self.ending_x
+    }
+
+    /// The number of subfiles
+    pub fn number_of_subfiles(&self) -> u32 {
+        self.spectra
+    }
+
+    pub fn x_unit_type(&self) -> xzwType {
+        self.x_unit_type
+    }
+
+    pub fn y_unit_type(&self) -> yType {
+        self.y_unit_type
+    }
+
+    pub fn z_unit_type(&self) -> xzwType {
+        self.z_unit_type
+    }
+
+    /// What the collecting software should do with the data after acquisition
+    pub fn posting_disposition(&self) -> PostingDisposition {
+        self.posting_disposition
+    }
+
+    /// The peak point number for interferograms
+    pub fn peak_point_number(&self) -> u16 {
+        self.peak_point_number
+    }
+
+    /// The offset of the log block in bytes, zero if there is no log
+    pub fn log_offset(&self) -> u32 {
+        self.log_offset
+    }
+
+    /// The operations which have modified the data since collection
+    pub fn modification_flags(&self) -> ModificationFlags {
+        self.modified_flag
+    }
+
+    /// The post-processing to apply to the data after collection
+    pub fn processing_code(&self) -> ProcessingCode {
+        self.processing_code
+    }
+
+    /// The calibration level, or `None` if the data is not calibration data
+    ///
+    /// The file stores the level plus one, where 1 means the data is not calibration data. Zero,
+    /// left by software which does not set the field, is read the same way.
+    pub fn calibration_level(&self) -> Option<u8> {
+        match self.calibration_level {
+            0 | 1 => None,
+            stored => Some(stored - 1),
+        }
+    }
+
+    /// The sample injection number within the sub-method, where 1 is the first or only injection
+    pub fn sub_method_sample_injection_number(&self) -> u16 {
+        self.sub_method_sample_injection_number
+    }
+
+    /// The floating data concentration factor
+    pub fn concentration_factor(&self) -> f32 {
+        self.concentration_factor
+    }
+
+    /// The z-increment between subfiles, for multifiles with evenly spaced z
+    pub fn z_sub_increment(&self) -> f32 {
+        self.z_sub_increment
+    }
+
+    /// The number of w-planes
+    pub fn w_planes(&self) -> u32 {
+        self.w_planes
+    }
+
+    /// The w-increment between planes. If zero the w-value of each plane is taken from the
+    /// subheaders instead
+    pub fn w_plane_increment(&self) -> f32 {
+        self.w_plane_increment
+    }
+
+    pub fn w_unit_type(&self) -> xzwType {
+        self.w_axis_units
+    }
+
+    /// The time the data was collected, or `None` if the file does not record it
+    pub fn datetime(&self) -> Option<DateTime<Utc>> {
+        self.datetime
+    }
+
+    /// The packed collection time exactly as stored in the file
+    ///
+    /// From least to most significant, this holds six bits of minutes, five of hours, five of
+    /// the day, four of the month and twelve of the year.
+    pub fn packed_datetime(&self) -> u32 {
+        self.packed_datetime
+    }
+
+    /// Set the collection time.
+    ///
+    /// The packed representation stores a wall-clock time with no timezone, so the time is packed
+    /// as it reads in the timezone of `datetime`. Passing `None` clears the collection time.
+    pub fn with_datetime<Tz: TimeZone>(mut self, datetime: Option<DateTime<Tz>>) -> Self {
+        self.packed_datetime = datetime.as_ref().map_or(0, |datetime| {
+            pack_datetime(
+                datetime.year() as u16,
+                datetime.month() as u8,
+                datetime.day() as u8,
+                datetime.hour() as u8,
+                datetime.minute() as u8,
+            )
+        });
+        self.datetime = datetime.map(|datetime| datetime.with_timezone(&Utc));
+        self
+    }
+
+    /// The resolution description text
+    pub fn resolution_description(&self) -> &Text {
+        &self.resolution_description
+    }
+
+    /// The source instrument description text
+    pub fn source_instrument_description(&self) -> &Text {
+        &self.source_instrument_description
+    }
+
+    /// The memo text
+    pub fn memo(&self) -> &Text {
+        &self.memo
+    }
+
+    /// The custom axis labels, see [`Text::segments`] for the individual labels
+    pub fn xyz_labels(&self) -> &Text {
+        &self.xyz_labels
+    }
+
+    /// The name of the method file
+    pub fn method_file(&self) -> &Text {
+        &self.method_file
+    }
+
+    // A little-endian header with every field empty, describing a single subfile of float data
+    pub(crate) fn empty() -> Self {
+        Self {
+            flags: FlagParameters(0),
+            file_version: 0x4b,
+            instrument_technique: InstrumentTechnique::GeneralSPC,
+            exponent_y: -128,
+            number_points: 0,
+            starting_x: 0.0,
+            ending_x: 0.0,
+            spectra: 1,
+            x_unit_type: xzwType::Arbitrary,
+            y_unit_type: yType::ArbitraryIntensity,
+            z_unit_type: xzwType::Arbitrary,
+            posting_disposition: PostingDisposition::Default,
+            datetime: None,
+            packed_datetime: 0,
+            resolution_description: Text::default(),
+            source_instrument_description: Text::default(),
+            peak_point_number: 0,
+            memo: Text::default(),
+            xyz_labels: Text::default(),
+            log_offset: 0,
+            modified_flag: ModificationFlags::default(),
+            processing_code: ProcessingCode::None,
+            calibration_level: 0,
+            sub_method_sample_injection_number: 0,
+            concentration_factor: 0.0,
+            method_file: Text::default(),
+            z_sub_increment: 0.0,
+            w_planes: 0,
+            w_plane_increment: 0.0,
+            w_axis_units: xzwType::Arbitrary,
+        }
+    }
+
+    // An old-format header in the new format, with the fields the old format lacks left empty
+    pub(crate) fn from_old(header: &OldFormatHeader) -> Self {
+        Self {
+            flags: header.flags,
+            exponent_y: match header.exponent_y {
+                0x80 => -128,
+                exponent => exponent as i8,
+            },
+            number_points: header.number_points as u32,
+            starting_x: header.starting_x as f64,
+            ending_x: header.ending_x as f64,
+            x_unit_type: header.x_unit_type,
+            y_unit_type: header.y_unit_type,
+            z_unit_type: header.z_unit_type,
+            datetime: header.datetime,
+            packed_datetime: header.packed_datetime,
+            resolution_description: header.resolution_description.clone(),
+            peak_point_number: header.peak_point_number,
+            memo: header.memo.clone(),
+            xyz_labels: header.xyz_labels.clone(),
+            ..Self::empty()
+        }
+    }
+
+    // The header as it is laid out in a file with byte order `E`
+    pub(crate) fn to_lexed<E: ByteOrder>(&self) -> LexedNewFormatHeader<E> {
+        LexedNewFormatHeader {
+            flags: self.flags,
+            file_version: self.file_version,
+            instrument_technique: self.instrument_technique as u8,
+            exponent_y: self.exponent_y,
+            number_points: U32::new(self.number_points),
+            starting_x: F64::new(self.starting_x),
+            ending_x: F64::new(self.ending_x),
+            spectra: U32::new(self.spectra),
+            x_unit_type: self.x_unit_type as u8,
+            y_unit_type: self.y_unit_type as u8,
+            z_unit_type: self.z_unit_type as u8,
+            posting_disposition: self.posting_disposition.into(),
+            datetime: U32::new(self.packed_datetime),
+            resolution_description: self.resolution_description.to_field(),
+            source_instrument_description: self.source_instrument_description.to_field(),
+            peak_point_number: U16::new(self.peak_point_number),
+            spare: [F32::new(0.0); 8],
+            memo: self.memo.to_field(),
+            xyz_labels: self.xyz_labels.to_field(),
+            log_offset: U32::new(self.log_offset),
+            modified_flag: U32::new(self.modified_flag.bits()),
+            processing_code: self.processing_code.into(),
+            calibration_level: self.calibration_level,
+            sub_method_sample_injection_number: U16::new(self.sub_method_sample_injection_number),
+            concentration_factor: F32::new(self.concentration_factor),
+            method_file: self.method_file.to_field(),
+            z_sub_increment: F32::new(self.z_sub_increment),
+            w_planes: U32::new(self.w_planes),
+            w_plane_increment: F32::new(self.w_plane_increment),
+            w_axis_units: self.w_axis_units as u8,
+            reserved: [0; 187],
+        }
+    }
+}
+
+fn display_datetime(datetime: Option<DateTime<Utc>>) -> String {
+    datetime.map_or_else(
+        || "not recorded".to_owned(),
+        |datetime| datetime.to_string(),
+    )
+}
+
+impl ::std::fmt::Display for OldFormatHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "file version: {:#04x}", self.version)?;
+        write!(f, "{}", self.flags)?;
+        writeln!(f, "y exponent: {}", self.exponent_y)?;
+        writeln!(f, "number of points: {}", self.number_points)?;
+        writeln!(f, "x range: {} to {}", self.starting_x, self.ending_x)?;
+        writeln!(f, "x units: {:?}", self.x_unit_type)?;
+        writeln!(f, "y units: {:?}", self.y_unit_type)?;
+        writeln!(f, "z units: {:?}", self.z_unit_type)?;
+        writeln!(f, "collected: {}", display_datetime(self.datetime))?;
+        writeln!(f, "resolution: {}", self.resolution_description)?;
+        writeln!(f, "peak point number: {}", self.peak_point_number)?;
+        writeln!(f, "scans: {}", self.scans)?;
+        writeln!(f, "memo: {}", self.memo)?;
+        writeln!(f, "axis labels: {:?}", self.xyz_labels.segments())?;
+        Ok(())
+    }
+}
+
+impl ::std::fmt::Display for NewFormatHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "file version: {:#04x}", self.file_version)?;
+        write!(f, "{}", self.flags)?;
+        writeln!(f, "instrument technique: {:?}", self.instrument_technique)?;
+        writeln!(f, "y exponent: {}", self.exponent_y)?;
+        writeln!(f, "number of points: {}", self.number_points)?;
+        writeln!(f, "x range: {} to {}", self.starting_x, self.ending_x)?;
+        writeln!(f, "number of subfiles: {}", self.spectra)?;
+        writeln!(f, "x units: {:?}", self.x_unit_type)?;
+        writeln!(f, "y units: {:?}", self.y_unit_type)?;
+        writeln!(f, "z units: {:?}", self.z_unit_type)?;
+        writeln!(f, "posting disposition: {:?}", self.posting_disposition)?;
+        writeln!(f, "collected: {}", display_datetime(self.datetime))?;
+        writeln!(f, "resolution: {}", self.resolution_description)?;
+        writeln!(
+            f,
+            "source instrument: {}",
+            self.source_instrument_description
+        )?;
+        writeln!(f, "peak point number: {}", self.peak_point_number)?;
+        writeln!(f, "memo: {}", self.memo)?;
+        writeln!(f, "axis labels: {:?}", self.xyz_labels.segments())?;
+        writeln!(f, "modifications: {}", self.modified_flag)?;
+        writeln!(f, "processing code: {:?}", self.processing_code)?;
+        match self.calibration_level() {
+            Some(level) => writeln!(f, "calibration level: {level}")?,
+            None => writeln!(f, "calibration level: not calibration data")?,
+        }
+        writeln!(
+            f,
+            "sub-method sample injection number: {}",
+            self.sub_method_sample_injection_number
+        )?;
+        writeln!(f, "concentration factor: {}", self.concentration_factor)?;
+        writeln!(f, "method file: {}", self.method_file)?;
+        writeln!(f, "z increment: {}", self.z_sub_increment)?;
+        writeln!(f, "w planes: {}", self.w_planes)?;
+        writeln!(f, "w increment: {}", self.w_plane_increment)?;
+        writeln!(f, "w units: {:?}", self.w_axis_units)?;
+        Ok(())
+    }
+}
+
+impl ::std::fmt::Display for Header {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Header::Old(header) => write!(f, "{header}"),
+            Header::New(header) => write!(f, "{header}"),
+        }
+    }
 }
 
 // pub(crate) struct HeaderParser<'a, 'de> {
@@ -2077,3 +2778,128 @@ pub(crate) struct NewFormatHeader {
 //         approx::assert_relative_eq!(last_x_in_txt, header.ending_x, epsilon = 1e-2);
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use chrono::{FixedOffset, TimeZone, Timelike, Utc};
+    use zerocopy::{IntoBytes, LittleEndian, TryFromBytes};
+
+    use super::{even_x_points, LexedNewFormatHeader, LexedOldFormatHeader};
+    use crate::{
+        parse::{HeaderTimezone, ParseOptions, TryParseWith},
+        xzwType,
+    };
+
+    // A minimal little-endian new-format header with the given packed collection time
+    fn header_bytes(packed_datetime: u32) -> Vec<u8> {
+        let mut source = vec![0; 512];
+        source[1] = 0x4b;
+        source[32..36].copy_from_slice(&packed_datetime.to_le_bytes());
+        source
+    }
+
+    #[test]
+    fn even_x_points_of_fewer_than_two_points_are_the_starting_x() {
+        assert!(even_x_points(4000.0, 3998.0, 0).is_empty());
+        assert_eq!(even_x_points(4000.0, 3998.0, 1), [4000.0]);
+        assert_eq!(even_x_points(4000.0, 3998.0, 3), [4000.0, 3999.0, 3998.0]);
+    }
+
+    #[test]
+    fn zero_packed_datetime_parses_as_none() {
+        let source = header_bytes(0);
+        let lexed = LexedNewFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+        let header = lexed.try_parse_with(&ParseOptions::default()).unwrap();
+
+        assert!(header.datetime().is_none());
+        assert_eq!(header.packed_datetime(), 0);
+    }
+
+    #[test]
+    fn unknown_w_units_are_read_as_arbitrary() {
+        let mut source = header_bytes(0);
+        source[324] = 200;
+        let lexed = LexedNewFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+        let header = lexed.try_parse_with(&ParseOptions::default()).unwrap();
+
+        assert!(matches!(header.w_unit_type(), xzwType::Arbitrary));
+    }
+
+    #[test]
+    fn calibration_level_is_stored_plus_one() {
+        for (stored, level) in [(0, None), (1, None), (3, Some(2))] {
+            let mut source = header_bytes(0);
+            source[257] = stored;
+            let lexed = LexedNewFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+            let header = lexed.try_parse_with(&ParseOptions::default()).unwrap();
+
+            assert_eq!(header.calibration_level(), level);
+        }
+    }
+
+    #[test]
+    fn packed_datetime_is_interpreted_in_the_configured_timezone() {
+        // 1994-08-26 16:45
+        let packed = (1994 << 20) | (8 << 16) | (26 << 11) | (16 << 6) | 45;
+        let source = header_bytes(packed);
+        let lexed = LexedNewFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+
+        let header = lexed.try_parse_with(&ParseOptions::default()).unwrap();
+        assert_eq!(
+            header.datetime(),
+            Some(Utc.with_ymd_and_hms(1994, 8, 26, 16, 45, 0).unwrap())
+        );
+
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let options = ParseOptions::new().with_timezone(HeaderTimezone::Fixed(offset));
+        let header = lexed.try_parse_with(&options).unwrap();
+        assert_eq!(header.datetime().unwrap().hour(), 14);
+        assert_eq!(header.packed_datetime(), packed);
+
+        // Setting the same wall-clock time in the same timezone packs to the same value
+        let local = offset.with_ymd_and_hms(1994, 8, 26, 16, 45, 0).unwrap();
+        let header = header.with_datetime(Some(local));
+        assert_eq!(header.packed_datetime(), packed);
+        assert_eq!(header.datetime().unwrap().hour(), 14);
+
+        let header = header.with_datetime(None::<chrono::DateTime<Utc>>);
+        assert_eq!(header.packed_datetime(), 0);
+        assert!(header.datetime().is_none());
+    }
+    #[test]
+    fn local_time_skipped_by_daylight_saving_is_read_with_the_earlier_offset() {
+        // 2024-03-31 01:30, which does not occur in London as clocks go from 01:00 GMT to 02:00 BST
+        let packed = (2024 << 20) | (3 << 16) | (31 << 11) | (1 << 6) | 30;
+        let source = header_bytes(packed);
+        let lexed = LexedNewFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+
+        std::env::set_var("TZ", "GMT0BST,M3.5.0/1,M10.5.0");
+        let options = ParseOptions::new().with_timezone(HeaderTimezone::Local);
+        let header = lexed.try_parse_with(&options).unwrap();
+        assert_eq!(
+            header.datetime(),
+            Some(Utc.with_ymd_and_hms(2024, 3, 31, 1, 30, 0).unwrap())
+        );
+        assert_eq!(header.packed_datetime(), packed);
+    }
+
+    #[test]
+    fn old_format_collection_time_is_written_back_as_stored() {
+        // An old-format header collected at 1994-08-26 16:45
+        let mut source = vec![0; size_of::<LexedOldFormatHeader<LittleEndian>>()];
+        source[1] = 0x4d;
+        source[18..20].copy_from_slice(&1994u16.to_le_bytes());
+        source[20..24].copy_from_slice(&[8, 26, 16, 45]);
+        let lexed = LexedOldFormatHeader::<LittleEndian>::try_ref_from_bytes(&source).unwrap();
+
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let options = ParseOptions::new().with_timezone(HeaderTimezone::Fixed(offset));
+        let header = lexed.try_parse_with(&options).unwrap();
+        assert_eq!(header.datetime().unwrap().hour(), 14);
+        assert_eq!(
+            header.packed_datetime(),
+            (1994 << 20) | (8 << 16) | (26 << 11) | (16 << 6) | 45
+        );
+        assert_eq!(header.to_lexed::<LittleEndian>().as_bytes(), source);
+    }
+}
@@ -1,9 +1,12 @@
 use zerocopy::{
     byteorder::{F32, U16, U32},
-    ByteOrder, Immutable, KnownLayout, TryFromBytes,
+    ByteOrder, Immutable, IntoBytes, KnownLayout, TryFromBytes, Unaligned,
 };
 
-use crate::parse::TryParse;
+use crate::{
+    inspect::{field_span, FieldSpan, Inspect},
+    parse::TryParse,
+};
 
 #[derive(Clone, Debug, thiserror::Error, miette::Diagnostic)]
 pub(crate) enum SubheaderParseError {
@@ -29,12 +32,61 @@ impl<E: ByteOrder> GuardedLexedSubheader<E> {
     }
 }
 
+/// Flag parameters for a subfile
+///
+/// Only three of the bits are defined by the specification:
+/// - SUBCHGD (bit 0): The subfile was changed since it was collected
+/// - SUBNOPT (bit 3): The peak table file should not be used
+/// - SUBMODF (bit 7): The subfile was modified by arithmetic
 #[repr(C)]
-#[derive(Clone, Copy, Debug, KnownLayout, Immutable, TryFromBytes)]
-struct SubFlagParameters(u8);
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, KnownLayout, Immutable, IntoBytes, TryFromBytes, Unaligned,
+)]
+pub struct SubFlagParameters(pub(crate) u8);
+
+impl SubFlagParameters {
+    /// The flags as stored in the file
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// Whether the subfile has been changed since it was collected (SUBCHGD)
+    pub fn changed(&self) -> bool {
+        (self.0 & 1) == 1
+    }
+
+    /// Whether the peak table file should be ignored for this subfile (SUBNOPT)
+    pub fn no_peak_table(&self) -> bool {
+        ((self.0 >> 3) & 1) == 1
+    }
+
+    /// Whether the subfile has been modified by arithmetic (SUBMODF)
+    pub fn modified_by_arithmetic(&self) -> bool {
+        ((self.0 >> 7) & 1) == 1
+    }
+}
+
+impl ::std::fmt::Display for SubFlagParameters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = [
+            (self.changed(), "changed"),
+            (self.no_peak_table(), "no peak table"),
+            (self.modified_by_arithmetic(), "modified by arithmetic"),
+        ]
+        .into_iter()
+        .filter_map(|(set, name)| set.then_some(name))
+        .collect::<Vec<_>>();
+
+        if names.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", names.join(", "))
+        }
+    }
+}
 
 #[repr(C)]
-#[derive(Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
+#[derive(Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes)]
 pub(crate) struct LexedSubheader<E: ByteOrder> {
     parameters: SubFlagParameters,
     /// The exponent of the Y axis for the sub-file
@@ -65,9 +117,10 @@ pub(crate) struct LexedSubheader<E: ByteOrder> {
     reserved: [u8; 4],
 }
 
+/// The subheader preceding the y-values of each subfile
 #[derive(Clone, Debug)]
-pub(crate) struct Subheader {
-    parameters: SubFlagParameters,
+pub struct Subheader {
+    pub(crate) parameters: SubFlagParameters,
     /// The exponent of the Y axis for the sub-file
     ///
     /// If the exponent is equal to 80h, then the values are to be interpreted directly as floating
@@ -77,23 +130,19 @@ pub(crate) struct Subheader {
     /// Depending on the whether the data is 16 or 32 bit according to the flag parameters.
     pub(crate) exponent_y: i8,
     /// The integer index number of the trace subfile, where 0 refers to the first
-    index_number: u16,
+    pub(crate) index_number: u16,
     /// The z-axis coordinate for this trace
     pub(crate) z: f32,
     /// The z-axis coordinate for the next trace
-    next_z: f32,
+    pub(crate) next_z: f32,
     /// The floating peak pick noise value, if the high byte is nonzero
-    noise: f32,
+    pub(crate) noise: f32,
     /// The integer number of subfile points for TXYXYS types
     pub(crate) number_points: u32,
     /// The integer number of co-added scans
-    scan: u32,
+    pub(crate) scan: u32,
     /// The value of the floating w-axis (if fwplanes is non-zero)
-    w_level: f32,
-    /// A reserved region which must be set to zero
-    ///
-    /// This is only stored here so we can implement [`TryFromBytes`]
-    reserved: [u8; 4],
+    pub(crate) w_level: f32,
 }
 
 impl<E: ByteOrder> TryParse for LexedSubheader<E> {
@@ -117,11 +166,111 @@ impl<E: ByteOrder> TryParse for LexedSubheader<E> {
             number_points: self.number_points.get(),
             scan: self.scan.get(),
             w_level: self.w_level.get(),
-            reserved: self.reserved,
         })
     }
 }
 
+impl Subheader {
+    // The subheader of a subfile holding float data, with every other field empty
+    pub(crate) fn empty() -> Self {
+        Self {
+            parameters: SubFlagParameters(0),
+            exponent_y: -128,
+            index_number: 0,
+            z: 0.0,
+            next_z: 0.0,
+            noise: 0.0,
+            number_points: 0,
+            scan: 0,
+            w_level: 0.0,
+        }
+    }
+
+    // The subheader as it is laid out in a file with byte order `E`
+    pub(crate) fn to_lexed<E: ByteOrder>(&self) -> LexedSubheader<E> {
+        LexedSubheader {
+            parameters: self.parameters,
+            exponent_y: self.exponent_y,
+            index_number: U16::new(self.index_number),
+            z: F32::new(self.z),
+            next_z: F32::new(self.next_z),
+            noise: F32::new(self.noise),
+            number_points: U32::new(self.number_points),
+            scan: U32::new(self.scan),
+            w_level: F32::new(self.w_level),
+            reserved: [0; 4],
+        }
+    }
+
+    pub fn flags(&self) -> SubFlagParameters {
+        self.parameters
+    }
+
+    /// The exponent of the y-values in the subfile, or `None` if the subfile holds float data
+    pub fn exponent_y(&self) -> Option<i8> {
+        (self.exponent_y != -128).then_some(self.exponent_y)
+    }
+
+    /// The index of the subfile, where 0 refers to the first
+    pub fn index_number(&self) -> u16 {
+        self.index_number
+    }
+
+    /// The z-axis coordinate of the subfile
+    pub fn z(&self) -> f32 {
+        self.z
+    }
+
+    /// The z-axis coordinate of the next subfile
+    pub fn next_z(&self) -> f32 {
+        self.next_z
+    }
+
+    /// The peak pick noise level
+    ///
+    /// The specification only defines the noise when the high byte of the stored value is
+    /// non-zero, otherwise this returns `None`.
+    pub fn noise(&self) -> Option<f32> {
+        (self.noise.to_bits() >> 24 != 0).then_some(self.noise)
+    }
+
+    /// The number of points in the subfile. This is only set for XYXY files
+    pub fn number_points(&self) -> u32 {
+        self.number_points
+    }
+
+    /// The number of co-added scans, where 0 means the number of scans is unknown
+    pub fn scans(&self) -> u32 {
+        self.scan
+    }
+
+    /// The w-axis value of the subfile, which is only meaningful if the header has w-planes
+    pub fn w_level(&self) -> f32 {
+        self.w_level
+    }
+}
+
+impl ::std::fmt::Display for Subheader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "index: {}", self.index_number)?;
+        writeln!(f, "flags: {}", self.parameters)?;
+        match self.exponent_y() {
+            Some(exponent) => writeln!(f, "y exponent: {exponent}")?,
+            None => writeln!(f, "y exponent: float data")?,
+        }
+        writeln!(f, "z: {}", self.z)?;
+        writeln!(f, "next z: {}", self.next_z)?;
+        match self.noise() {
+            Some(noise) => writeln!(f, "noise: {noise}")?,
+            None => writeln!(f, "noise: not set")?,
+        }
+        writeln!(f, "points: {}", self.number_points)?;
+        writeln!(f, "scans: {}", self.scan)?;
+        writeln!(f, "w level: {}", self.w_level)?;
+        Ok(())
+    }
+}
+
 impl<E: ByteOrder> LexedSubheader<E> {
     pub(crate) fn number_of_points(&self) -> usize {
         let number_points: u32 = self.number_points.into();
@@ -133,11 +282,59 @@ impl<E: ByteOrder> LexedSubheader<E> {
     }
 }
 
+impl<E: ByteOrder> Inspect for LexedSubheader<E> {
+    fn inspect(&self) -> Vec<FieldSpan> {
+        vec![
+            field_span!(
+                self,
+                parameters,
+                format!("{:#010b} ({})", self.parameters.0, self.parameters)
+            ),
+            field_span!(
+                self,
+                exponent_y,
+                if self.float_data_expected() {
+                    "0x80 (float data)".to_owned()
+                } else {
+                    self.exponent_y.to_string()
+                }
+            ),
+            field_span!(self, index_number, self.index_number.get()),
+            field_span!(self, z, self.z.get()),
+            field_span!(self, next_z, self.next_z.get()),
+            field_span!(
+                self,
+                noise,
+                if self.noise.get().to_bits() >> 24 != 0 {
+                    self.noise.get().to_string()
+                } else {
+                    "not set".to_owned()
+                }
+            ),
+            field_span!(self, number_points, self.number_points.get()),
+            field_span!(self, scan, self.scan.get()),
+            field_span!(self, w_level, self.w_level.get()),
+            field_span!(
+                self,
+                reserved,
+                if self.reserved.iter().all(|&x| x == 0) {
+                    "zeroed"
+                } else {
+                    "NON-ZERO"
+                }
+            ),
+        ]
+    }
+}
+
 #[cfg(test)]
 mod test {
     use zerocopy::{LittleEndian, TryFromBytes};
 
-    use crate::header::subheader::GuardedLexedSubheader;
+    use crate::{
+        header::subheader::{GuardedLexedSubheader, LexedSubheader},
+        parse::TryParse,
+    };
 
     #[test]
     fn water_refractive_index_subheader_parses_correctly() {
@@ -162,4 +359,33 @@ mod test {
         let result = parsed.try_into_inner();
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn subheader_flags_and_noise_are_decoded() {
+        let mut data = [0u8; 32];
+        // SUBCHGD and SUBMODF
+        data[0] = 0b1000_0001;
+        // float data
+        data[1] = 0x80;
+        data[12..16].copy_from_slice(&2.5f32.to_le_bytes());
+        data[20..24].copy_from_slice(&16u32.to_le_bytes());
+
+        let lexed = LexedSubheader::<LittleEndian>::try_ref_from_bytes(&data[..]).unwrap();
+        let subheader = lexed.try_parse().unwrap();
+
+        let flags = subheader.flags();
+        assert!(flags.changed());
+        assert!(!flags.no_peak_table());
+        assert!(flags.modified_by_arithmetic());
+        assert_eq!(flags.to_string(), "changed, modified by arithmetic");
+
+        assert_eq!(subheader.exponent_y(), None);
+        assert_eq!(subheader.noise(), Some(2.5));
+        assert_eq!(subheader.scans(), 16);
+
+        // A noise value with a zero high byte is not set
+        data[12..16].copy_from_slice(&[1, 0, 0, 0]);
+        let lexed = LexedSubheader::<LittleEndian>::try_ref_from_bytes(&data[..]).unwrap();
+        assert_eq!(lexed.try_parse().unwrap().noise(), None);
+    }
 }
@@ -0,0 +1,714 @@
+//! An annotated walk over the raw structure of an SPC file.
+//!
+//! The [`Inspection`] produced here records where each of the lexed structures lives in the file,
+//! every field they contain with its offset, raw bytes and decoded value, and any regions of the
+//! file which the lexer did not consume. It is intended for debugging malformed files, where the
+//! strict lexer in [`crate::lex`] would give up.
+
+use std::fmt;
+
+use zerocopy::{BigEndian, ByteOrder, LittleEndian, TryFromBytes};
+
+use crate::{
+    block::{LexedDirectory, LexedSubfile, YMode},
+    detect::{detect_galactic, FileKind},
+    header::{DataShape, LexedHeader, LexedSubheader},
+    lex::{byte_len, SPCReader},
+    logblock::LexedLogHeader,
+    parse::{Parse, TryParse},
+};
+
+/// Implemented by lexed structures which can describe their own fields.
+pub(crate) trait Inspect {
+    /// The fields of the structure, with offsets relative to the start of the structure.
+    fn inspect(&self) -> Vec<FieldSpan>;
+}
+
+/// A single field in a lexed structure, located relative to the start of the structure.
+pub(crate) struct FieldSpan {
+    name: &'static str,
+    offset: usize,
+    len: usize,
+    value: String,
+}
+
+impl FieldSpan {
+    /// Locate `field` within `base`, which must be the structure containing it.
+    pub(crate) fn new<S, F>(base: &S, field: &F, name: &'static str, value: impl ToString) -> Self {
+        let offset = (field as *const F as usize) - (base as *const S as usize);
+        debug_assert!(offset + size_of::<F>() <= size_of_val(base));
+        Self {
+            name,
+            offset,
+            len: size_of::<F>(),
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Builds a [`FieldSpan`] for the named field of a lexed structure
+macro_rules! field_span {
+    ($base:expr, $name:ident, $value:expr) => {
+        $crate::inspect::FieldSpan::new($base, &$base.$name, stringify!($name), $value)
+    };
+}
+pub(crate) use field_span;
+
+/// Describes the result of a fallible decode, used for fields which may contain invalid values.
+pub(crate) fn describe<T: fmt::Debug, E: fmt::Display>(result: Result<T, E>) -> String {
+    match result {
+        Ok(value) => format!("{value:?}"),
+        Err(e) => format!("invalid: {e}"),
+    }
+}
+
+/// Renders a null-terminated text field without assuming it is valid UTF-8.
+pub(crate) fn lossy_text(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    format!("{:?}", String::from_utf8_lossy(&bytes[..end]))
+}
+
+fn preview(values: impl ExactSizeIterator<Item = f64>) -> String {
+    const SHOWN: usize = 4;
+    let len = values.len();
+    let shown = values
+        .take(SHOWN)
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if len > SHOWN {
+        format!("[{shown}, …] ({len} values)")
+    } else {
+        format!("[{shown}] ({len} values)")
+    }
+}
+
+/// The structure a [`Region`] of the file was identified as
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    /// The main file header
+    Header,
+    /// An array of x-values. This is shared by all subfiles unless the file is XYXY
+    XData { subfile: Option<usize> },
+    /// The subheader preceding the y-values of a subfile
+    Subheader { subfile: usize },
+    /// The y-values of a subfile
+    YData { subfile: usize },
+    /// The directory entry for a subfile in an XYXY file
+    Directory { subfile: usize },
+    /// The header of the log block
+    LogHeader,
+    /// The binary area of the log block
+    LogData,
+    /// The text area of the log block
+    LogText,
+    /// Bytes which the lexer did not consume
+    Unconsumed,
+}
+
+impl RegionKind {
+    /// The subfile this region belongs to, if it belongs to a single subfile
+    pub fn subfile(&self) -> Option<usize> {
+        match self {
+            Self::XData { subfile } => *subfile,
+            Self::Subheader { subfile } | Self::YData { subfile } | Self::Directory { subfile } => {
+                Some(*subfile)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Header => write!(f, "header"),
+            Self::XData { subfile: None } => write!(f, "x-data"),
+            Self::XData {
+                subfile: Some(subfile),
+            } => write!(f, "x-data {subfile}"),
+            Self::Subheader { subfile } => write!(f, "subheader {subfile}"),
+            Self::YData { subfile } => write!(f, "y-data {subfile}"),
+            Self::Directory { subfile } => write!(f, "directory entry {subfile}"),
+            Self::LogHeader => write!(f, "log header"),
+            Self::LogData => write!(f, "log binary data"),
+            Self::LogText => write!(f, "log text"),
+            Self::Unconsumed => write!(f, "UNCONSUMED"),
+        }
+    }
+}
+
+/// A field within a [`Region`]
+#[derive(Clone, Debug)]
+pub struct Field {
+    name: &'static str,
+    offset: usize,
+    raw: Vec<u8>,
+    value: String,
+}
+
+impl Field {
+    /// The name of the field
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    /// The absolute offset of the field in the file
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The raw bytes of the field
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The decoded value of the field
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// A contiguous region of the file, identified as one of the lexed structures
+#[derive(Clone, Debug)]
+pub struct Region {
+    kind: RegionKind,
+    offset: usize,
+    len: usize,
+    fields: Vec<Field>,
+}
+
+impl Region {
+    /// What the region was identified as
+    pub fn kind(&self) -> RegionKind {
+        self.kind
+    }
+
+    /// The absolute offset of the region in the file
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// The length of the region in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the region is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The fields contained in the region
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+}
+
+/// An annotated map of an SPC file
+#[derive(Clone, Debug)]
+pub struct Inspection {
+    len: usize,
+    regions: Vec<Region>,
+    error: Option<String>,
+}
+
+impl Inspection {
+    /// All regions of the file, ordered by offset
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Regions of the file which were not consumed by the lexer
+    pub fn unconsumed(&self) -> impl Iterator<Item = &Region> {
+        self.regions
+            .iter()
+            .filter(|region| region.kind == RegionKind::Unconsumed)
+    }
+
+    /// The reason the walk stopped early, if it did
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Restrict the inspection to the regions belonging to a single subfile
+    pub fn subfile(&self, index: usize) -> Self {
+        Self {
+            len: self.len,
+            regions: self
+                .regions
+                .iter()
+                .filter(|region| region.kind.subfile() == Some(index))
+                .cloned()
+                .collect(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "file length: {} bytes", self.len)?;
+        for region in &self.regions {
+            writeln!(
+                f,
+                "{:#010x}..{:#010x}  {} ({} bytes)",
+                region.offset,
+                region.offset + region.len,
+                region.kind,
+                region.len
+            )?;
+            for field in &region.fields {
+                let mut raw = field
+                    .raw
+                    .iter()
+                    .take(16)
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if field.raw.len() > 16 {
+                    raw.push_str(" …");
+                }
+                writeln!(
+                    f,
+                    "  {:#010x}  {:<36} {:<50} {}",
+                    field.offset, field.name, raw, field.value
+                )?;
+            }
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "walk stopped early: {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Walk an SPC file, annotating every structure the lexer recognises.
+///
+/// Unlike [`crate::parse`], this does not require the file to be well formed: the walk stops at
+/// the first structure which cannot be read, and everything after it is marked as unconsumed.
+pub fn inspect(source: &[u8]) -> miette::Result<Inspection> {
+    match detect_galactic(source)? {
+        FileKind::NewBigEndian => {
+            Ok(Walker::new(SPCReader::<BigEndian>::big_endian(source), source).walk())
+        }
+        _ => Ok(Walker::new(SPCReader::<LittleEndian>::little_endian(source), source).walk()),
+    }
+}
+
+struct Walker<'data, E: ByteOrder> {
+    reader: SPCReader<'data, E>,
+    source: &'data [u8],
+    regions: Vec<Region>,
+}
+
+impl<'data, E: ByteOrder + 'data> Walker<'data, E> {
+    fn new(reader: SPCReader<'data, E>, source: &'data [u8]) -> Self {
+        Self {
+            reader,
+            source,
+            regions: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, kind: RegionKind, offset: usize, len: usize, fields: Vec<FieldSpan>) {
+        let fields = fields
+            .into_iter()
+            .map(|span| Field {
+                name: span.name,
+                offset: offset + span.offset,
+                raw: self.source[offset + span.offset..][..span.len].to_vec(),
+                value: span.value,
+            })
+            .collect();
+        self.regions.push(Region {
+            kind,
+            offset,
+            len,
+            fields,
+        });
+    }
+
+    fn walk(mut self) -> Inspection {
+        let error = self.walk_structures().err().map(|e| e.to_string());
+
+        // Everything the walk did not claim is marked as unconsumed
+        self.regions.sort_by_key(|region| region.offset);
+        let mut gaps = Vec::new();
+        let mut position = 0;
+        for region in &self.regions {
+            if region.offset > position {
+                gaps.push((position, region.offset - position));
+            }
+            position = position.max(region.offset + region.len);
+        }
+        if position < self.source.len() {
+            gaps.push((position, self.source.len() - position));
+        }
+        for (offset, len) in gaps {
+            self.regions.push(Region {
+                kind: RegionKind::Unconsumed,
+                offset,
+                len,
+                fields: vec![Field {
+                    name: "bytes",
+                    offset,
+                    raw: self.source[offset..offset + len].to_vec(),
+                    value: lossy_text(&self.source[offset..offset + len]),
+                }],
+            });
+        }
+        self.regions.sort_by_key(|region| region.offset);
+
+        Inspection {
+            len: self.source.len(),
+            regions: self.regions,
+            error,
+        }
+    }
+
+    fn walk_structures(&mut self) -> miette::Result<()> {
+        let header = self.reader.lex_header()?;
+        let fields = match &header {
+            LexedHeader::Old(header) => header.inspect(),
+            LexedHeader::New(header) => header.inspect(),
+        };
+        self.push(RegionKind::Header, 0, self.reader.byte, fields);
+
+        let exponent = header.exponent() as i32;
+        let y_mode = header.y_mode();
+        let number_points = header.number_points();
+
+        match header.data_shape()? {
+            DataShape::Y => self.walk_subfile(0, &y_mode, number_points, exponent)?,
+            DataShape::XY => {
+                self.walk_x(None, number_points)?;
+                self.walk_subfile(0, &y_mode, number_points, exponent)?;
+            }
+            DataShape::YY => self.walk_subfiles(&header, &y_mode, exponent)?,
+            DataShape::XYY => {
+                self.walk_x(None, number_points)?;
+                self.walk_subfiles(&header, &y_mode, exponent)?;
+            }
+            DataShape::XYXY => {
+                let number_of_subfiles = header.number_of_subfiles().unwrap_or_default();
+                for index in 0..number_of_subfiles {
+                    let offset = self.reader.byte;
+                    let subheader = self.reader.lex_subheader()?;
+                    self.push(
+                        RegionKind::Subheader { subfile: index },
+                        offset,
+                        32,
+                        subheader.inspect(),
+                    );
+                    self.walk_x(Some(index), subheader.number_of_points())?;
+                    self.walk_y(
+                        index,
+                        subheader,
+                        &y_mode,
+                        subheader.number_of_points(),
+                        exponent,
+                    )?;
+                }
+
+                // A directory may follow the subfiles, either before the log or at the end of the
+                // file
+                let directory_len = byte_len(number_of_subfiles, size_of::<LexedDirectory<E>>())?;
+                let directory_present = match header.log_offset() {
+                    Some(log_offset) => log_offset >= self.reader.byte + directory_len,
+                    None => self.reader.remaining_bytes() == directory_len,
+                } && directory_len != 0;
+                if directory_present {
+                    for index in 0..number_of_subfiles {
+                        let offset = self.reader.byte;
+                        let source = self
+                            .reader
+                            .read_byte_slice(size_of::<LexedDirectory<E>>())?;
+                        let entry = LexedDirectory::<E>::try_ref_from_bytes(source)
+                            .map_err(|e| miette::miette!("invalid directory entry: {e}"))?;
+                        self.push(
+                            RegionKind::Directory { subfile: index },
+                            offset,
+                            source.len(),
+                            entry.inspect(),
+                        );
+                    }
+                }
+            }
+        }
+
+        if let Some(log_offset) = header.log_offset() {
+            self.walk_log(log_offset)?;
+        }
+        Ok(())
+    }
+
+    fn walk_x(&mut self, subfile: Option<usize>, number_points: usize) -> miette::Result<()> {
+        let offset = self.reader.byte;
+        let x = self.reader.lex_x(number_points)?.parse();
+        let len = self.reader.byte - offset;
+        self.regions.push(Region {
+            kind: RegionKind::XData { subfile },
+            offset,
+            len,
+            fields: vec![Field {
+                name: "x",
+                offset,
+                raw: self.source[offset..offset + len].to_vec(),
+                value: preview(x.iter().map(|x| *x as f64).collect::<Vec<_>>().into_iter()),
+            }],
+        });
+        Ok(())
+    }
+
+    fn walk_subfile(
+        &mut self,
+        index: usize,
+        y_mode: &YMode,
+        number_points: usize,
+        exponent: i32,
+    ) -> miette::Result<()> {
+        let offset = self.reader.byte;
+        let subheader = self.reader.lex_subheader()?;
+        self.push(
+            RegionKind::Subheader { subfile: index },
+            offset,
+            32,
+            subheader.inspect(),
+        );
+        // The number of points is normally stored in the header, but may be overridden in the
+        // subheader
+        let number_points = match subheader.number_of_points() {
+            0 => number_points,
+            n => n,
+        };
+        self.walk_y(index, subheader, y_mode, number_points, exponent)
+    }
+
+    fn walk_subfiles(
+        &mut self,
+        header: &LexedHeader<'data, E>,
+        y_mode: &YMode,
+        exponent: i32,
+    ) -> miette::Result<()> {
+        let number_points = header.number_points();
+        match header.number_of_subfiles() {
+            Some(number_of_subfiles) => {
+                for index in 0..number_of_subfiles {
+                    self.walk_subfile(index, y_mode, number_points, exponent)?;
+                }
+            }
+            // Old-format multifiles do not record the number of subfiles, so read until there is
+            // not enough data left for another
+            None => {
+                let mut index = 0;
+                let subfile_len =
+                    byte_len(number_points, y_mode.bytes_per_point())?.saturating_add(32);
+                while self.reader.remaining_bytes() >= subfile_len {
+                    self.walk_subfile(index, y_mode, number_points, exponent)?;
+                    index += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn walk_y(
+        &mut self,
+        index: usize,
+        subheader: &'data LexedSubheader<E>,
+        y_mode: &YMode,
+        number_points: usize,
+        exponent: i32,
+    ) -> miette::Result<()> {
+        let mode = y_mode
+            .for_subfile(subheader.float_data_expected())
+            .ok_or_else(|| {
+                miette::miette!("subfile {index} does not match the data type in the header")
+            })?;
+        let offset = self.reader.byte;
+        let data = self
+            .reader
+            .read_byte_slice(byte_len(number_points, mode.bytes_per_point())?)?;
+        let subfile = LexedSubfile::new(subheader, data, mode)?;
+        let value = match subfile.try_parse() {
+            Ok(subfile) => preview(subfile.data.decode(exponent).into_iter()),
+            Err(e) => format!("invalid: {e}"),
+        };
+        self.regions.push(Region {
+            kind: RegionKind::YData { subfile: index },
+            offset,
+            len: data.len(),
+            fields: vec![Field {
+                name: "y",
+                offset,
+                raw: data.to_vec(),
+                value,
+            }],
+        });
+        Ok(())
+    }
+
+    fn walk_log(&mut self, log_offset: usize) -> miette::Result<()> {
+        if log_offset < self.reader.byte {
+            miette::bail!(
+                "log offset {log_offset:#x} points inside the data block, which ends at {:#x}",
+                self.reader.byte
+            );
+        }
+        let source = self
+            .source
+            .get(log_offset..log_offset + size_of::<LexedLogHeader<E>>())
+            .ok_or_else(|| {
+                miette::miette!("log offset {log_offset:#x} is past the end of the file")
+            })?;
+        let log_header = LexedLogHeader::<E>::try_ref_from_bytes(source)
+            .map_err(|e| miette::miette!("invalid log header: {e}"))?;
+        self.push(
+            RegionKind::LogHeader,
+            log_offset,
+            source.len(),
+            log_header.inspect(),
+        );
+
+        let data_offset = log_offset + source.len();
+        let data_len = log_header
+            .binary_size()
+            .min(self.source.len().saturating_sub(data_offset));
+        if data_len > 0 {
+            self.regions.push(Region {
+                kind: RegionKind::LogData,
+                offset: data_offset,
+                len: data_len,
+                fields: vec![Field {
+                    name: "data",
+                    offset: data_offset,
+                    raw: self.source[data_offset..data_offset + data_len].to_vec(),
+                    value: format!("{data_len} bytes"),
+                }],
+            });
+        }
+
+        let text_offset = log_offset + log_header.text_offset();
+        if let Some(text) = self.source.get(text_offset..) {
+            if !text.is_empty() {
+                self.regions.push(Region {
+                    kind: RegionKind::LogText,
+                    offset: text_offset,
+                    len: text.len(),
+                    fields: vec![Field {
+                        name: "text",
+                        offset: text_offset,
+                        raw: text.to_vec(),
+                        value: lossy_text(text),
+                    }],
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{inspect, RegionKind};
+
+    // A new-format little-endian Y file holding four float points, followed by three bytes the
+    // lexer knows nothing about
+    fn y_file_with_trailing_bytes() -> Vec<u8> {
+        let mut source = vec![0; 512];
+        source[1] = 0x4b;
+        source[3] = 0x80;
+        source[4..8].copy_from_slice(&4u32.to_le_bytes());
+        source[8..16].copy_from_slice(&100f64.to_le_bytes());
+        source[16..24].copy_from_slice(&400f64.to_le_bytes());
+
+        let mut subheader = [0; 32];
+        subheader[1] = 0x80;
+        source.extend_from_slice(&subheader);
+
+        for y in [1f32, 2., 3., 4.] {
+            source.extend_from_slice(&y.to_le_bytes());
+        }
+        source.extend_from_slice(&[0xde, 0xad, 0xbf]);
+        source
+    }
+
+    #[test]
+    fn inspection_annotates_fields_and_unconsumed_bytes() {
+        let source = y_file_with_trailing_bytes();
+        let inspection = inspect(&source).unwrap();
+
+        let kinds = inspection
+            .regions()
+            .iter()
+            .map(|region| region.kind())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                RegionKind::Header,
+                RegionKind::Subheader { subfile: 0 },
+                RegionKind::YData { subfile: 0 },
+                RegionKind::Unconsumed,
+            ]
+        );
+
+        let number_points = inspection.regions()[0]
+            .fields()
+            .iter()
+            .find(|field| field.name() == "number_points")
+            .unwrap();
+        assert_eq!(number_points.offset(), 4);
+        assert_eq!(number_points.raw(), [4, 0, 0, 0]);
+        assert_eq!(number_points.value(), "4");
+
+        let unconsumed = inspection.unconsumed().collect::<Vec<_>>();
+        assert_eq!(unconsumed.len(), 1);
+        assert_eq!(unconsumed[0].offset(), 512 + 32 + 16);
+        assert_eq!(unconsumed[0].len(), 3);
+
+        let subfile = inspection.subfile(0);
+        assert_eq!(subfile.regions().len(), 2);
+        assert_eq!(
+            subfile.regions()[1].fields()[0].value(),
+            "[1, 2, 3, 4] (4 values)"
+        );
+    }
+
+    #[test]
+    fn truncated_file_is_reported_rather_than_panicking() {
+        let source = y_file_with_trailing_bytes();
+        let inspection = inspect(&source[..520]).unwrap();
+
+        assert!(inspection.error().is_some());
+        assert_eq!(inspection.regions()[0].kind(), RegionKind::Header);
+        assert_eq!(inspection.regions()[1].kind(), RegionKind::Unconsumed);
+        assert_eq!(inspection.regions()[1].len(), 8);
+    }
+
+    #[test]
+    fn separate_x_values_without_multifile_are_reported_rather_than_panicking() {
+        let mut source = vec![0; 600];
+        // TXVALS and TXYXYS without TMULTI
+        source[0] = 0xc0;
+        source[1] = 0x4b;
+        let inspection = inspect(&source).unwrap();
+
+        assert!(inspection.error().is_some());
+        assert_eq!(inspection.regions()[0].kind(), RegionKind::Header);
+        assert_eq!(inspection.regions()[1].kind(), RegionKind::Unconsumed);
+    }
+
+    #[test]
+    fn overflowing_point_count_is_reported_rather_than_panicking() {
+        let mut source = vec![0; 600];
+        // An old-format XY file, whose number of points is stored as a float
+        source[0] = 0x80;
+        source[1] = 0x4d;
+        source[4..8].copy_from_slice(&1e30f32.to_le_bytes());
+        let inspection = inspect(&source).unwrap();
+
+        assert!(inspection.error().is_some());
+    }
+}
@@ -1,34 +1,51 @@
 use zerocopy::{BigEndian, ByteOrder, Immutable, KnownLayout, LittleEndian, TryFromBytes};
 
 use crate::{
-    block::{LexedBlock, LexedDirectory, LexedSubfile, LexedXData, YMode},
+    block::{validate_directory, LexedBlock, LexedDirectory, LexedSubfile, LexedXData, YMode},
     header::{
         DataShape, FlagParameters, LexedHeader, LexedNewFormatHeader, LexedOldFormatHeader,
         LexedSubheader, Precision,
     },
     logblock::{LexedLogBlock, LexedLogHeader},
-    parse::{ParseError, ParsedSPC, TryParse},
+    parse::{Parse, ParseError, ParseOptions, ParsedSPC, TryParse, TryParseWith},
 };
 
+type XYXYSubfiles<'data, E> = (
+    Vec<(LexedXData<'data, E>, LexedSubfile<'data, E>)>,
+    Vec<(usize, usize)>,
+);
+
 #[derive(Clone, Debug)]
 pub struct LexedSPC<'data, E: ByteOrder> {
-    header: LexedHeader<'data, E>,
-    block: LexedBlock<'data, E>,
-    log: Option<LexedLogBlock<'data, E>>,
+    pub(crate) header: LexedHeader<'data, E>,
+    pub(crate) block: LexedBlock<'data, E>,
+    pub(crate) log: Option<LexedLogBlock<'data, E>>,
 }
 
-impl<E: ByteOrder> TryParse for LexedSPC<'_, E> {
+impl<E: ByteOrder> TryParseWith for LexedSPC<'_, E> {
     type Parsed = ParsedSPC;
     type Error = ParseError;
-    fn try_parse(&self) -> Result<Self::Parsed, Self::Error> {
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error> {
         Ok(ParsedSPC {
-            header: self.header.try_parse()?,
+            header: self.header.try_parse_with(options)?,
             block: self.block.try_parse()?,
-            log: self.log.as_ref().map(|log| log.try_parse()).transpose()?,
+            log: self
+                .log
+                .as_ref()
+                .map(|log| log.try_parse_with(options))
+                .transpose()?,
         })
     }
 }
 
+// The length in bytes of `count` values of `size` bytes, where the count comes from a field of the
+// file and so may be too large to address
+pub(crate) fn byte_len(count: usize, size: usize) -> miette::Result<usize> {
+    count
+        .checked_mul(size)
+        .ok_or_else(|| miette::miette!("{count} values of {size} bytes are too large to read"))
+}
+
 #[derive(Copy, Clone, Debug)]
 pub(crate) enum Version {
     Old,
@@ -79,15 +96,26 @@ impl<'data> SPCReader<'data, LittleEndian> {
 }
 
 impl<'data, E: ByteOrder> SPCReader<'data, E> {
-    fn is_exhausted(&self) -> bool {
+    // A reader over part of a file, such as a single subfile read from a stream
+    pub(crate) fn fragment(input: &'data [u8], version: Version) -> Self {
+        Self {
+            whole: input,
+            rest: input,
+            byte: 0,
+            version,
+            byte_order: std::marker::PhantomData,
+        }
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
         self.rest.is_empty()
     }
 
-    fn remaining_bytes(&self) -> usize {
+    pub(crate) fn remaining_bytes(&self) -> usize {
         self.rest.len()
     }
 
-    fn read_byte_slice(&mut self, len: usize) -> miette::Result<&'data [u8]> {
+    pub(crate) fn read_byte_slice(&mut self, len: usize) -> miette::Result<&'data [u8]> {
         if len > self.rest.len() {
             return Err(miette::miette!(
                 "Not enough bytes left in the buffer. requested {}, remaining {}",
@@ -101,7 +129,7 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         Ok(slice)
     }
 
-    fn lex_header(&mut self) -> miette::Result<LexedHeader<'data, E>> {
+    pub(crate) fn lex_header(&mut self) -> miette::Result<LexedHeader<'data, E>> {
         let header_len = match self.version {
             Version::Old => 224,
             Version::New => 512,
@@ -123,7 +151,7 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         Ok(header)
     }
 
-    fn lex_subheader(&mut self) -> miette::Result<&'data LexedSubheader<E>> {
+    pub(crate) fn lex_subheader(&mut self) -> miette::Result<&'data LexedSubheader<E>> {
         let source = self.read_byte_slice(32)?;
         Ok(LexedSubheader::try_ref_from_bytes(source).unwrap())
     }
@@ -131,12 +159,15 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
     // Lex X-data from the input
     //
     // X-data is always stored as a contiguous list of 32-bit floating point values.
-    fn lex_x(&mut self, num_points: usize) -> miette::Result<LexedXData<'data, E>> {
-        let data = self.read_byte_slice(num_points * Precision::ThirtyTwoBit.bytes_per_point())?;
+    pub(crate) fn lex_x(&mut self, num_points: usize) -> miette::Result<LexedXData<'data, E>> {
+        let data = self.read_byte_slice(byte_len(
+            num_points,
+            Precision::ThirtyTwoBit.bytes_per_point(),
+        )?)?;
         LexedXData::new(data)
     }
 
-    fn lex_subfile(
+    pub(crate) fn lex_subfile(
         &mut self,
         y_mode: YMode,
         num_points: usize,
@@ -145,14 +176,12 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         let subheader = self.lex_subheader()?;
 
         // Check to see if the subfile overrides the header data type
-        let float_expected_from_subheader = subheader.float_data_expected();
-        let mode = match (y_mode, float_expected_from_subheader) {
-            (YMode::SixteenBitInt, false) => YMode::SixteenBitInt,
-            (YMode::ThirtyTwoBitInt(m), false) => YMode::ThirtyTwoBitInt(m),
-            (_, true) => YMode::IEEEFloat,
-            (_, false) => panic!("Inconsistent data types expected"),
-        };
-        let data = self.read_byte_slice(num_points * mode.bytes_per_point())?;
+        let mode = y_mode
+            .for_subfile(subheader.float_data_expected())
+            .ok_or_else(|| {
+                miette::miette!("the header declares float data, but a subheader declares integers")
+            })?;
+        let data = self.read_byte_slice(byte_len(num_points, mode.bytes_per_point())?)?;
         LexedSubfile::new(subheader, data, mode)
     }
 
@@ -160,59 +189,97 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         &mut self,
         header: &LexedHeader<'data, E>,
     ) -> miette::Result<Vec<LexedSubfile<'data, E>>> {
+        let mut subfiles = Vec::new();
         // A new-style header stores the number of subfiles in the `fnsub` field, if this
         // is provided we just use it.
-        let num_subfiles = if let Some(num_subfiles) = header.number_of_subfiles() {
-            num_subfiles
-        // If not we have to try and work out the number of subfiles present in the data.
+        if let Some(num_subfiles) = header.number_of_subfiles() {
+            for _ in 0..num_subfiles {
+                let subfile = self.lex_subfile(header.y_mode(), header.number_points())?;
+                subfiles.push(subfile);
+            }
+        // If not the file is old-style, which has no log, so the subfiles fill the rest of it
         } else {
-            todo!()
-        };
-
-        let mut subfiles = Vec::new();
-        for _ in 0..num_subfiles {
-            let subfile = self.lex_subfile(header.y_mode(), header.number_points())?;
-            subfiles.push(subfile);
+            while !self.is_exhausted() {
+                let subfile = self.lex_subfile(header.y_mode(), header.number_points())?;
+                subfiles.push(subfile);
+            }
         }
         Ok(subfiles)
     }
 
+    // Lex the subfiles of an XYXY file, returning the (offset, size) in bytes of each alongside
+    // the lexed data so they can be checked against the directory
     fn lex_xyxy_blocks(
         &mut self,
         header: &LexedHeader<'data, E>,
-    ) -> miette::Result<Vec<(LexedXData<'data, E>, LexedSubfile<'data, E>)>> {
-        // Only new style headers can be XYXY format, and the number_of_subfiles method always
-        // returns Some for a new style header. This means we can unwrap safely.
-        let num_subfiles = header.number_of_subfiles().unwrap();
+    ) -> miette::Result<XYXYSubfiles<'data, E>> {
+        // Only new style headers can be XYXY format, as the old style header does not record the
+        // number of subfiles
+        let num_subfiles = header.number_of_subfiles().ok_or_else(|| {
+            miette::miette!("an old-format header cannot describe separate x-values per subfile")
+        })?;
 
         let mut subfiles = Vec::new();
+        let mut extents = Vec::new();
         for _ in 0..num_subfiles {
-            let subheader = self.lex_subheader()?;
-            let x_data = self.lex_x(subheader.number_of_points())?;
+            let offset = self.byte;
+            subfiles.push(self.lex_xyxy_subfile(header.y_mode())?);
+            extents.push((offset, self.byte - offset));
+        }
+        Ok((subfiles, extents))
+    }
 
-            let header_mode = header.y_mode();
-            let float_expected_from_subheader = subheader.float_data_expected();
+    // Lex a single subfile of an XYXY file, which holds its own subheader, x-data and y-data
+    pub(crate) fn lex_xyxy_subfile(
+        &mut self,
+        y_mode: YMode,
+    ) -> miette::Result<(LexedXData<'data, E>, LexedSubfile<'data, E>)> {
+        let subheader = self.lex_subheader()?;
+        let x_data = self.lex_x(subheader.number_of_points())?;
 
-            let mode = match (header_mode, float_expected_from_subheader) {
-                (YMode::SixteenBitInt, false) => YMode::SixteenBitInt,
-                (YMode::ThirtyTwoBitInt(m), false) => YMode::ThirtyTwoBitInt(m),
-                (_, true) => YMode::IEEEFloat,
-                _ => panic!("Invalid mode for XYXY data"),
-            };
+        let mode = y_mode
+            .for_subfile(subheader.float_data_expected())
+            .ok_or_else(|| {
+                miette::miette!("the header declares float data, but a subheader declares integers")
+            })?;
 
-            let data =
-                self.read_byte_slice(subheader.number_of_points() * mode.bytes_per_point())?;
+        let data = self.read_byte_slice(byte_len(
+            subheader.number_of_points(),
+            mode.bytes_per_point(),
+        )?)?;
 
-            subfiles.push((x_data, LexedSubfile::new(subheader, data, mode)?));
-        }
-        Ok(subfiles)
+        Ok((x_data, LexedSubfile::new(subheader, data, mode)?))
+    }
+
+    // Lex the directory following the subfiles of an XYXY file, checking each entry describes the
+    // subfile it belongs to
+    fn lex_directory(
+        &mut self,
+        extents: &[(usize, usize)],
+    ) -> miette::Result<Vec<&'data LexedDirectory<E>>> {
+        let directory = (0..extents.len())
+            .map(|_| self.read_byte_slice(size_of::<LexedDirectory<E>>()))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .map(|source| LexedDirectory::try_ref_from_bytes(source))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| miette::miette!("invalid directory entry: {e}"))?;
+
+        validate_directory(
+            &directory
+                .iter()
+                .map(|entry| entry.parse())
+                .collect::<Vec<_>>(),
+            extents,
+        )?;
+        Ok(directory)
     }
 
     fn lex_block(
         &mut self,
         header: &LexedHeader<'data, E>,
     ) -> miette::Result<LexedBlock<'data, E>> {
-        let block = match header.data_shape() {
+        let block = match header.data_shape()? {
             // If the DataShape is Y, after the header the file consists of a single subfile
             // containing the y-data points
             DataShape::Y => {
@@ -247,7 +314,8 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
             // consisting of a header, followed by the x-data points and the y-data points
             DataShape::XYXY => {
                 log::info!("lexing XYXY block");
-                let data = self.lex_xyxy_blocks(header)?;
+                let (data, extents) = self.lex_xyxy_blocks(header)?;
+                let directory_len = data.len() * size_of::<LexedDirectory<E>>();
 
                 // XYXY data can be optionally followed by a directory structure, containing
                 // information about the individual subfiles
@@ -259,34 +327,19 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
                     None if self.is_exhausted() => None,
                     // If there is no log, and the buffer is not exhausted then it must contain the
                     // directory data
-                    None => {
-                        assert_eq!(self.remaining_bytes(), data.len() * 12);
-                        Some(
-                            (0..data.len())
-                                .map(|_| self.read_byte_slice(12))
-                                .collect::<Result<Vec<_>, _>>()?
-                                .into_iter()
-                                .map(|source| LexedDirectory::try_ref_from_bytes(source))
-                                .collect::<Result<Vec<_>, _>>()
-                                .unwrap(),
-                        )
+                    None if self.remaining_bytes() == directory_len => {
+                        Some(self.lex_directory(&extents)?)
                     }
                     // If there is a log, and the buffer is not at the log position the gap must
                     // contain the directory data
-                    Some(n) => {
-                        let bytes_to_log = n - self.byte;
-                        assert_eq!(bytes_to_log, data.len() * 12);
-
-                        Some(
-                            (0..data.len())
-                                .map(|_| self.read_byte_slice(12))
-                                .collect::<Result<Vec<_>, _>>()?
-                                .into_iter()
-                                .map(|source| LexedDirectory::try_ref_from_bytes(source))
-                                .collect::<Result<Vec<_>, _>>()
-                                .unwrap(),
-                        )
+                    Some(n) if n == self.byte + directory_len => {
+                        Some(self.lex_directory(&extents)?)
                     }
+                    _ => miette::bail!(
+                        "expected a directory of {} bytes after the subfiles at {:#x}",
+                        directory_len,
+                        self.byte
+                    ),
                 };
                 LexedBlock::XYXY { data, directory }
             }
@@ -295,9 +348,18 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         // Check we read enough
         match header.log_offset() {
             // If there is no log, then we should have read the whole file
-            None => assert!(self.is_exhausted()),
+            None => miette::ensure!(
+                self.is_exhausted(),
+                "{} bytes follow the data, but the file has no log",
+                self.remaining_bytes()
+            ),
             // And if there is a log it should be next in the buffer
-            Some(log_offset) => assert_eq!(self.byte, log_offset),
+            Some(log_offset) => miette::ensure!(
+                self.byte == log_offset,
+                "the data ends at {:#x}, but the log starts at {:#x}",
+                self.byte,
+                log_offset
+            ),
         }
 
         Ok(block)
@@ -310,12 +372,19 @@ impl<'data, E: ByteOrder> SPCReader<'data, E> {
         let source = self.read_byte_slice(64)?;
         let header = LexedLogHeader::try_ref_from_bytes(source).unwrap();
 
-        // The log data is immediately after the header
-        let data = &self.rest[..header.binary_size()];
-        // And the text block is the remainder?
-        let text = self.rest;
-
-        // So at this point the rest is always empty
+        // The binary data is immediately after the header
+        let data = self.rest.get(..header.binary_size()).ok_or_else(|| {
+            miette::miette!(
+                "the log binary area of {} bytes runs past the end of the file",
+                header.binary_size()
+            )
+        })?;
+        // And the text runs from its offset, which is measured from the start of the log block, to
+        // the end of the file
+        let text = self
+            .rest
+            .get(header.text_offset().saturating_sub(source.len())..)
+            .unwrap_or_default();
 
         self.rest = &[];
 
@@ -2,58 +2,282 @@
 use camino::Utf8Path;
 use lex::SPCReader;
 use miette::IntoDiagnostic;
-use parse::ParsedSPC;
 
 mod block;
+mod detect;
+#[cfg(test)]
+mod fixtures;
 mod header;
+mod inspect;
 mod lex;
 mod logblock;
+mod metadata;
 mod parse;
+mod peek;
+mod read;
+mod stream;
+mod text;
+mod trace;
 pub(crate) mod units;
 mod write;
+mod yaml;
+mod zaxis;
 
+pub use block::Directory;
+use detect::detect_galactic;
+pub use detect::{detect, detect_supported, FileKind};
+pub use header::{
+    Header, Modification, ModificationFlags, NewFormatHeader, OldFormatHeader, PostingDisposition,
+    ProcessingCode, SubFlagParameters, Subheader,
+};
+pub use inspect::{inspect, Field, Inspection, Region, RegionKind};
 use lex::LexedSPC;
-use parse::TryParse;
-use units::{
-    xzwType, xzwTypeCreationError, yType, yTypeCreationError, InstrumentTechnique,
-    InstrumentTechniqueCreationError,
+pub use logblock::LogBlock;
+pub use metadata::{Metadata, MetadataFormat, MetadataOptions};
+use parse::TryParseWith;
+pub use parse::{HeaderTimezone, ParseOptions, ParsedSPC};
+pub use peek::{peek_header, peek_header_with_options, Endianness, PeekedHeader};
+pub use read::{CsvReader, OpusReader, OpusSpectrum, ReadSPC, ShimadzuReader, WdfReader};
+pub use stream::SpcStream;
+pub use text::{Text, TextEncoding};
+pub use trace::{Plane, Trace};
+pub use units::{xzwType, yType, InstrumentTechnique};
+use units::{xzwTypeCreationError, yTypeCreationError, InstrumentTechniqueCreationError};
+#[cfg(feature = "arrow")]
+pub use write::{ArrowFormat, ArrowLayout, ArrowWriter};
+pub use write::{
+    AndiWriter, CsvLayout, CsvWriter, Delimiter, EnviInterleave, EnviWriter, FloatFormat,
+    MatWriter, MzmlWriter, NpzWriter, SpcVersion, SpcWriter, WriteSPC, XlsxWriter,
 };
-use write::{CsvWriter, WriteSPC};
+pub use zaxis::{ZAxis, ZSpacing};
 use zerocopy::{BigEndian, LittleEndian};
 
-pub fn write_spc(input_path: &Utf8Path, parsed: ParsedSPC) -> miette::Result<()> {
-    let output_path = input_path.with_extension("csv");
-    let writer = CsvWriter;
+pub fn write_spc(
+    input_path: &Utf8Path,
+    parsed: ParsedSPC,
+    writer: &CsvWriter,
+) -> miette::Result<()> {
+    write_csv(&input_path.with_extension("csv"), &parsed, writer)
+}
+
+/// Write the traces of the file alongside the input as a NumPy `.npz` archive
+pub fn write_npz(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &NpzWriter,
+) -> miette::Result<()> {
+    write_buffered(&input_path.with_extension("npz"), |buffer| {
+        writer.write_spc(buffer, parsed)
+    })
+}
+
+/// Write the traces and header of the file alongside the input as a MATLAB `.mat` file
+pub fn write_mat(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &MatWriter,
+) -> miette::Result<()> {
+    write_buffered(&input_path.with_extension("mat"), |buffer| {
+        writer.write_spc(buffer, parsed)
+    })
+}
+
+/// Write the traces and header of the file alongside the input as an Excel workbook
+pub fn write_xlsx(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &XlsxWriter,
+) -> miette::Result<()> {
+    write_buffered(&input_path.with_extension("xlsx"), |buffer| {
+        writer.write_spc(buffer, parsed)
+    })
+}
+
+/// Write a trace of a chromatogram alongside the input as an ANDI chromatography file
+pub fn write_andi(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &AndiWriter,
+) -> miette::Result<()> {
+    write_buffered(&input_path.with_extension("cdf"), |buffer| {
+        writer.write_spc(buffer, parsed)
+    })
+}
+
+/// Write the traces of a mass spectrum file alongside the input as an mzML document
+pub fn write_mzml(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &MzmlWriter,
+) -> miette::Result<()> {
+    write_buffered(&input_path.with_extension("mzML"), |buffer| {
+        writer.write_spc(buffer, parsed)
+    })
+}
+
+/// Write the traces of a mapped multifile alongside the input as an ENVI cube, with the extension
+/// of its interleave, and its `.hdr` header
+pub fn write_envi(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &EnviWriter,
+) -> miette::Result<()> {
+    let mut header = Vec::new();
+    writer.write_header(&mut header, parsed)?;
+    let mut cube = Vec::new();
+    writer.write_spc(&mut cube, parsed)?;
+
+    fs_err::write(input_path.with_extension("hdr"), header).into_diagnostic()?;
+    fs_err::write(
+        input_path.with_extension(writer.interleave().extension()),
+        cube,
+    )
+    .into_diagnostic()
+}
+
+/// Write the traces of the file alongside the input as Parquet or Arrow IPC, with the extension of
+/// the format
+#[cfg(feature = "arrow")]
+pub fn write_arrow(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &ArrowWriter,
+) -> miette::Result<()> {
+    write_buffered(
+        &input_path.with_extension(writer.format().extension()),
+        |buffer| writer.write_spc(buffer, parsed),
+    )
+}
+
+/// Write each w-plane of the file to its own CSV alongside the input, named `<stem>_w<index>.csv`
+pub fn write_spc_planes(
+    input_path: &Utf8Path,
+    parsed: ParsedSPC,
+    writer: &CsvWriter,
+) -> miette::Result<()> {
+    let stem = input_path.file_stem().unwrap_or_default();
+    for (index, plane) in parsed.split_planes()?.iter().enumerate() {
+        write_csv(
+            &input_path.with_file_name(format!("{stem}_w{index}.csv")),
+            plane,
+            writer,
+        )?;
+    }
+    Ok(())
+}
+
+/// Read a CSV file written in one of the layouts of the [`CsvWriter`] and write it alongside the
+/// input as an SPC file, named `<stem>_imported.spc`
+pub fn import_csv(input_path: &Utf8Path, reader: &CsvReader) -> miette::Result<()> {
+    let source = fs_err::read(input_path).into_diagnostic()?;
+    write_imported(input_path, &reader.read_bytes(&source)?)
+}
+
+/// Read a Bruker OPUS file and write it alongside the input as an SPC file, named
+/// `<stem>_imported.spc`
+pub fn import_opus(input_path: &Utf8Path, reader: &OpusReader) -> miette::Result<()> {
+    let source = fs_err::read(input_path).into_diagnostic()?;
+    write_imported(input_path, &reader.read(&source)?)
+}
+
+/// Read a Renishaw WDF file and write it alongside the input as an SPC file, named
+/// `<stem>_imported.spc`
+pub fn import_wdf(input_path: &Utf8Path, reader: &WdfReader) -> miette::Result<()> {
+    let source = fs_err::read(input_path).into_diagnostic()?;
+    write_imported(input_path, &reader.read(&source)?)
+}
+
+/// Write the file alongside the input as an SPC file of the version of the writer, named
+/// `<stem>_<version>.spc` such as `sample_4d.spc`. Anything the version cannot hold is dropped with
+/// a warning
+pub fn convert_spc(
+    input_path: &Utf8Path,
+    parsed: &ParsedSPC,
+    writer: &SpcWriter,
+) -> miette::Result<()> {
+    let stem = input_path.file_stem().unwrap_or_default();
+    write_buffered(
+        &input_path.with_file_name(format!("{stem}_{:x}.spc", writer.version().code())),
+        |buffer| writer.write_spc(buffer, parsed),
+    )
+}
+
+// Imported files are named `<stem>_imported.spc`, so importing the CSV exported from `sample.spc`
+// does not overwrite the original
+fn write_imported(input_path: &Utf8Path, parsed: &ParsedSPC) -> miette::Result<()> {
+    let stem = input_path.file_stem().unwrap_or_default();
+    write_buffered(
+        &input_path.with_file_name(format!("{stem}_imported.spc")),
+        |buffer| SpcWriter::new().write_spc(buffer, parsed),
+    )
+}
+
+// Write the output to a buffer, and only create the file once it has been written in full, so an
+// error leaves no partial file behind
+fn write_buffered(
+    output_path: &Utf8Path,
+    write: impl FnOnce(&mut Vec<u8>) -> miette::Result<()>,
+) -> miette::Result<()> {
+    let mut buffer = Vec::new();
+    write(&mut buffer)?;
+    fs_err::write(output_path, buffer).into_diagnostic()
+}
 
-    let mut file_handle = fs_err::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(output_path)
-        .into_diagnostic()?;
+fn write_csv(output_path: &Utf8Path, parsed: &ParsedSPC, writer: &CsvWriter) -> miette::Result<()> {
+    write_buffered(output_path, |buffer| {
+        writer.write_spc(buffer, parsed).into_diagnostic()
+    })
+}
+
+/// Gather the metadata of an SPC file without decoding its y-values, unless their ranges are
+/// requested in the options
+pub fn metadata(source: &'_ [u8], options: &MetadataOptions) -> miette::Result<Metadata> {
+    match detect_galactic(source)? {
+        FileKind::NewBigEndian => lex_big_endian_spc(source)?.metadata(options),
+        _ => lex_little_endian_spc(source)?.metadata(options),
+    }
+}
 
-    writer
-        .write_spc(&mut file_handle, &parsed)
-        .into_diagnostic()
+/// Write the metadata alongside the input, with the extension of the format
+pub fn write_metadata(
+    input_path: &Utf8Path,
+    metadata: &Metadata,
+    format: MetadataFormat,
+) -> miette::Result<()> {
+    fs_err::write(
+        input_path.with_extension(format.extension()),
+        metadata.serialize(format)?,
+    )
+    .into_diagnostic()
 }
 
 pub fn parse(source: &'_ [u8]) -> miette::Result<ParsedSPC> {
-    Ok(match source.get(1).copied() {
-        Some(0x4c) => lex_big_endian_spc(source)?.try_parse(),
-        Some(0x4b) | Some(0x4d) => lex_little_endian_spc(source)?.try_parse(),
-        Some(b) => panic!("impossible file type descriptor {b}"),
-        None => panic!("file contained less than two bytes"),
+    parse_with_options(source, &ParseOptions::default())
+}
+
+pub fn parse_with_options(source: &'_ [u8], options: &ParseOptions) -> miette::Result<ParsedSPC> {
+    Ok(match detect_supported(source)? {
+        FileKind::Shimadzu => return ShimadzuReader::new().read(source),
+        FileKind::NewBigEndian => lex_big_endian_spc(source)?.try_parse_with(options),
+        _ => lex_little_endian_spc(source)?.try_parse_with(options),
     }?)
 }
 
 pub fn lex_big_endian_spc(source: &'_ [u8]) -> miette::Result<LexedSPC<'_, BigEndian>> {
     log::info!("lexing big-endian SPC file");
-    assert!(matches!(source.get(1).copied().unwrap(), 0x4c));
+    miette::ensure!(
+        detect(source) == FileKind::NewBigEndian,
+        "not a big-endian SPC file"
+    );
     SPCReader::big_endian(source).lex()
 }
 
 pub fn lex_little_endian_spc(source: &'_ [u8]) -> miette::Result<LexedSPC<'_, LittleEndian>> {
     log::info!("lexing little-endian SPC file");
-    assert!(matches!(source.get(1).copied().unwrap(), 0x4b | 0x4d));
+    miette::ensure!(
+        matches!(detect(source), FileKind::NewLittleEndian | FileKind::Old),
+        "not a little-endian SPC file"
+    );
     SPCReader::little_endian(source).lex()
 }
 
@@ -65,6 +289,7 @@ mod test {
     use miette::{Context, IntoDiagnostic};
 
     use crate::{
+        block::Block,
         parse,
         write::{CsvWriter, WriteSPC},
     };
@@ -86,7 +311,7 @@ mod test {
 
         let parsed = parse(&source[..])?;
 
-        let writer = CsvWriter;
+        let writer = CsvWriter::default();
         let mut sink: Cursor<Vec<u8>> = Cursor::new(Vec::new());
 
         writer.write_spc(&mut sink, &parsed).unwrap();
@@ -103,4 +328,39 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn xyy_files_share_one_x_array() -> miette::Result<()> {
+        // A little-endian file with TMULTI and TXVALS set: one x-array then two float subfiles
+        let mut source = vec![0u8; 512];
+        source[..4].copy_from_slice(&[0x84, 0x4b, 0, 0x80]);
+        source[4..8].copy_from_slice(&3u32.to_le_bytes());
+        source[8..16].copy_from_slice(&1f64.to_le_bytes());
+        source[16..24].copy_from_slice(&7f64.to_le_bytes());
+        source[24..28].copy_from_slice(&2u32.to_le_bytes());
+        for x in [1f32, 3., 7.] {
+            source.extend(x.to_le_bytes());
+        }
+        for (index, ys) in [[2f32, 4., 6.], [-4., 8., 0.]].into_iter().enumerate() {
+            let mut subheader = [0u8; 32];
+            subheader[1] = 0x80;
+            subheader[2..4].copy_from_slice(&(index as u16).to_le_bytes());
+            source.extend(subheader);
+            for y in ys {
+                source.extend(y.to_le_bytes());
+            }
+        }
+
+        let parsed = parse(&source)?;
+        assert!(matches!(parsed.block, Block::XYY { .. }));
+        let traces = parsed.traces();
+        assert_eq!(traces.len(), 2);
+        for trace in &traces {
+            assert_eq!(trace.x(), [1., 3., 7.]);
+        }
+        assert_eq!(traces[0].y(), [2., 4., 6.]);
+        assert_eq!(traces[1].y(), [-4., 8., 0.]);
+
+        Ok(())
+    }
 }
@@ -1,8 +1,15 @@
-use zerocopy::{byteorder::U32, ByteOrder, Immutable, KnownLayout, TryFromBytes};
+use zerocopy::{
+    byteorder::U32, ByteOrder, Immutable, IntoBytes, KnownLayout, TryFromBytes, Unaligned,
+};
 
-use crate::{header::str_from_null_terminated_utf8_safe, parse::TryParse};
+use crate::{
+    inspect::{field_span, FieldSpan, Inspect},
+    parse::{ParseOptions, TryParse, TryParseWith},
+    text::Text,
+};
 
-#[derive(Clone, Debug, KnownLayout, Immutable, TryFromBytes)]
+#[repr(C)]
+#[derive(Clone, Debug, KnownLayout, Immutable, IntoBytes, TryFromBytes, Unaligned)]
 pub(crate) struct LexedLogHeader<E: ByteOrder> {
     // Size of disk block in bytes
     size: U32<E>,
@@ -23,6 +30,32 @@ impl<E: ByteOrder> LexedLogHeader<E> {
         let binary_size: u32 = self.binary_size.into();
         binary_size as usize
     }
+
+    pub(super) fn text_offset(&self) -> usize {
+        let text_offset: u32 = self.text_offset.into();
+        text_offset as usize
+    }
+}
+
+impl<E: ByteOrder> Inspect for LexedLogHeader<E> {
+    fn inspect(&self) -> Vec<FieldSpan> {
+        vec![
+            field_span!(self, size, self.size.get()),
+            field_span!(self, memory_size, self.memory_size.get()),
+            field_span!(self, text_offset, format!("{:#x}", self.text_offset.get())),
+            field_span!(self, binary_size, self.binary_size.get()),
+            field_span!(self, disk_area, self.disk_area.get()),
+            field_span!(
+                self,
+                reserved,
+                if self.reserved.iter().all(|&x| x == 0) {
+                    "zeroed"
+                } else {
+                    "NON-ZERO"
+                }
+            ),
+        ]
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -80,22 +113,82 @@ impl<E: ByteOrder> TryParse for LexedLogHeader<E> {
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct LogBlock {
+pub struct LogBlock {
     pub(super) header: LogHeader,
     pub(super) data: Vec<u8>,
-    pub(super) text: String,
+    pub(super) text: Text,
+}
+
+impl LogBlock {
+    // A log block holding only text, such as `key=value` entries built by a reader
+    pub(crate) fn new(text: &str) -> Self {
+        let text = Text::new(text);
+        let size = 64 + text.raw().len() as u32;
+        Self {
+            header: LogHeader {
+                size,
+                memory_size: size.next_multiple_of(4096),
+                text_offset: 64,
+                binary_size: 0,
+                disk_area: 0,
+                reserved: [0; 44],
+            },
+            data: Vec::new(),
+            text,
+        }
+    }
+
+    /// The binary area of the log block
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The text area of the log block
+    pub fn text(&self) -> &Text {
+        &self.text
+    }
+
+    /// The `key=value` entries in the log text, in the order they are written. Lines without an
+    /// `=` are split on the first `:` instead, and lines with neither are skipped
+    pub fn entries(&self) -> Vec<(String, String)> {
+        self.text
+            .as_str()
+            .lines()
+            .filter_map(|line| line.split_once('=').or_else(|| line.split_once(':')))
+            .map(|(key, value)| (key.trim().to_owned(), value.trim().to_owned()))
+            .filter(|(key, _)| !key.is_empty())
+            .collect()
+    }
+
+    // The log block as it is laid out in a file with byte order `E`. The sizes and offsets in the
+    // header are recomputed, with the text immediately following the binary area
+    pub(crate) fn to_bytes<E: ByteOrder>(&self) -> Vec<u8> {
+        let header_size = size_of::<LexedLogHeader<E>>();
+        let size = header_size + self.data.len() + self.text.raw().len();
+        let header = LexedLogHeader::<E> {
+            size: U32::new(size as u32),
+            memory_size: U32::new(size.next_multiple_of(4096) as u32),
+            text_offset: U32::new((header_size + self.data.len()) as u32),
+            binary_size: U32::new(self.data.len() as u32),
+            disk_area: U32::new(self.header.disk_area),
+            reserved: [0; 44],
+        };
+
+        let mut bytes = header.as_bytes().to_vec();
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(self.text.raw());
+        bytes
+    }
 }
 
-impl<E: ByteOrder> TryParse for LexedLogBlock<'_, E> {
+impl<E: ByteOrder> TryParseWith for LexedLogBlock<'_, E> {
     type Error = LogHeaderParseError;
     type Parsed = LogBlock;
-    fn try_parse(&self) -> Result<Self::Parsed, Self::Error> {
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error> {
         Ok(LogBlock {
             header: self.header.try_parse()?,
             data: self.data.to_owned(),
-            text: str_from_null_terminated_utf8_safe(self.text)
-                .trim()
-                .to_owned(),
+            text: Text::decode(self.text, options.encoding()),
         })
     }
 }
@@ -0,0 +1,523 @@
+//! A summary of everything in an SPC file except its data points, for export as a sidecar file.
+//!
+//! The summary is built from the lexed file, so the y-values are only read when their ranges are
+//! requested. This keeps scanning a folder of large multifiles quick.
+
+use std::collections::BTreeMap;
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use zerocopy::ByteOrder;
+
+use crate::{
+    block::LexedBlock,
+    header::{FlagParameters, Header},
+    lex::LexedSPC,
+    parse::{Parse, ParseOptions, TryParse, TryParseWith},
+    ParsedSPC,
+};
+
+/// The format metadata is serialized to
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MetadataFormat {
+    #[default]
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl MetadataFormat {
+    /// The usual file extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Options controlling what is gathered into the [`Metadata`]
+#[derive(Clone, Debug, Default)]
+pub struct MetadataOptions {
+    parse_options: ParseOptions,
+    y_ranges: bool,
+}
+
+impl MetadataOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the options used to decode the text fields and collection time of the header
+    pub fn with_parse_options(mut self, parse_options: ParseOptions) -> Self {
+        self.parse_options = parse_options;
+        self
+    }
+
+    /// Include the range of the y-values of the file and of each subfile. This requires every
+    /// y-value to be read
+    pub fn with_y_ranges(mut self, y_ranges: bool) -> Self {
+        self.y_ranges = y_ranges;
+        self
+    }
+}
+
+/// The metadata of an SPC file: the header, its flags, a summary of each subheader, the entries
+/// in the log, and facts derived from the layout of the data
+#[derive(Clone, Debug, Serialize)]
+pub struct Metadata {
+    header: HeaderMetadata,
+    flags: FlagMetadata,
+    derived: DerivedMetadata,
+    subheaders: Vec<SubheaderMetadata>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    log: BTreeMap<String, String>,
+}
+
+// The header, flags and log of a parsed file, stored alongside the arrays of exports which have no
+// room for the header itself
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct ParsedMetadata {
+    header: HeaderMetadata,
+    flags: FlagMetadata,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    log: BTreeMap<String, String>,
+}
+
+impl ParsedMetadata {
+    pub(crate) fn new(spc: &ParsedSPC) -> Self {
+        Self {
+            header: HeaderMetadata::new(&spc.header),
+            flags: FlagMetadata::new(spc.header.flags()),
+            log: spc
+                .log
+                .as_ref()
+                .map(|log| log.entries().into_iter().collect())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> miette::Result<String> {
+        serde_json::to_string(self).into_diagnostic()
+    }
+
+    // The header alone, as a tree of JSON values
+    pub(crate) fn header_value(&self) -> miette::Result<serde_json::Value> {
+        serde_json::to_value(&self.header).into_diagnostic()
+    }
+
+    // The fields of the header as key/value pairs named after the fields, such as `x_units`, with
+    // lists joined by commas, followed by the entries of the log with their keys prefixed by `log.`
+    pub(crate) fn entries(&self) -> miette::Result<Vec<(String, String)>> {
+        let serde_json::Value::Object(header) = self.header_value()? else {
+            miette::bail!("the header did not serialize to a map of fields");
+        };
+        let text = |value: serde_json::Value| match value {
+            serde_json::Value::String(value) => value,
+            value => value.to_string(),
+        };
+        let mut entries = header
+            .into_iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::Array(values) => (
+                    key,
+                    values.into_iter().map(text).collect::<Vec<_>>().join(", "),
+                ),
+                value => (key, text(value)),
+            })
+            .collect::<Vec<_>>();
+        entries.extend(
+            self.log
+                .iter()
+                .map(|(key, value)| (format!("log.{key}"), value.clone())),
+        );
+        Ok(entries)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct HeaderMetadata {
+    file_version: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instrument_technique: Option<String>,
+    y_exponent: i16,
+    number_points: u32,
+    starting_x: f64,
+    ending_x: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    number_of_subfiles: Option<u32>,
+    x_units: String,
+    y_units: String,
+    z_units: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    w_units: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    posting_disposition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    collected: Option<String>,
+    resolution: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_instrument: Option<String>,
+    peak_point_number: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scans: Option<u16>,
+    memo: String,
+    axis_labels: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modifications: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    processing_code: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    calibration_level: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub_method_sample_injection_number: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    concentration_factor: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    method_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    z_increment: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    w_planes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    w_increment: Option<f32>,
+}
+
+impl HeaderMetadata {
+    fn new(header: &Header) -> Self {
+        match header {
+            Header::Old(header) => Self {
+                file_version: header.file_version(),
+                instrument_technique: None,
+                y_exponent: header.exponent_y(),
+                number_points: header.number_points() as u32,
+                starting_x: header.starting_x() as f64,
+                ending_x: header.ending_x() as f64,
+                number_of_subfiles: None,
+                x_units: format!("{:?}", header.x_unit_type()),
+                y_units: format!("{:?}", header.y_unit_type()),
+                z_units: format!("{:?}", header.z_unit_type()),
+                w_units: None,
+                posting_disposition: None,
+                collected: header.datetime().map(|datetime| datetime.to_rfc3339()),
+                resolution: header.resolution_description().to_string(),
+                source_instrument: None,
+                peak_point_number: header.peak_point_number(),
+                scans: Some(header.scans()),
+                memo: header.memo().to_string(),
+                axis_labels: header.xyz_labels().segments(),
+                modifications: None,
+                processing_code: None,
+                calibration_level: None,
+                sub_method_sample_injection_number: None,
+                concentration_factor: None,
+                method_file: None,
+                z_increment: None,
+                w_planes: None,
+                w_increment: None,
+            },
+            Header::New(header) => Self {
+                file_version: header.file_version(),
+                instrument_technique: Some(format!("{:?}", header.instrument_technique())),
+                y_exponent: header.exponent_y() as i16,
+                number_points: header.number_points(),
+                starting_x: header.starting_x(),
+                ending_x: header.ending_x(),
+                number_of_subfiles: Some(header.number_of_subfiles()),
+                x_units: format!("{:?}", header.x_unit_type()),
+                y_units: format!("{:?}", header.y_unit_type()),
+                z_units: format!("{:?}", header.z_unit_type()),
+                w_units: Some(format!("{:?}", header.w_unit_type())),
+                posting_disposition: Some(format!("{:?}", header.posting_disposition())),
+                collected: header.datetime().map(|datetime| datetime.to_rfc3339()),
+                resolution: header.resolution_description().to_string(),
+                source_instrument: Some(header.source_instrument_description().to_string()),
+                peak_point_number: header.peak_point_number(),
+                scans: None,
+                memo: header.memo().to_string(),
+                axis_labels: header.xyz_labels().segments(),
+                modifications: Some(
+                    header
+                        .modification_flags()
+                        .iter()
+                        .map(|modification| modification.to_string())
+                        .collect(),
+                ),
+                processing_code: Some(format!("{:?}", header.processing_code())),
+                calibration_level: header.calibration_level(),
+                sub_method_sample_injection_number: Some(
+                    header.sub_method_sample_injection_number(),
+                ),
+                concentration_factor: Some(header.concentration_factor()),
+                method_file: Some(header.method_file().to_string()),
+                z_increment: Some(header.z_sub_increment()),
+                w_planes: Some(header.w_planes()),
+                w_increment: Some(header.w_plane_increment()),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct FlagMetadata {
+    bits: u8,
+    y_precision: usize,
+    fexper: bool,
+    multifile: bool,
+    random_z: bool,
+    uneven_z: bool,
+    custom_axis_labels: bool,
+    xyxy: bool,
+    xy: bool,
+}
+
+impl FlagMetadata {
+    fn new(flags: FlagParameters) -> Self {
+        Self {
+            bits: flags.0,
+            y_precision: flags.y_precision().bytes_per_point() * 8,
+            fexper: flags.use_fexper_extension(),
+            multifile: flags.multifile(),
+            random_z: flags.z_values_are_random(),
+            uneven_z: flags.z_values_are_uneven(),
+            custom_axis_labels: flags.custom_axis_labels(),
+            xyxy: flags.xyxy(),
+            xy: flags.xy(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct SubheaderMetadata {
+    index: u16,
+    flags: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exponent: Option<i8>,
+    z: f32,
+    next_z: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    noise: Option<f32>,
+    points: usize,
+    scans: u32,
+    w_level: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y_range: Option<[f64; 2]>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct DerivedMetadata {
+    shape: &'static str,
+    number_of_subfiles: usize,
+    total_points: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    points_per_subfile: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x_range: Option<[f64; 2]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    y_range: Option<[f64; 2]>,
+}
+
+// The smallest and largest of the values, or `None` if there are none
+fn range(values: impl IntoIterator<Item = f64>) -> Option<[f64; 2]> {
+    values.into_iter().fold(None, |range, value| match range {
+        None => Some([value, value]),
+        Some([min, max]) => Some([min.min(value), max.max(value)]),
+    })
+}
+
+fn merge(ranges: impl IntoIterator<Item = Option<[f64; 2]>>) -> Option<[f64; 2]> {
+    range(ranges.into_iter().flatten().flatten())
+}
+
+impl Metadata {
+    /// The shape of the data: `Y`, `XY`, `YY`, `XYY` or `XYXY`
+    pub fn shape(&self) -> &str {
+        self.derived.shape
+    }
+
+    /// The number of points in each subfile
+    pub fn point_counts(&self) -> Vec<usize> {
+        self.subheaders
+            .iter()
+            .map(|subheader| subheader.points)
+            .collect()
+    }
+
+    /// The smallest and largest x-value in the file
+    pub fn x_range(&self) -> Option<[f64; 2]> {
+        self.derived.x_range
+    }
+
+    /// The smallest and largest y-value in the file, if the ranges were requested
+    pub fn y_range(&self) -> Option<[f64; 2]> {
+        self.derived.y_range
+    }
+
+    /// The entries of the log, see [`LogBlock::entries`](crate::LogBlock::entries)
+    pub fn log(&self) -> &BTreeMap<String, String> {
+        &self.log
+    }
+
+    /// Serialize the metadata in the given format
+    pub fn serialize(&self, format: MetadataFormat) -> miette::Result<String> {
+        match format {
+            MetadataFormat::Yaml => crate::yaml::to_string(self),
+            MetadataFormat::Toml => toml::to_string(self).into_diagnostic(),
+            MetadataFormat::Json => serde_json::to_string_pretty(self).into_diagnostic(),
+        }
+    }
+}
+
+impl<E: ByteOrder> LexedSPC<'_, E> {
+    pub(crate) fn metadata(&self, options: &MetadataOptions) -> miette::Result<Metadata> {
+        let header = self.header.try_parse_with(&options.parse_options)?;
+        let exponent = header.exponent_y();
+        let times = self.block.directory();
+        let subfiles = self.block.subfiles();
+
+        let mut subheaders = Vec::new();
+        let mut x_ranges = Vec::new();
+        for (index, (x, subfile)) in subfiles.iter().enumerate() {
+            let subheader = subfile.subheader().try_parse()?;
+            let y_range = if options.y_ranges {
+                range(subfile.try_parse()?.data.decode(exponent))
+            } else {
+                None
+            };
+            x_ranges.push(x.map(|x| range(x.parse().iter().map(|&x| x as f64))));
+            subheaders.push(SubheaderMetadata {
+                index: subheader.index_number(),
+                flags: subheader.flags().to_string(),
+                exponent: subheader.exponent_y(),
+                z: subheader.z(),
+                next_z: subheader.next_z(),
+                noise: subheader.noise(),
+                points: subfile.number_of_points(),
+                scans: subheader.scans(),
+                w_level: subheader.w_level(),
+                time: times
+                    .as_ref()
+                    .and_then(|times| times.get(index))
+                    .map(|entry| entry.time()),
+                y_range,
+            });
+        }
+
+        // Evenly spaced x-values are not stored, so their range comes from the header
+        let x_range = if x_ranges.iter().all(Option::is_none) {
+            range([header.starting_x(), header.ending_x()])
+        } else {
+            merge(x_ranges.into_iter().flatten())
+        };
+        let points = subheaders
+            .iter()
+            .map(|subheader| subheader.points)
+            .collect::<Vec<_>>();
+
+        let flags = header.flags();
+        let derived = DerivedMetadata {
+            shape: match self.block {
+                LexedBlock::Y(_) => "Y",
+                LexedBlock::XY { .. } => "XY",
+                LexedBlock::YY(_) => "YY",
+                LexedBlock::XYY { .. } => "XYY",
+                LexedBlock::XYXY { .. } => "XYXY",
+            },
+            number_of_subfiles: subheaders.len(),
+            total_points: points.iter().sum(),
+            points_per_subfile: points
+                .iter()
+                .all(|&count| Some(&count) == points.first())
+                .then(|| points.first().copied())
+                .flatten(),
+            x_range,
+            y_range: merge(subheaders.iter().map(|subheader| subheader.y_range)),
+        };
+
+        let log = self
+            .log
+            .as_ref()
+            .map(|log| log.try_parse_with(&options.parse_options))
+            .transpose()?
+            .map(|log| log.entries().into_iter().collect())
+            .unwrap_or_default();
+
+        Ok(Metadata {
+            header: HeaderMetadata::new(&header),
+            flags: FlagMetadata::new(flags),
+            derived,
+            subheaders,
+            log,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MetadataFormat, MetadataOptions, ParsedMetadata};
+    use crate::{fixtures::multifile, metadata, text::Text, write::WriteSPC, Header, SpcWriter};
+
+    #[test]
+    fn multifile_metadata_has_its_shape_and_ranges_in_every_format() {
+        let parsed = multifile();
+        let mut source = Vec::new();
+        SpcWriter::new().write_spc(&mut source, &parsed).unwrap();
+
+        let without_ranges = metadata(&source, &MetadataOptions::new()).unwrap();
+        assert_eq!(without_ranges.shape(), "XYY");
+        assert_eq!(without_ranges.point_counts(), [3, 3]);
+        assert_eq!(without_ranges.x_range(), Some([1.0, 7.0]));
+        assert_eq!(without_ranges.y_range(), None);
+
+        let with_ranges = metadata(&source, &MetadataOptions::new().with_y_ranges(true)).unwrap();
+        assert_eq!(with_ranges.y_range(), Some([-4.0, 8.0]));
+
+        for format in [
+            MetadataFormat::Yaml,
+            MetadataFormat::Toml,
+            MetadataFormat::Json,
+        ] {
+            assert!(with_ranges.serialize(format).unwrap().contains("XYY"));
+        }
+
+        // Each header field is one entry, however its value is punctuated
+        let mut parsed = parsed;
+        let Header::New(header) = &mut parsed.header else {
+            unreachable!()
+        };
+        header.memo = Text::new("first line\nkey: value");
+        let entries = ParsedMetadata::new(&parsed).entries().unwrap();
+        assert!(entries.contains(&("memo".to_owned(), "first line\nkey: value".to_owned())));
+        assert!(entries.contains(&("x_units".to_owned(), "Arbitrary".to_owned())));
+        assert!(!entries.iter().any(|(key, _)| key == "key"));
+
+        // A trailing byte in a file without a log is an error rather than a panic
+        source.push(0);
+        assert!(metadata(&source, &MetadataOptions::new()).is_err());
+        assert!(crate::parse(&source).is_err());
+    }
+
+    #[test]
+    fn malformed_headers_are_errors_rather_than_panics() {
+        // An old-format XYXY file, which cannot record the number of subfiles
+        let mut old_xyxy = vec![0; 600];
+        old_xyxy[0] = 0xc4;
+        old_xyxy[1] = 0x4d;
+        // TXVALS and TXYXYS without TMULTI
+        let mut single_xyxy = vec![0; 600];
+        single_xyxy[0] = 0xc0;
+        single_xyxy[1] = 0x4b;
+        // An old-format XY file with more points than can be addressed
+        let mut huge = vec![0; 600];
+        huge[0] = 0x80;
+        huge[1] = 0x4d;
+        huge[4..8].copy_from_slice(&1e30f32.to_le_bytes());
+
+        for source in [old_xyxy, single_xyxy, huge] {
+            assert!(metadata(&source, &MetadataOptions::new()).is_err());
+        }
+    }
+}
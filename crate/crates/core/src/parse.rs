@@ -1,7 +1,14 @@
+use std::borrow::Cow;
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeDelta, TimeZone, Utc};
+
 use crate::{
-    block::Block,
+    block::{Block, Directory},
     header::{Header, HeaderParseError, SubheaderParseError},
     logblock::{LogBlock, LogHeaderParseError},
+    text::TextEncoding,
+    trace::{subfiles_per_plane, Plane, PlaneError, Trace},
+    zaxis::ZAxis,
 };
 
 #[derive(Debug, thiserror::Error, miette::Diagnostic)]
@@ -25,9 +32,235 @@ pub(crate) trait TryParse {
     fn try_parse(&self) -> Result<Self::Parsed, Self::Error>;
 }
 
+// Parsing for structures whose interpretation depends on the caller's [`ParseOptions`]
+pub(crate) trait TryParseWith {
+    type Parsed;
+    type Error;
+    fn try_parse_with(&self, options: &ParseOptions) -> Result<Self::Parsed, Self::Error>;
+}
+
+/// The timezone in which the collection time stored in a header is interpreted
+///
+/// The SPC specification stores the collection time as a local wall-clock time, without recording
+/// which timezone it was local to.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum HeaderTimezone {
+    /// Treat the stored time as UTC
+    #[default]
+    Utc,
+    /// Treat the stored time as local to the machine doing the parsing
+    Local,
+    /// Treat the stored time as local to a fixed offset from UTC
+    Fixed(FixedOffset),
+}
+
+impl HeaderTimezone {
+    // Convert a wall-clock time in this timezone to UTC. Times which occur twice, when clocks go
+    // back, resolve to the earlier instant. Times which never occur, when clocks go forward, are
+    // read with the offset from before the change.
+    pub(crate) fn to_utc(self, naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Utc => Some(Utc.from_utc_datetime(naive)),
+            Self::Local => resolve(&Local, naive),
+            Self::Fixed(offset) => resolve(&offset, naive),
+        }
+    }
+}
+
+fn resolve<Tz: TimeZone>(timezone: &Tz, naive: &NaiveDateTime) -> Option<DateTime<Utc>> {
+    timezone
+        .from_local_datetime(naive)
+        .earliest()
+        // A time skipped when clocks go forward by an hour is the instant the clock reads an hour
+        // later, after the change
+        .or_else(|| {
+            timezone
+                .from_local_datetime(&(*naive + TimeDelta::hours(1)))
+                .earliest()
+        })
+        .map(|datetime| datetime.to_utc())
+}
+
+/// Options controlling how the contents of an SPC file are interpreted
+#[derive(Clone, Debug, Default)]
+pub struct ParseOptions {
+    encoding: TextEncoding,
+    timezone: HeaderTimezone,
+}
+
+impl ParseOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the encoding used to decode text fields such as the memo and the log text
+    pub fn with_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Set the timezone in which the collection time in the header is interpreted
+    pub fn with_timezone(mut self, timezone: HeaderTimezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn timezone(&self) -> HeaderTimezone {
+        self.timezone
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ParsedSPC {
     pub(crate) header: Header,
     pub(crate) block: Block,
     pub(crate) log: Option<LogBlock>,
 }
+
+impl ParsedSPC {
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn log(&self) -> Option<&LogBlock> {
+        self.log.as_ref()
+    }
+
+    /// Every trace in the file, in the order they are stored.
+    ///
+    /// Integer y-values are scaled by the exponent in the header, matching the CSV export. The
+    /// z-value of each trace is taken from [`ParsedSPC::z_axis`].
+    pub fn traces(&self) -> Vec<Trace<'_>> {
+        let exponent = self.header.exponent_y();
+        let z_axis = self.z_axis();
+        let times = self.subfile_times();
+        self.block
+            .subfiles()
+            .into_iter()
+            .zip(z_axis.values())
+            .enumerate()
+            .map(|(index, ((x, subfile), &z))| {
+                let x = match x {
+                    Some(x) => x.iter().map(|&x| x as f64).collect(),
+                    None => self.header.x_points(),
+                };
+                Trace::new(
+                    index,
+                    x,
+                    subfile.data.decode(exponent),
+                    z,
+                    Cow::Borrowed(&subfile.subheader),
+                )
+                .with_time(times.as_ref().map(|times| times[index]))
+            })
+            .collect()
+    }
+
+    /// The directory of an XYXY file, or `None` if the file does not have one
+    pub fn directory(&self) -> Option<&[Directory]> {
+        match &self.block {
+            Block::XYXY {
+                directory: Some(directory),
+                ..
+            } => Some(directory),
+            _ => None,
+        }
+    }
+
+    /// The time of each subfile from the directory of an XYXY file. In GC-MS style files this is
+    /// the retention time of each spectrum
+    pub fn subfile_times(&self) -> Option<Vec<f64>> {
+        self.directory()
+            .map(|directory| directory.iter().map(|entry| entry.time() as f64).collect())
+    }
+
+    /// The traces sorted by ascending z within each w-plane, see [`ZAxis::sort_order`]
+    pub fn traces_sorted_by_z(&self) -> Vec<Trace<'_>> {
+        let mut traces = self.traces().into_iter().map(Some).collect::<Vec<_>>();
+        self.z_axis()
+            .sort_order()
+            .into_iter()
+            .filter_map(|index| traces[index].take())
+            .collect()
+    }
+
+    /// The z-value of every subfile, reconstructed from the file flags, the z-increment in the
+    /// header and the subheaders
+    pub fn z_axis(&self) -> ZAxis {
+        let subheaders = self
+            .block
+            .subfiles()
+            .into_iter()
+            .map(|(_, subfile)| &subfile.subheader)
+            .collect::<Vec<_>>();
+        // Files whose subfiles cannot be split into w-planes are treated as a single plane, the
+        // error is reported by `planes`
+        let per_plane = subfiles_per_plane(subheaders.len(), self.header.w_planes())
+            .unwrap_or(subheaders.len());
+        ZAxis::new(&self.header, &subheaders, per_plane)
+    }
+
+    /// The traces grouped into w-planes.
+    ///
+    /// Files without w-planes give a single plane, with no w-value, holding every trace. The
+    /// w-value of each plane is computed from the w-increment in the header when it is non-zero,
+    /// and read from the subheader of the first trace in the plane otherwise.
+    pub fn planes(&self) -> miette::Result<Vec<Plane<'_>>> {
+        let traces = self.traces();
+        let planes = self.header.w_planes();
+        let per_plane = subfiles_per_plane(traces.len(), planes)?;
+
+        let increment = self.header.w_plane_increment();
+        let first_w = traces.first().map(|trace| trace.subheader().w_level());
+
+        let mut traces = traces.into_iter();
+        (0..planes.max(1))
+            .map(|index| {
+                let traces = traces.by_ref().take(per_plane).collect::<Vec<_>>();
+                let w = match (planes, first_w) {
+                    (0, _) => None,
+                    (_, Some(first_w)) if increment != 0.0 => {
+                        Some(first_w as f64 + index as f64 * increment as f64)
+                    }
+                    // Without an increment the w-level of every trace in the plane must agree
+                    _ => {
+                        let w = traces.first().map(|trace| trace.subheader().w_level());
+                        if traces
+                            .iter()
+                            .any(|trace| Some(trace.subheader().w_level()) != w)
+                        {
+                            return Err(PlaneError::MixedLevels { plane: index }.into());
+                        }
+                        w.map(f64::from)
+                    }
+                };
+                Ok(Plane::new(index, w, traces))
+            })
+            .collect()
+    }
+
+    /// Split the file into one file per w-plane, so each plane can be exported separately.
+    ///
+    /// Files without w-planes give a single file.
+    pub fn split_planes(&self) -> miette::Result<Vec<ParsedSPC>> {
+        let subfiles = self.block.subfiles().len();
+        let per_plane = subfiles_per_plane(subfiles, self.header.w_planes())?;
+        if per_plane == 0 {
+            return Ok(vec![self.clone()]);
+        }
+
+        Ok((0..subfiles / per_plane)
+            .map(|index| ParsedSPC {
+                header: self.header.for_plane(per_plane),
+                block: self
+                    .block
+                    .select(index * per_plane..(index + 1) * per_plane),
+                log: self.log.clone(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,130 @@
+//! Reading only the header of an SPC file, for cataloguing many files quickly.
+//!
+//! Nothing past the header is read from the source, so the data section is never touched and a
+//! file which is truncated after its header can still be peeked.
+
+use std::io::Read;
+
+use miette::{Context, IntoDiagnostic};
+
+use crate::{
+    detect::{detect_galactic, FileKind},
+    header::Header,
+    lex::SPCReader,
+    parse::{ParseOptions, TryParseWith},
+};
+
+/// The order of the bytes in the numbers of an SPC file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// The header of an SPC file, with the version and byte order it was stored in
+#[derive(Clone, Debug)]
+pub struct PeekedHeader {
+    header: Header,
+    file_version: u8,
+    endianness: Endianness,
+}
+
+impl PeekedHeader {
+    /// The parsed header
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Take the parsed header
+    pub fn into_header(self) -> Header {
+        self.header
+    }
+
+    /// The file version: 0x4b or 0x4c for the new format, and 0x4d for the old format
+    pub fn file_version(&self) -> u8 {
+        self.file_version
+    }
+
+    /// The byte order of the file
+    pub fn endianness(&self) -> Endianness {
+        self.endianness
+    }
+}
+
+/// Read and parse the header from the start of `reader`, reading nothing past it
+pub fn peek_header<R: Read>(reader: &mut R) -> miette::Result<PeekedHeader> {
+    peek_header_with_options(reader, &ParseOptions::default())
+}
+
+/// Read and parse the header from the start of `reader` with the given options, reading nothing
+/// past it
+pub fn peek_header_with_options<R: Read>(
+    reader: &mut R,
+    options: &ParseOptions,
+) -> miette::Result<PeekedHeader> {
+    // Enough bytes to tell the kind of file apart, all of which belong to the header
+    let mut start = [0; 8];
+    reader
+        .read_exact(&mut start)
+        .into_diagnostic()
+        .wrap_err("reading the start of the file failed")?;
+    let (endianness, header_len) = match detect_galactic(&start)? {
+        FileKind::NewBigEndian => (Endianness::Big, 512),
+        FileKind::Old => (Endianness::Little, 224),
+        _ => (Endianness::Little, 512),
+    };
+
+    let mut source = vec![0; header_len];
+    source[..start.len()].copy_from_slice(&start);
+    reader
+        .read_exact(&mut source[start.len()..])
+        .into_diagnostic()
+        .wrap_err_with(|| format!("the file is shorter than its {header_len} byte header"))?;
+
+    let header = match endianness {
+        Endianness::Little => SPCReader::little_endian(&source)
+            .lex_header()?
+            .try_parse_with(options)?,
+        Endianness::Big => SPCReader::big_endian(&source)
+            .lex_header()?
+            .try_parse_with(options)?,
+    };
+    Ok(PeekedHeader {
+        header,
+        file_version: start[1],
+        endianness,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{peek_header, Endianness};
+
+    #[test]
+    fn truncated_and_garbled_headers_are_rejected_without_panicking() {
+        let mut source = vec![0; 600];
+        source[1] = 0x4c;
+
+        let peeked = peek_header(&mut &source[..]).unwrap();
+        assert_eq!(peeked.file_version(), 0x4c);
+        assert_eq!(peeked.endianness(), Endianness::Big);
+
+        for len in 0..512 {
+            assert!(peek_header(&mut &source[..len]).is_err());
+        }
+        assert!(peek_header(&mut &source[..512]).is_ok());
+
+        source[1] = 0x4d;
+        assert!(peek_header(&mut &source[..223]).is_err());
+        assert!(peek_header(&mut &source[..224]).is_ok());
+
+        // Arbitrary bytes, including invalid units and dates, are an error rather than a panic
+        for descriptor in [0x4b, 0x4c, 0x4d] {
+            for (index, byte) in source.iter_mut().enumerate() {
+                *byte = (index * 37 % 251) as u8;
+            }
+            source[1] = descriptor;
+            let _ = peek_header(&mut &source[..]);
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Reading the CSV and TSV layouts written by the [`CsvWriter`](crate::CsvWriter).
+//!
+//! The layout is recognised from the header row and the comment lines:
+//! - A header starting with `trace_index` is the tidy layout, with one row per point.
+//! - Comment lines such as `# z = 5` between blocks of rows are the wide layout of an XYXY file,
+//!   where each block is one trace.
+//! - Otherwise a table with two columns holds a single trace, and a wider table holds one trace in
+//!   each column after the first, sharing the x-values in the first column.
+//!
+//! Comment lines before the table are read as `key: value` pairs, matching the preamble written by
+//! the [`CsvWriter`](crate::CsvWriter). Column names which match a unit name or label set the
+//! units of their axis, and any other names other than `x`, `y` and `z` are kept as custom axis
+//! labels.
+
+use std::io::Read;
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+use csv::{ReaderBuilder, StringRecord};
+use miette::IntoDiagnostic;
+
+use crate::{
+    header::{DataShape, NewFormatHeader, SubFlagParameters},
+    text::{Text, TextEncoding},
+    write::Delimiter,
+    xzwType, yType, InstrumentTechnique, ParsedSPC,
+};
+
+use super::{assemble, ImportedTrace, ReadSPC};
+
+/// Reads the CSV layouts written by the [`CsvWriter`](crate::CsvWriter) into a [`ParsedSPC`]
+///
+/// The units and metadata set here take precedence over those read from the preamble and the
+/// column names.
+#[derive(Clone, Debug)]
+pub struct CsvReader {
+    delimiter: Option<Delimiter>,
+    comment: u8,
+    decimal_comma: bool,
+    encoding: TextEncoding,
+    x_unit_type: Option<xzwType>,
+    y_unit_type: Option<yType>,
+    z_unit_type: Option<xzwType>,
+    w_unit_type: Option<xzwType>,
+    instrument_technique: Option<InstrumentTechnique>,
+    memo: Option<String>,
+}
+
+impl Default for CsvReader {
+    fn default() -> Self {
+        Self {
+            delimiter: None,
+            comment: b'#',
+            decimal_comma: false,
+            encoding: TextEncoding::Auto,
+            x_unit_type: None,
+            y_unit_type: None,
+            z_unit_type: None,
+            w_unit_type: None,
+            instrument_technique: None,
+            memo: None,
+        }
+    }
+}
+
+// A block of rows, preceded by the z-value and time of its trace in the XYXY layout
+#[derive(Default)]
+struct Segment {
+    z: Option<f64>,
+    time: Option<f64>,
+    lines: String,
+}
+
+impl CsvReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the character separating the fields of each row. By default it is detected from the
+    /// first row, preferring a tab, then a semicolon, then a comma
+    pub fn with_delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = Some(delimiter);
+        self
+    }
+
+    /// Set the character starting comment lines
+    pub fn with_comment(mut self, comment: u8) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Read a comma rather than a point before the fractional part of each value
+    pub fn with_decimal_comma(mut self, decimal_comma: bool) -> Self {
+        self.decimal_comma = decimal_comma;
+        self
+    }
+
+    /// Set the encoding the file is decoded with, when it is read from bytes. By default it is
+    /// read as UTF-8, or as Windows-1252 if it is not valid UTF-8
+    pub fn with_encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Set the units of the x-axis
+    pub fn with_x_unit_type(mut self, unit: xzwType) -> Self {
+        self.x_unit_type = Some(unit);
+        self
+    }
+
+    /// Set the units of the y-axis
+    pub fn with_y_unit_type(mut self, unit: yType) -> Self {
+        self.y_unit_type = Some(unit);
+        self
+    }
+
+    /// Set the units of the z-axis
+    pub fn with_z_unit_type(mut self, unit: xzwType) -> Self {
+        self.z_unit_type = Some(unit);
+        self
+    }
+
+    /// Set the units of the w-axis
+    pub fn with_w_unit_type(mut self, unit: xzwType) -> Self {
+        self.w_unit_type = Some(unit);
+        self
+    }
+
+    /// Set the technique of the instrument which collected the data
+    pub fn with_instrument_technique(mut self, technique: InstrumentTechnique) -> Self {
+        self.instrument_technique = Some(technique);
+        self
+    }
+
+    /// Set the memo text
+    pub fn with_memo(mut self, memo: impl Into<String>) -> Self {
+        self.memo = Some(memo.into());
+        self
+    }
+
+    /// Read a CSV file held in memory as bytes, decoded with the encoding of the reader
+    pub fn read_bytes(&self, source: &[u8]) -> miette::Result<ParsedSPC> {
+        self.read(&self.encoding.decode(source))
+    }
+
+    /// Read a CSV file held in memory
+    pub fn read(&self, source: &str) -> miette::Result<ParsedSPC> {
+        let mut preamble = Vec::new();
+        let mut first_row = None;
+        let mut segments = vec![Segment::default()];
+        let mut z_name = None;
+
+        for line in source.lines() {
+            if let Some(comment) = line.strip_prefix(self.comment as char) {
+                let comment = comment.trim();
+                if first_row.is_none() {
+                    if let Some((key, value)) = comment.split_once(':') {
+                        preamble.push((key.trim().to_owned(), value.trim().to_owned()));
+                    }
+                } else if let Some((name, z, time)) = self.marker(comment) {
+                    z_name = Some(name);
+                    segments.push(Segment {
+                        z: Some(z),
+                        time,
+                        lines: String::new(),
+                    });
+                }
+            } else if line.trim().is_empty() {
+                continue;
+            } else if first_row.is_none() {
+                first_row = Some(line.to_owned());
+            } else {
+                let segment = segments.last_mut().unwrap();
+                segment.lines.push_str(line);
+                segment.lines.push('\n');
+            }
+        }
+
+        let Some(first_row) = first_row else {
+            miette::bail!("the file does not contain a table");
+        };
+        let delimiter = self.delimiter.map_or_else(
+            || {
+                [b'\t', b';', b',']
+                    .into_iter()
+                    .find(|delimiter| first_row.contains(*delimiter as char))
+                    .unwrap_or(b',')
+            },
+            Delimiter::byte,
+        );
+
+        // A file without a header row starts straight away with values
+        let first_record = self.records(&first_row, delimiter)?.remove(0);
+        let names = if first_record
+            .iter()
+            .all(|field| self.value(field).is_ok_and(|value| value.is_some()))
+        {
+            segments[0].lines.insert_str(0, &format!("{first_row}\n"));
+            None
+        } else {
+            Some(
+                first_record
+                    .iter()
+                    .map(|name| name.trim().to_owned())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        let mut header = NewFormatHeader::empty();
+        let mut labels = [None, None, None];
+        let (traces, shape) = match names.as_deref() {
+            Some([first, ..]) if first == "trace_index" => {
+                let names = names.as_deref().unwrap();
+                labels = [names.get(3), names.get(4), names.get(1)].map(|name| name.cloned());
+                self.read_tidy(&mut header, names, &segments[0].lines, delimiter)?
+            }
+            _ if segments.len() > 1 => {
+                if let Some(names) = &names {
+                    labels = [names.first().cloned(), names.get(1).cloned(), z_name];
+                }
+                // Every row of an XYXY layout belongs to the trace of the marker before it
+                if !segments[0].lines.is_empty() {
+                    miette::bail!("rows before the first `z = ` marker belong to no trace");
+                }
+                let mut traces = Vec::new();
+                for segment in &segments[1..] {
+                    let mut trace = ImportedTrace::new(segment.z.unwrap());
+                    trace.time = segment.time;
+                    for row in self.rows(&segment.lines, delimiter)? {
+                        trace.x.push(self.required(&row, 0)?);
+                        trace.y.push(self.required(&row, 1)?);
+                    }
+                    traces.push(trace);
+                }
+                (traces, Some(DataShape::XYXY))
+            }
+            _ => {
+                let rows = self.rows(&segments[0].lines, delimiter)?;
+                let columns = rows
+                    .first()
+                    .map(Vec::len)
+                    .or(names.as_ref().map(Vec::len))
+                    .unwrap_or_default();
+                if columns < 2 {
+                    miette::bail!("the table has no y-columns, only the x-values");
+                }
+                // The y columns of the multi-y layout are suffixed with the trace index, which
+                // distinguishes a multifile holding one trace from a single trace
+                let y_name = names.as_ref().and_then(|names| names.get(1));
+                let indexed = y_name.and_then(|name| {
+                    name.rsplit_once('_')
+                        .filter(|(_, index)| index.parse::<usize>().is_ok())
+                        .map(|(name, _)| name.to_owned())
+                });
+                let multifile = columns > 2 || indexed.is_some();
+                if let Some(names) = &names {
+                    labels = [names.first().cloned(), indexed.or(y_name.cloned()), None];
+                }
+                let mut traces = (1..columns.max(2))
+                    .map(|_| ImportedTrace::new(0.0))
+                    .collect::<Vec<_>>();
+                for row in rows {
+                    if row.len() != columns {
+                        miette::bail!(
+                            "every row must hold {columns} columns, but a row holds {}",
+                            row.len()
+                        );
+                    }
+                    let x = row.first().copied().flatten();
+                    for (trace, y) in traces.iter_mut().zip(row.iter().skip(1)) {
+                        let (Some(x), Some(y)) = (x, *y) else {
+                            miette::bail!("every row must hold a value in every column");
+                        };
+                        trace.x.push(x);
+                        trace.y.push(y);
+                    }
+                }
+                let shape = if multifile {
+                    DataShape::YY
+                } else {
+                    DataShape::Y
+                };
+                (traces, Some(shape))
+            }
+        };
+
+        self.apply_preamble(&mut header, &preamble)?;
+        self.apply_labels(&mut header, labels);
+        self.apply_options(&mut header);
+
+        Ok(assemble(header, traces, shape))
+    }
+
+    // Parse a marker such as `z = 5, time = 1.5`, returning the name of the z-axis, the z-value and
+    // the time
+    fn marker(&self, comment: &str) -> Option<(String, f64, Option<f64>)> {
+        let (marker, time) = match comment.split_once(", time = ") {
+            Some((marker, time)) => (marker, Some(self.value(time).ok()??)),
+            None => (comment, None),
+        };
+        let (name, z) = marker.rsplit_once(" = ")?;
+        Some((name.trim().to_owned(), self.value(z).ok()??, time))
+    }
+
+    fn records(&self, lines: &str, delimiter: u8) -> miette::Result<Vec<StringRecord>> {
+        ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(lines.as_bytes())
+            .records()
+            .collect::<Result<Vec<_>, _>>()
+            .into_diagnostic()
+    }
+
+    fn rows(&self, lines: &str, delimiter: u8) -> miette::Result<Vec<Vec<Option<f64>>>> {
+        self.records(lines, delimiter)?
+            .iter()
+            .map(|record| record.iter().map(|field| self.value(field)).collect())
+            .collect()
+    }
+
+    fn value(&self, field: &str) -> miette::Result<Option<f64>> {
+        let field = field.trim();
+        if field.is_empty() {
+            return Ok(None);
+        }
+        let parsed = if self.decimal_comma {
+            field.replace(',', ".").parse()
+        } else {
+            field.parse()
+        };
+        parsed
+            .map(Some)
+            .map_err(|_| miette::miette!("could not read '{field}' as a number"))
+    }
+
+    // A value which must be present in every row
+    fn required(&self, row: &[Option<f64>], column: usize) -> miette::Result<f64> {
+        row.get(column)
+            .copied()
+            .flatten()
+            .ok_or_else(|| miette::miette!("expected a value in column {column} of every row"))
+    }
+
+    fn read_tidy(
+        &self,
+        header: &mut NewFormatHeader,
+        names: &[String],
+        lines: &str,
+        delimiter: u8,
+    ) -> miette::Result<(Vec<ImportedTrace>, Option<DataShape>)> {
+        let column = |name: &str| {
+            names
+                .iter()
+                .skip(5)
+                .position(|each| each == name)
+                .map(|index| index + 5)
+        };
+        let [flags, next_z, noise, scans, w_level, time] =
+            ["flags", "next_z", "noise", "scans", "w_level", "time"].map(column);
+
+        let mut traces: Vec<(f64, Option<f64>, ImportedTrace)> = Vec::new();
+        for row in self.rows(lines, delimiter)? {
+            let index = self.required(&row, 0)?;
+            let z = self.required(&row, 1)?;
+            let x = self.required(&row, 3)?;
+            let y = self.required(&row, 4)?;
+            let w = row.get(2).copied().flatten();
+            let field = |column: Option<usize>| {
+                column.and_then(|column| row.get(column).copied().flatten())
+            };
+
+            if traces.last().is_none_or(|(last, _, _)| *last != index) {
+                let mut trace = ImportedTrace::new(z);
+                let subheader = &mut trace.subheader;
+                if let Some(flags) = field(flags) {
+                    subheader.parameters = SubFlagParameters(flags as u8);
+                }
+                subheader.next_z = field(next_z).unwrap_or_default() as f32;
+                subheader.noise = field(noise).unwrap_or_default() as f32;
+                subheader.scan = field(scans).unwrap_or_default() as u32;
+                subheader.w_level = field(w_level).or(w).unwrap_or_default() as f32;
+                trace.time = field(time);
+                traces.push((index, w, trace));
+            }
+            let (_, _, trace) = traces.last_mut().unwrap();
+            trace.x.push(x);
+            trace.y.push(y);
+        }
+
+        // Without a next_z column, each trace points at the one after it in its plane
+        if next_z.is_none() {
+            for index in 1..traces.len() {
+                if traces[index].1 == traces[index - 1].1 {
+                    traces[index - 1].2.subheader.next_z = traces[index].2.subheader.z;
+                }
+            }
+        }
+
+        // Each run of traces sharing a w-value is a plane
+        if traces.iter().any(|(_, w, _)| w.is_some()) {
+            header.w_planes = 1 + traces
+                .windows(2)
+                .filter(|pair| pair[0].1 != pair[1].1)
+                .count() as u32;
+        }
+        header.flags = header.flags.with_uneven_z(true);
+
+        let traces = traces
+            .into_iter()
+            .map(|(_, _, trace)| trace)
+            .collect::<Vec<_>>();
+        let shape = if traces.iter().any(|trace| trace.time.is_some()) {
+            Some(DataShape::XYXY)
+        } else {
+            None
+        };
+        Ok((traces, shape))
+    }
+
+    fn apply_preamble(
+        &self,
+        header: &mut NewFormatHeader,
+        preamble: &[(String, String)],
+    ) -> miette::Result<()> {
+        for (key, value) in preamble {
+            match key.as_str() {
+                "instrument technique" => {
+                    if let Some(technique) = InstrumentTechnique::from_name(value) {
+                        header.instrument_technique = technique;
+                    }
+                }
+                "x units" => {
+                    header.x_unit_type = xzwType::from_name(value).unwrap_or(header.x_unit_type)
+                }
+                "y units" => {
+                    header.y_unit_type = yType::from_name(value).unwrap_or(header.y_unit_type)
+                }
+                "z units" => {
+                    header.z_unit_type = xzwType::from_name(value).unwrap_or(header.z_unit_type)
+                }
+                "w units" => {
+                    header.w_axis_units = xzwType::from_name(value).unwrap_or(header.w_axis_units)
+                }
+                "collected" => {
+                    if let Ok(datetime) =
+                        NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S UTC")
+                    {
+                        *header = header
+                            .clone()
+                            .with_datetime(Some(Utc.from_utc_datetime(&datetime)));
+                    }
+                }
+                "resolution" => header.resolution_description = Text::new(value),
+                "source instrument" => header.source_instrument_description = Text::new(value),
+                "memo" => header.memo = Text::new(value),
+                "method file" => header.method_file = Text::new(value),
+                "axis labels" => {
+                    let labels = value
+                        .trim_matches(['[', ']'])
+                        .split(", ")
+                        .map(|label| label.trim_matches('"'))
+                        .collect::<Vec<_>>();
+                    if labels.iter().any(|label| !label.is_empty()) {
+                        header.xyz_labels = Text::new(&labels.join("\0"));
+                    }
+                }
+                "custom axis labels" => {
+                    header.flags = header.flags.with_custom_axis_labels(value == "true")
+                }
+                "peak point number" => {
+                    header.peak_point_number = value.parse().into_diagnostic()?
+                }
+                "concentration factor" => {
+                    header.concentration_factor = value.parse().into_diagnostic()?
+                }
+                "sub-method sample injection number" => {
+                    header.sub_method_sample_injection_number = value.parse().into_diagnostic()?
+                }
+                "calibration level" => {
+                    header.calibration_level = value
+                        .parse::<u8>()
+                        .ok()
+                        .and_then(|level| level.checked_add(1))
+                        .unwrap_or(1)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Set the units, or the custom labels, from the names of the x, y and z columns
+    fn apply_labels(&self, header: &mut NewFormatHeader, names: [Option<String>; 3]) {
+        let [x, y, z] = names;
+        let mut custom: [String; 3] = Default::default();
+
+        for (axis, (name, default)) in [x, y, z].into_iter().zip(["x", "y", "z"]).enumerate() {
+            let Some(name) = name.filter(|name| name != default) else {
+                continue;
+            };
+            let unit_found = match axis {
+                0 => xzwType::from_name(&name).map(|unit| header.x_unit_type = unit),
+                1 => yType::from_name(&name).map(|unit| header.y_unit_type = unit),
+                _ => xzwType::from_name(&name).map(|unit| header.z_unit_type = unit),
+            };
+            if unit_found.is_none() {
+                custom[axis] = name;
+            }
+        }
+
+        if custom.iter().any(|label| !label.is_empty()) {
+            header.xyz_labels = Text::new(&custom.join("\0"));
+            header.flags = header.flags.with_custom_axis_labels(true);
+        }
+    }
+
+    fn apply_options(&self, header: &mut NewFormatHeader) {
+        if let Some(unit) = self.x_unit_type {
+            header.x_unit_type = unit;
+        }
+        if let Some(unit) = self.y_unit_type {
+            header.y_unit_type = unit;
+        }
+        if let Some(unit) = self.z_unit_type {
+            header.z_unit_type = unit;
+        }
+        if let Some(unit) = self.w_unit_type {
+            header.w_axis_units = unit;
+        }
+        if let Some(technique) = self.instrument_technique {
+            header.instrument_technique = technique;
+        }
+        if let Some(memo) = &self.memo {
+            header.memo = Text::new(memo);
+        }
+    }
+}
+
+impl ReadSPC for CsvReader {
+    type Error = miette::Report;
+    fn read_spc<R: Read>(&self, reader: &mut R) -> Result<ParsedSPC, Self::Error> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source).into_diagnostic()?;
+        self.read_bytes(&source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::CsvReader;
+    use crate::{
+        parse,
+        text::TextEncoding,
+        write::{CsvLayout, CsvWriter, SpcWriter, WriteSPC},
+        xzwType, yType,
+    };
+
+    // Read a CSV, write it as SPC and export the SPC file to CSV again
+    fn round_trip(source: &str, writer: &CsvWriter) -> String {
+        let imported = CsvReader::new().read(source).unwrap();
+        let mut spc = Vec::new();
+        SpcWriter::new().write_spc(&mut spc, &imported).unwrap();
+
+        let mut exported = Vec::new();
+        writer
+            .write_spc(&mut exported, &parse(&spc).unwrap())
+            .unwrap();
+        String::from_utf8(exported).unwrap()
+    }
+
+    #[test]
+    fn every_layout_round_trips_through_spc() {
+        let wide = CsvWriter::new();
+        let tidy = CsvWriter::new().with_layout(CsvLayout::Tidy);
+        for (source, writer) in [
+            // Evenly and unevenly spaced single traces
+            ("x,y\n100.0,1.0\n150.0,2.0\n200.0,3.0\n", &wide),
+            ("x,y\n1.0,1.0\n1.5,2.0\n4.0,3.0\n", &wide),
+            // Decimals which are not exact in binary
+            ("x,y\n0.1,0.3\n0.2,0.7\n0.5,1.1\n", &wide),
+            // A multifile with a single trace, and one with uneven x
+            ("x,y_0\n100.0,1.0\n200.0,2.0\n", &wide),
+            (
+                "x,y_0,y_1\n1.0,1.0,11.0\n3.0,2.0,12.0\n3.5,0.25,-1.0\n",
+                &wide,
+            ),
+            // XYXY, with and without subfile times
+            (
+                "x,y\n# z = 5.0, time = 1.5\n1.0,2.0\n# z = 6.0, time = 3.0\n1.0,2.0\n3.0,4.0\n",
+                &wide,
+            ),
+            (
+                "x,y\n# z = 5.0\n1.0,2.0\n# z = 6.5\n1.0,2.0\n3.0,4.0\n",
+                &wide,
+            ),
+            // Tidy data in two w-planes, and with subfiles of different lengths
+            (
+                "trace_index,z,w,x,y\n\
+                 0,5.0,1.0,100.0,1.0\n0,5.0,1.0,200.0,2.0\n\
+                 1,6.0,2.0,100.0,11.0\n1,6.0,2.0,200.0,12.0\n",
+                &tidy,
+            ),
+            (
+                "trace_index,z,w,x,y\n0,5.0,,100.0,1.0\n1,7.0,,100.0,11.0\n1,7.0,,150.0,12.0\n",
+                &tidy,
+            ),
+        ] {
+            assert_eq!(round_trip(source, writer), source);
+        }
+
+        // Ragged rows and rows outside any XYXY trace are errors rather than lost points
+        let reader = CsvReader::new();
+        assert!(reader.read("x,y_0,y_1\n1,2,3\n2,4\n").is_err());
+        assert!(reader.read("x,y\n1,2\n# z = 3\n5,6\n").is_err());
+    }
+
+    #[test]
+    fn tables_without_y_columns_are_rejected() {
+        let reader = CsvReader::new();
+        for source in ["x\n1\n2\n", "y\n1\n", "1\n2\n"] {
+            assert!(reader.read(source).is_err());
+        }
+    }
+
+    #[test]
+    fn units_are_read_from_the_preamble_names_and_options() {
+        let source = "# x units: Wavenumber\n\
+                      # memo: dried film\n\
+                      Wavenumber (cm-1);Absorbance\n\
+                      1000,5;0,25\n\
+                      2000,5;0,5\n";
+        let parsed = CsvReader::new()
+            .with_decimal_comma(true)
+            .read(source)
+            .unwrap();
+        let header = parsed.header();
+        assert_eq!(header.x_unit_type(), xzwType::Wavenumber);
+        assert!(matches!(header.y_unit_type(), yType::Absorbance));
+        assert_eq!(header.memo().as_str(), "dried film");
+        assert_eq!(parsed.traces()[0].y(), [0.25, 0.5]);
+
+        let parsed = CsvReader::new()
+            .with_decimal_comma(true)
+            .with_x_unit_type(xzwType::Nanometers)
+            .read(source)
+            .unwrap();
+        assert_eq!(parsed.header().x_unit_type(), xzwType::Nanometers);
+    }
+
+    #[test]
+    fn files_which_are_not_utf8_are_decoded_with_the_encoding() {
+        // A memo written by a western European Windows machine, in Windows-1252
+        let source = b"# memo: s\xe9ch\xe9\nx,y\n1,2\n";
+        let parsed = CsvReader::new().read_bytes(source).unwrap();
+        assert_eq!(parsed.header().memo().as_str(), "s\u{e9}ch\u{e9}");
+
+        let parsed = CsvReader::new()
+            .with_encoding(TextEncoding::Utf8)
+            .read_bytes(source)
+            .unwrap();
+        assert_eq!(parsed.header().memo().as_str(), "s\u{fffd}ch\u{fffd}");
+    }
+}
@@ -0,0 +1,141 @@
+//! Readers which build a [`ParsedSPC`] from files in other formats, so they can be written out as
+//! SPC with the [`SpcWriter`](crate::SpcWriter).
+
+use std::io::Read;
+
+use crate::{
+    block::{Block, Directory, Subfile, XData, YData},
+    header::{even_x_points, DataShape, Header, NewFormatHeader, Subheader},
+    ParsedSPC,
+};
+
+mod delimited;
+mod opus;
+mod shimadzu;
+mod wdf;
+
+pub use delimited::CsvReader;
+pub use opus::{OpusReader, OpusSpectrum};
+pub use shimadzu::ShimadzuReader;
+pub use wdf::WdfReader;
+
+pub trait ReadSPC {
+    type Error;
+    fn read_spc<R: Read>(&self, reader: &mut R) -> Result<ParsedSPC, Self::Error>;
+}
+
+// A trace read from another format, before it is assembled into a block
+pub(crate) struct ImportedTrace {
+    pub(crate) x: Vec<f64>,
+    pub(crate) y: Vec<f64>,
+    pub(crate) subheader: Subheader,
+    pub(crate) time: Option<f64>,
+}
+
+impl ImportedTrace {
+    pub(crate) fn new(z: f64) -> Self {
+        Self {
+            x: Vec::new(),
+            y: Vec::new(),
+            subheader: Subheader {
+                z: z as f32,
+                ..Subheader::empty()
+            },
+            time: None,
+        }
+    }
+}
+
+// Whether the x-values are evenly spaced, so that they are reproduced exactly from the first and
+// last values when they are not stored
+fn evenly_spaced(x: &[f64]) -> bool {
+    x.len() > 1 && even_x_points(x[0], x[x.len() - 1], x.len()) == x
+}
+
+// Build the block from the traces, choosing the most compact shape which stores them exactly. A
+// shape of Y or YY only fixes whether the file is a multifile, and is widened to XY or XYY if the
+// x-values are not evenly spaced
+pub(crate) fn assemble(
+    mut header: NewFormatHeader,
+    traces: Vec<ImportedTrace>,
+    shape: Option<DataShape>,
+) -> ParsedSPC {
+    let x = traces
+        .first()
+        .map(|trace| trace.x.clone())
+        .unwrap_or_default();
+    let shared_x = traces.iter().all(|trace| trace.x == x);
+    let even = evenly_spaced(&x);
+    let shape = match shape {
+        Some(DataShape::Y) if even => DataShape::Y,
+        Some(DataShape::Y) => DataShape::XY,
+        Some(DataShape::YY) if even => DataShape::YY,
+        Some(DataShape::YY) => DataShape::XYY,
+        Some(shape) => shape,
+        None if !shared_x => DataShape::XYXY,
+        None if traces.len() == 1 && even => DataShape::Y,
+        None if traces.len() == 1 => DataShape::XY,
+        None if even => DataShape::YY,
+        None => DataShape::XYY,
+    };
+
+    header.spectra = traces.len() as u32;
+    header.starting_x = x.first().copied().unwrap_or_default();
+    header.ending_x = x.last().copied().unwrap_or_default();
+    header.number_points = match shape {
+        DataShape::XYXY => 0,
+        _ => x.len() as u32,
+    };
+
+    let times = traces.iter().map(|trace| trace.time).collect::<Vec<_>>();
+    let x_data = |x: &[f64]| XData::new(x.iter().map(|&x| x as f32).collect());
+    let mut subfiles = traces
+        .into_iter()
+        .enumerate()
+        .map(|(index, trace)| {
+            let mut subheader = trace.subheader;
+            subheader.index_number = index as u16;
+            let subfile = Subfile {
+                subheader,
+                data: YData::Float(trace.y.iter().map(|&y| y as f32 as f64).collect()),
+            };
+            (x_data(&trace.x), subfile)
+        })
+        .collect::<Vec<_>>();
+
+    let block = match shape {
+        DataShape::Y => Block::Y(subfiles.remove(0).1),
+        DataShape::XY => {
+            let (x, y) = subfiles.remove(0);
+            Block::XY { x, y }
+        }
+        DataShape::YY => Block::YY(subfiles.into_iter().map(|(_, subfile)| subfile).collect()),
+        DataShape::XYY => Block::XYY {
+            x: x_data(&x),
+            ys: subfiles.into_iter().map(|(_, subfile)| subfile).collect(),
+        },
+        DataShape::XYXY => {
+            for (_, subfile) in &mut subfiles {
+                subfile.subheader.number_points = subfile.data.len() as u32;
+            }
+            // The positions and sizes in the directory are filled in when the file is written
+            let directory = times.iter().any(Option::is_some).then(|| {
+                times
+                    .iter()
+                    .map(|time| Directory::new(0, 0, time.unwrap_or_default() as f32))
+                    .collect()
+            });
+            Block::XYXY {
+                data: subfiles,
+                directory,
+            }
+        }
+    };
+    header.flags = header.flags.with_shape(shape);
+
+    ParsedSPC {
+        header: Header::New(header),
+        block,
+        log: None,
+    }
+}
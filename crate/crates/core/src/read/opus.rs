@@ -0,0 +1,452 @@
+//! Reading the binary files written by Bruker OPUS.
+//!
+//! An OPUS file starts with a directory of blocks. Each block is described by a four byte type, its
+//! length in four byte words and its offset. In the type:
+//! - bits 2 and 3 of the first byte tell sample (1), reference (2) and ratioed (3) spectra apart,
+//!   and bit 4 marks the data status parameters describing a data block of the same type;
+//! - the second byte, shifted right by two, is the kind of data, such as 4 for absorbance.
+//!
+//! Parameter blocks are a list of entries, each with a three letter name, a type, a size in two
+//! byte words and a value, ending with `END`. The data block holds the y-values as 32-bit floats,
+//! or as 32-bit integers when the data point format `DPF` is 2, and the x-values are spread evenly
+//! from `FXV` to `LXV` over `NPT` points.
+
+use std::{collections::BTreeMap, io::Read};
+
+use chrono::{FixedOffset, NaiveDate, NaiveTime, TimeZone};
+use miette::IntoDiagnostic;
+
+use crate::{
+    header::{even_x_points, NewFormatHeader},
+    text::Text,
+    xzwType, yType, InstrumentTechnique, ParsedSPC,
+};
+
+use super::{assemble, ImportedTrace, ReadSPC};
+
+const OPUS_MAGIC: [u8; 4] = [0x0a, 0x0a, 0xfe, 0xfe];
+
+/// The kinds of spectrum stored in the data blocks of an OPUS file
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OpusSpectrum {
+    Absorbance,
+    Transmittance,
+    KubelkaMunk,
+    Reflectance,
+    Raman,
+    /// The single-channel spectrum of the sample
+    SampleSingleChannel,
+    /// The single-channel spectrum of the reference
+    ReferenceSingleChannel,
+}
+
+impl OpusSpectrum {
+    // The order in which spectra are looked for when none is chosen, processed spectra first
+    const PREFERENCE: [Self; 7] = [
+        Self::Absorbance,
+        Self::Transmittance,
+        Self::KubelkaMunk,
+        Self::Reflectance,
+        Self::Raman,
+        Self::SampleSingleChannel,
+        Self::ReferenceSingleChannel,
+    ];
+
+    // The channel, from bits 2 and 3 of the first byte of the block type, and the kind of data,
+    // from the second byte
+    fn block_type(self) -> (u8, u8) {
+        match self {
+            Self::Absorbance => (3, 4),
+            Self::Transmittance => (3, 5),
+            Self::KubelkaMunk => (3, 6),
+            Self::Reflectance => (3, 12),
+            Self::Raman => (1, 10),
+            Self::SampleSingleChannel => (1, 1),
+            Self::ReferenceSingleChannel => (2, 1),
+        }
+    }
+
+    fn y_unit_type(self) -> yType {
+        match self {
+            Self::Absorbance => yType::Absorbance,
+            Self::Transmittance => yType::Transmission,
+            Self::KubelkaMunk => yType::KubelkaMonk,
+            Self::Reflectance => yType::Reflectance,
+            Self::Raman | Self::SampleSingleChannel | Self::ReferenceSingleChannel => {
+                yType::ArbitraryIntensity
+            }
+        }
+    }
+}
+
+/// Reads Bruker OPUS files into a [`ParsedSPC`]
+///
+/// Every data block holding the chosen spectrum becomes one trace. If no spectrum is chosen, the
+/// first of absorbance, transmittance, Kubelka-Munk, reflectance, Raman and the sample and
+/// reference single channels present in the file is read.
+#[derive(Clone, Debug, Default)]
+pub struct OpusReader {
+    spectrum: Option<OpusSpectrum>,
+}
+
+// An entry of the directory
+#[derive(Copy, Clone, Debug)]
+struct BlockEntry {
+    block_type: [u8; 4],
+    offset: usize,
+    length: usize,
+}
+
+impl BlockEntry {
+    fn is_parameters(&self) -> bool {
+        self.block_type[0] & 0xf0 != 0
+    }
+
+    fn is_data(&self, spectrum: OpusSpectrum) -> bool {
+        let [first, second, ..] = self.block_type;
+        let (channel, kind) = spectrum.block_type();
+        first & 0xf0 == 0 && (first >> 2) & 0b11 == channel && second >> 2 == kind
+    }
+
+    // Whether this block holds the data status parameters of `data`
+    fn describes(&self, data: &BlockEntry) -> bool {
+        self.block_type[0] == data.block_type[0] | 0x10
+            && self.block_type[1..3] == data.block_type[1..3]
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Parameter {
+    Integer(i32),
+    Float(f64),
+    Text(String),
+}
+
+impl Parameter {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Integer(value) => Some(*value as f64),
+            Self::Float(value) => Some(*value),
+            Self::Text(_) => None,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Text(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+type Parameters = BTreeMap<String, Parameter>;
+
+fn u32_at(source: &[u8], offset: usize) -> miette::Result<u32> {
+    source
+        .get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| miette::miette!("the file ends inside the directory"))
+}
+
+// The entries of a parameter block. Reading stops at `END`, or at the first entry which does not
+// fit in the block
+fn read_parameters(block: &[u8]) -> Parameters {
+    let mut parameters = Parameters::new();
+    let mut cursor = 0;
+    while let Some(entry) = block.get(cursor..cursor + 8) {
+        let name = String::from_utf8_lossy(&entry[..3]).into_owned();
+        if name == "END" {
+            break;
+        }
+        let kind = u16::from_le_bytes([entry[4], entry[5]]);
+        let size = 2 * u16::from_le_bytes([entry[6], entry[7]]) as usize;
+        let Some(value) = block.get(cursor + 8..cursor + 8 + size) else {
+            break;
+        };
+        let value = match (kind, value.len()) {
+            (0, 4..) => Parameter::Integer(i32::from_le_bytes(value[..4].try_into().unwrap())),
+            (0, 2..) => {
+                Parameter::Integer(i16::from_le_bytes(value[..2].try_into().unwrap()) as i32)
+            }
+            (1, 8..) => Parameter::Float(f64::from_le_bytes(value[..8].try_into().unwrap())),
+            _ => {
+                let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+                Parameter::Text(String::from_utf8_lossy(&value[..end]).into_owned())
+            }
+        };
+        parameters.insert(name, value);
+        cursor += 8 + size;
+    }
+    parameters
+}
+
+// The collection time from the `DAT` and `TIM` parameters, such as `19/03/2021` and
+// `14:32:10.123 (GMT+1)`
+fn collection_time(parameters: &Parameters) -> Option<chrono::DateTime<FixedOffset>> {
+    let date = NaiveDate::parse_from_str(parameters.get("DAT")?.as_str()?, "%d/%m/%Y").ok()?;
+    let time = parameters.get("TIM")?.as_str()?;
+    let (time, zone) = time.split_once(' ').unwrap_or((time, ""));
+    let time = NaiveTime::parse_from_str(time, "%H:%M:%S%.f").ok()?;
+    let hours = zone
+        .trim_matches(|c| c == '(' || c == ')')
+        .strip_prefix("GMT")
+        .and_then(|hours| hours.parse::<i32>().ok())
+        .unwrap_or_default();
+    FixedOffset::east_opt(hours * 3600)?
+        .from_local_datetime(&date.and_time(time))
+        .single()
+}
+
+fn x_unit_type(name: &str) -> xzwType {
+    match name {
+        "WN" => xzwType::Wavenumber,
+        "MI" => xzwType::Micrometers,
+        "NM" => xzwType::Nanometers,
+        "MIN" => xzwType::Minutes,
+        "SEC" => xzwType::Seconds,
+        "PNT" => xzwType::DataPoints,
+        _ => xzwType::Arbitrary,
+    }
+}
+
+impl OpusReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the given spectrum, rather than the first one found
+    pub fn with_spectrum(mut self, spectrum: OpusSpectrum) -> Self {
+        self.spectrum = Some(spectrum);
+        self
+    }
+
+    /// Whether the source starts like an OPUS file
+    pub fn is_opus(source: &[u8]) -> bool {
+        source.starts_with(&OPUS_MAGIC)
+    }
+
+    pub fn read(&self, source: &[u8]) -> miette::Result<ParsedSPC> {
+        if !Self::is_opus(source) {
+            miette::bail!("not an OPUS file: the file does not start with 0a 0a fe fe");
+        }
+        let directory = u32_at(source, 12)? as usize;
+        let entries = (0..u32_at(source, 20)? as usize)
+            .map(|index| {
+                let start = directory + 12 * index;
+                Ok(BlockEntry {
+                    block_type: u32_at(source, start)?.to_le_bytes(),
+                    length: 4 * u32_at(source, start + 4)? as usize,
+                    offset: u32_at(source, start + 8)? as usize,
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+        let block = |entry: &BlockEntry| {
+            source
+                .get(entry.offset..entry.offset + entry.length)
+                .ok_or_else(|| miette::miette!("block at {} runs past the file", entry.offset))
+        };
+
+        let spectrum = match self.spectrum {
+            Some(spectrum) => spectrum,
+            None => *OpusSpectrum::PREFERENCE
+                .iter()
+                .find(|&&spectrum| entries.iter().any(|entry| entry.is_data(spectrum)))
+                .ok_or_else(|| miette::miette!("the file does not contain any spectra"))?,
+        };
+
+        // Parameters which describe the whole measurement, such as the sample name
+        let mut shared = Parameters::new();
+        for entry in entries.iter().filter(|entry| entry.is_parameters()) {
+            for (name, value) in read_parameters(block(entry)?) {
+                shared.entry(name).or_insert(value);
+            }
+        }
+
+        let mut traces = Vec::new();
+        let mut x_unit = xzwType::Arbitrary;
+        let mut collected = None;
+        for data in entries.iter().filter(|entry| entry.is_data(spectrum)) {
+            let Some(status) = entries.iter().find(|entry| entry.describes(data)) else {
+                miette::bail!("the {spectrum:?} block has no data status parameters");
+            };
+            let parameters = read_parameters(block(status)?);
+            let value = |name: &str| {
+                parameters
+                    .get(name)
+                    .and_then(Parameter::as_f64)
+                    .ok_or_else(|| miette::miette!("the {spectrum:?} block has no {name}"))
+            };
+            let number_points = value("NPT")? as usize;
+            let scale = value("CSF").unwrap_or(1.0);
+            let decode: fn([u8; 4]) -> f64 = match value("DPF").unwrap_or(1.0) {
+                1.0 => |y| f32::from_le_bytes(y) as f64,
+                2.0 => |y| i32::from_le_bytes(y) as f64,
+                format => miette::bail!(
+                    "the {spectrum:?} block has the unknown data point format {format}"
+                ),
+            };
+
+            let values = block(data)?;
+            if number_points
+                .checked_mul(4)
+                .is_none_or(|bytes| values.len() < bytes)
+            {
+                miette::bail!("the {spectrum:?} block holds fewer than {number_points} points");
+            }
+            let mut trace = ImportedTrace::new(traces.len() as f64);
+            trace.x = even_x_points(value("FXV")?, value("LXV")?, number_points);
+            trace.y = values
+                .chunks_exact(4)
+                .take(number_points)
+                .map(|y| scale * decode(y.try_into().unwrap()))
+                .collect();
+            if let Some(scans) = shared.get("NSS").and_then(Parameter::as_f64) {
+                trace.subheader.scan = scans as u32;
+            }
+            traces.push(trace);
+
+            if let Some(unit) = parameters.get("DXU").and_then(Parameter::as_str) {
+                x_unit = x_unit_type(unit);
+            }
+            collected = collected.or_else(|| collection_time(&parameters));
+        }
+
+        let mut header = NewFormatHeader::empty().with_datetime(collected);
+        header.instrument_technique = InstrumentTechnique::FTIRFTNIRFTRaman;
+        header.x_unit_type = x_unit;
+        header.y_unit_type = spectrum.y_unit_type();
+        let text = |name: &str| shared.get(name).and_then(Parameter::as_str).map(Text::new);
+        if let Some(memo) = text("SNM") {
+            header.memo = memo;
+        }
+        if let Some(instrument) = text("INS") {
+            header.source_instrument_description = instrument;
+        }
+        if let Some(resolution) = shared.get("RES").and_then(Parameter::as_f64) {
+            header.resolution_description = Text::new(&resolution.to_string());
+        }
+
+        Ok(assemble(header, traces, None))
+    }
+}
+
+impl ReadSPC for OpusReader {
+    type Error = miette::Report;
+    fn read_spc<R: Read>(&self, reader: &mut R) -> Result<ParsedSPC, Self::Error> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source).into_diagnostic()?;
+        self.read(&source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OpusReader, OpusSpectrum};
+    use crate::{xzwType, yType, Header};
+
+    fn parameter(name: &str, kind: u16, value: &[u8]) -> Vec<u8> {
+        let mut entry = format!("{name}\0").into_bytes();
+        entry.extend_from_slice(&kind.to_le_bytes());
+        entry.extend_from_slice(&(value.len() as u16 / 2).to_le_bytes());
+        entry.extend_from_slice(value);
+        entry
+    }
+
+    // An OPUS file holding the given blocks, after a header and directory of 24 and 36 bytes
+    fn opus(blocks: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut source = vec![0x0a, 0x0a, 0xfe, 0xfe];
+        source.extend_from_slice(&920622.0f64.to_le_bytes());
+        source.extend_from_slice(&24u32.to_le_bytes());
+        source.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        source.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        let mut offset = 24 + 12 * blocks.len();
+        let mut body = Vec::new();
+        for (block_type, block) in blocks {
+            source.extend_from_slice(block_type);
+            source.extend_from_slice(&(block.len() as u32 / 4).to_le_bytes());
+            source.extend_from_slice(&(offset as u32).to_le_bytes());
+            body.extend_from_slice(block);
+            offset += block.len();
+        }
+        source.append(&mut body);
+        source
+    }
+
+    #[test]
+    fn absorbance_is_read_with_its_parameters() {
+        let mut status = [
+            parameter("DPF", 0, &1i32.to_le_bytes()),
+            parameter("NPT", 0, &3i32.to_le_bytes()),
+            parameter("FXV", 1, &4000.0f64.to_le_bytes()),
+            parameter("LXV", 1, &3998.0f64.to_le_bytes()),
+            parameter("CSF", 1, &2.0f64.to_le_bytes()),
+            parameter("DXU", 2, b"WN\0\0"),
+            parameter("DAT", 2, b"19/03/2021\0\0"),
+            parameter("TIM", 2, b"14:32:10.500 (GMT+1)\0\0\0\0"),
+        ]
+        .concat();
+        status.extend_from_slice(b"END\0\0\0\0\0");
+        let mut sample = parameter("SNM", 2, b"polystyrene\0");
+        sample.extend_from_slice(b"END\0\0\0\0\0");
+        let data = [0.25f32, 0.5, 1.0]
+            .iter()
+            .flat_map(|y| y.to_le_bytes())
+            .collect::<Vec<_>>();
+        let single_channel = vec![0; 12];
+        let source = opus(&[
+            ([0x07, 0x04, 0x00, 0x00], single_channel),
+            ([0x0f, 0x10, 0x00, 0x00], data),
+            ([0x1f, 0x10, 0x00, 0x00], status.clone()),
+            ([0xa0, 0x00, 0x00, 0x00], sample),
+        ]);
+
+        let parsed = OpusReader::new().read(&source).unwrap();
+        let Header::New(header) = parsed.header() else {
+            panic!("expected a new-format header");
+        };
+        assert!(matches!(header.x_unit_type(), xzwType::Wavenumber));
+        assert!(matches!(header.y_unit_type(), yType::Absorbance));
+        assert_eq!(header.memo().to_string(), "polystyrene");
+        assert_eq!(
+            header.datetime().unwrap().to_rfc3339(),
+            "2021-03-19T13:32:10.500+00:00"
+        );
+
+        let traces = parsed.traces();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].x(), [4000.0, 3999.0, 3998.0]);
+        assert_eq!(traces[0].y(), [0.5, 1.0, 2.0]);
+
+        // Integer data points are decoded as integers, and unknown formats are rejected
+        let with_format = |format: i32, data: Vec<u8>| {
+            let mut status = status.clone();
+            status[8..12].copy_from_slice(&format.to_le_bytes());
+            OpusReader::new().read(&opus(&[
+                ([0x0f, 0x10, 0x00, 0x00], data),
+                ([0x1f, 0x10, 0x00, 0x00], status),
+            ]))
+        };
+        let integers = [1i32, -2, 3].iter().flat_map(|y| y.to_le_bytes()).collect();
+        let parsed = with_format(2, integers).unwrap();
+        assert_eq!(parsed.traces()[0].y(), [2.0, -4.0, 6.0]);
+        assert!(with_format(3, vec![0; 12]).is_err());
+
+        // Blocks of no points or a single point have no spacing between their x-values
+        let with_points = |points: i32| {
+            let mut status = status.clone();
+            status[20..24].copy_from_slice(&points.to_le_bytes());
+            OpusReader::new().read(&opus(&[
+                ([0x0f, 0x10, 0x00, 0x00], vec![0; 12]),
+                ([0x1f, 0x10, 0x00, 0x00], status),
+            ]))
+        };
+        assert!(with_points(0).unwrap().traces()[0].x().is_empty());
+        assert_eq!(with_points(1).unwrap().traces()[0].x(), [4000.0]);
+
+        // The single channel has no data status parameters to describe it
+        assert!(OpusReader::new()
+            .with_spectrum(OpusSpectrum::SampleSingleChannel)
+            .read(&source)
+            .is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! Reading the `.spc` files written by Shimadzu UV-Vis software such as UVProbe and LabSolutions.
+//!
+//! These are OLE2 compound documents rather than Galactic SPC files. Each data set is a storage
+//! holding a `DataSpectrumStorage/Data` storage, whose `X Data.1` and `Y Data.1` streams hold the
+//! wavelengths and absorbances as little-endian doubles. Every data set becomes one trace.
+
+use std::{
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Utc};
+use miette::{Context, IntoDiagnostic};
+
+use crate::{header::NewFormatHeader, text::Text, xzwType, yType, InstrumentTechnique, ParsedSPC};
+
+use super::{assemble, ImportedTrace, ReadSPC};
+
+/// Reads Shimadzu UV-Vis `.spc` files into a [`ParsedSPC`], with wavelengths in nanometers and
+/// absorbance on the y-axis
+#[derive(Clone, Debug, Default)]
+pub struct ShimadzuReader {}
+
+// The streams holding the x and y-values of one data set
+struct DataSet {
+    name: String,
+    x: PathBuf,
+    y: PathBuf,
+    created: Option<DateTime<Utc>>,
+}
+
+impl ShimadzuReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&self, source: &[u8]) -> miette::Result<ParsedSPC> {
+        let mut document = cfb::CompoundFile::open(Cursor::new(source))
+            .into_diagnostic()
+            .wrap_err("failed to open the OLE2 compound document")?;
+
+        let data_sets = document
+            .walk()
+            .filter(|entry| entry.is_stream() && entry.name().starts_with("X Data"))
+            .filter_map(|entry| {
+                let parent = entry.path().parent()?;
+                let y = parent.join(entry.name().replacen("X Data", "Y Data", 1));
+                // Only storages record a creation time, so it is read from the nearest storage
+                // holding the stream which has one. Those without hold a zero FILETIME
+                let created = parent
+                    .ancestors()
+                    .filter_map(|storage| document.entry(storage).ok())
+                    .map(|storage| DateTime::<Utc>::from(storage.created()))
+                    .find(|created| created.timestamp() > 0);
+                Some(DataSet {
+                    name: data_set_name(entry.path()),
+                    x: entry.path().to_path_buf(),
+                    y,
+                    created,
+                })
+            })
+            .filter(|data_set| document.is_stream(&data_set.y))
+            .collect::<Vec<_>>();
+        if data_sets.is_empty() {
+            miette::bail!("the document does not contain any X Data and Y Data streams");
+        }
+
+        let mut traces = Vec::new();
+        for data_set in &data_sets {
+            let mut trace = ImportedTrace::new(traces.len() as f64);
+            trace.x = read_doubles(&mut document, &data_set.x)?;
+            trace.y = read_doubles(&mut document, &data_set.y)?;
+            if trace.x.len() != trace.y.len() {
+                miette::bail!(
+                    "data set '{}' has {} x-values but {} y-values",
+                    data_set.name,
+                    trace.x.len(),
+                    trace.y.len()
+                );
+            }
+            traces.push(trace);
+        }
+
+        let mut header = NewFormatHeader::empty()
+            .with_datetime(data_sets.iter().find_map(|data_set| data_set.created));
+        header.instrument_technique = InstrumentTechnique::UVVISSpectrum;
+        header.x_unit_type = xzwType::Nanometers;
+        header.y_unit_type = yType::Absorbance;
+        header.memo = Text::new(
+            &data_sets
+                .iter()
+                .map(|data_set| data_set.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        );
+
+        Ok(assemble(header, traces, None))
+    }
+}
+
+impl ReadSPC for ShimadzuReader {
+    type Error = miette::Report;
+    fn read_spc<R: Read>(&self, reader: &mut R) -> Result<ParsedSPC, Self::Error> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source).into_diagnostic()?;
+        self.read(&source)
+    }
+}
+
+// The name of the storage holding the `DataSpectrumStorage` of a data set, or of the storage
+// holding the stream if the layout is unfamiliar
+fn data_set_name(stream: &Path) -> String {
+    let components = stream
+        .iter()
+        .map(|component| component.to_string_lossy())
+        .collect::<Vec<_>>();
+    let index = components
+        .iter()
+        .position(|component| component == "DataSpectrumStorage")
+        .unwrap_or(components.len().saturating_sub(1));
+    components
+        .get(index.wrapping_sub(1))
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
+
+fn read_doubles<F: Read + std::io::Seek>(
+    document: &mut cfb::CompoundFile<F>,
+    path: &Path,
+) -> miette::Result<Vec<f64>> {
+    let mut bytes = Vec::new();
+    document
+        .open_stream(path)
+        .and_then(|mut stream| stream.read_to_end(&mut bytes))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("failed to read the stream '{}'", path.display()))?;
+    if bytes.len() % 8 != 0 {
+        miette::bail!(
+            "the stream '{}' is not a whole number of doubles",
+            path.display()
+        );
+    }
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use super::ShimadzuReader;
+    use crate::{parse, xzwType, yType, Header, InstrumentTechnique};
+
+    fn document(data_sets: &[(&str, &[f64], &[f64])]) -> Vec<u8> {
+        let mut document = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        for (name, x, y) in data_sets {
+            let data = format!("/DataStorage1/DataSetGroup/{name}/DataSpectrumStorage/Data");
+            document.create_storage_all(&data).unwrap();
+            for (stream, values) in [("X Data.1", x), ("Y Data.1", y)] {
+                let mut stream = document.create_stream(format!("{data}/{stream}")).unwrap();
+                for value in values.iter() {
+                    stream.write_all(&value.to_le_bytes()).unwrap();
+                }
+            }
+        }
+        document.flush().unwrap();
+        document.into_inner().into_inner()
+    }
+
+    #[test]
+    fn data_sets_are_read_as_uv_vis_traces() {
+        let source = document(&[
+            ("DataSet1", &[400.0, 401.0, 402.5], &[0.25, 0.5, 0.75]),
+            ("DataSet2", &[400.0, 401.0, 402.5], &[0.0, 0.5, 1.0]),
+        ]);
+        assert!(ShimadzuReader::new().read(&source).is_ok());
+
+        // Shimadzu files are read by `parse` like any other SPC file
+        let parsed = parse(&source).unwrap();
+        let Header::New(header) = parsed.header() else {
+            panic!("expected a new-format header");
+        };
+        assert!(matches!(
+            header.instrument_technique(),
+            InstrumentTechnique::UVVISSpectrum
+        ));
+        assert!(matches!(header.x_unit_type(), xzwType::Nanometers));
+        assert!(matches!(header.y_unit_type(), yType::Absorbance));
+
+        let traces = parsed.traces();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0].x(), [400.0, 401.0, 402.5]);
+        assert_eq!(traces[1].y(), [0.0, 0.5, 1.0]);
+        assert_eq!(header.memo().to_string(), "DataSet1, DataSet2");
+
+        // The streams have no creation time, so it comes from the storages holding them
+        assert!(header.datetime().is_some());
+    }
+}
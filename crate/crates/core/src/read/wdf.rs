@@ -0,0 +1,412 @@
+//! Reading the WDF files written by Renishaw WiRE for Raman spectra, series and maps.
+//!
+//! A WDF file is a list of blocks, each starting with a four letter name, an id and its size in
+//! bytes including the 16 byte block header. The blocks read are:
+//! - `WDF1`, the file header, with the number of points in each spectrum, the number of spectra
+//!   the file has room for and how many were collected, the laser wavenumber and the title;
+//! - `DATA`, every spectrum one after the other as 32-bit floats;
+//! - `XLST`, the x-values shared by every spectrum, preceded by their type and units;
+//! - `YLST`, the positions of the rows of the detector, in the same layout as `XLST`;
+//! - `ORGN`, lists of values with one entry for each spectrum the file has room for, such as the
+//!   stage position or time at which it was collected. The list flagged as primary is the axis
+//!   the series or map was collected along;
+//! - `WMAP`, the start, step and number of points along each axis of a map.
+//!
+//! Each collected spectrum becomes a trace, with its z-value from the primary origin list. The
+//! remaining origin lists, such as the map coordinates, the map area and the y-list are kept as
+//! entries in the log.
+
+use std::{fmt::Write, io::Read};
+
+use miette::IntoDiagnostic;
+
+use crate::{
+    header::NewFormatHeader, logblock::LogBlock, text::Text, xzwType, yType, InstrumentTechnique,
+    ParsedSPC,
+};
+
+use super::{assemble, ImportedTrace, ReadSPC};
+
+// The origin list data type holding the collection time of each spectrum, as a FILETIME
+const ORIGIN_TIME: u32 = 11;
+// Set on the data type of the origin list the measurement was collected along
+const ORIGIN_PRIMARY: u32 = 1 << 31;
+// The number of 100 ns FILETIME ticks in a second
+const FILETIME_TICKS: f64 = 1e7;
+
+/// Reads Renishaw WDF files into a [`ParsedSPC`], with one trace for each spectrum
+#[derive(Clone, Debug, Default)]
+pub struct WdfReader {}
+
+// A list of values with one entry for each spectrum, from the ORGN block
+struct Origin {
+    primary: bool,
+    unit: u32,
+    name: String,
+    values: Vec<f64>,
+}
+
+// The `len` bytes at `offset`, where the offset may be past the end of the source or too large to
+// address
+fn bytes_at(source: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    source.get(offset..offset.checked_add(len)?)
+}
+
+fn u32_at(source: &[u8], offset: usize) -> miette::Result<u32> {
+    bytes_at(source, offset, 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| miette::miette!("the file ends unexpectedly at byte {offset}"))
+}
+
+fn f32_at(source: &[u8], offset: usize) -> miette::Result<f32> {
+    u32_at(source, offset).map(f32::from_bits)
+}
+
+fn u64_at(source: &[u8], offset: usize) -> miette::Result<u64> {
+    bytes_at(source, offset, 8)
+        .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| miette::miette!("the file ends unexpectedly at byte {offset}"))
+}
+
+fn text_at(source: &[u8], offset: usize, len: usize) -> String {
+    let bytes = bytes_at(source, offset, len).unwrap_or_default();
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+// The units of an XLST or ORGN list
+fn unit_type(unit: u32) -> xzwType {
+    match unit {
+        1 => xzwType::RamanShift,
+        2 => xzwType::Wavenumber,
+        3 => xzwType::Nanometers,
+        4 => xzwType::ElectronVolt,
+        5 => xzwType::Micrometers,
+        8 => xzwType::Millimeters,
+        9 => xzwType::Meters,
+        10 => xzwType::TemperatureK,
+        12 | 24 => xzwType::Seconds,
+        13 => xzwType::Milliseconds,
+        14 => xzwType::Hours,
+        15 => xzwType::Days,
+        16 => xzwType::DataPoints,
+        19 => xzwType::Degrees,
+        21 => xzwType::TemperatureC,
+        22 => xzwType::TemperatureF,
+        25 => xzwType::Microseconds,
+        _ => xzwType::Arbitrary,
+    }
+}
+
+impl WdfReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether the source starts like a WDF file
+    pub fn is_wdf(source: &[u8]) -> bool {
+        source.starts_with(b"WDF1")
+    }
+
+    pub fn read(&self, source: &[u8]) -> miette::Result<ParsedSPC> {
+        if !Self::is_wdf(source) {
+            miette::bail!("not a WDF file: the file does not start with WDF1");
+        }
+
+        // The start and end of the first block with each name
+        let mut blocks = Vec::<(&[u8], usize, usize)>::new();
+        let mut offset = 0;
+        while let (Some(name), Ok(size)) = (bytes_at(source, offset, 4), u64_at(source, offset + 8))
+        {
+            let end = usize::try_from(size)
+                .ok()
+                .filter(|&size| size >= 16)
+                .and_then(|size| offset.checked_add(size))
+                .filter(|&end| end <= source.len());
+            let Some(end) = end else {
+                break;
+            };
+            if !blocks.iter().any(|(existing, _, _)| *existing == name) {
+                blocks.push((name, offset, end));
+            }
+            offset = end;
+        }
+        let block = |name: &[u8]| {
+            blocks
+                .iter()
+                .find(|(existing, _, _)| *existing == name)
+                .map(|&(_, start, end)| &source[start..end])
+        };
+        let required = |name: &'static str| {
+            block(name.as_bytes())
+                .ok_or_else(|| miette::miette!("the file does not contain a {name} block"))
+        };
+
+        let file_header = required("WDF1")?;
+        let points = u32_at(file_header, 60)? as usize;
+        let capacity = u64_at(file_header, 64)?;
+        let count = u64_at(file_header, 72)?;
+        if count > capacity {
+            miette::bail!(
+                "{count} spectra were collected, but the file only has room for {capacity}"
+            );
+        }
+        let capacity = usize::try_from(capacity).into_diagnostic()?;
+        let count = count as usize;
+        let y_points = u32_at(file_header, 84)? as usize;
+        let laser_wavenumber = f32_at(file_header, 156)?;
+        let title = text_at(file_header, 240, 160);
+
+        let x_list = required("XLST")?;
+        let x_unit = unit_type(u32_at(x_list, 20)?);
+        let x = (0..points)
+            .map(|index| f32_at(x_list, 24 + 4 * index).map(f64::from))
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        let data = required("DATA")?;
+        let end = points
+            .checked_mul(count)
+            .and_then(|values| values.checked_mul(4))
+            .and_then(|bytes| bytes.checked_add(16))
+            .filter(|&end| end <= data.len())
+            .ok_or_else(|| {
+                miette::miette!(
+                    "the DATA block holds fewer than {count} spectra of {points} points"
+                )
+            })?;
+        let mut traces = data[16..end]
+            .chunks_exact(4 * points.max(1))
+            .enumerate()
+            .map(|(index, spectrum)| {
+                let mut trace = ImportedTrace::new(index as f64);
+                trace.x = x.clone();
+                trace.y = spectrum
+                    .chunks_exact(4)
+                    .map(|y| f32::from_le_bytes(y.try_into().unwrap()) as f64)
+                    .collect();
+                trace
+            })
+            .collect::<Vec<_>>();
+
+        let origins = match block(b"ORGN") {
+            Some(block) => read_origins(block, capacity, count)?,
+            None => Vec::new(),
+        };
+
+        let mut header = NewFormatHeader::empty();
+        header.instrument_technique = InstrumentTechnique::RamanSpectrum;
+        header.x_unit_type = x_unit;
+        header.y_unit_type = yType::Counts;
+        header.memo = Text::new(&title);
+
+        // The z-axis is the primary origin list, such as the time of each spectrum in a series
+        if let Some(primary) = origins.iter().find(|origin| origin.primary) {
+            header.z_unit_type = unit_type(primary.unit);
+            for (trace, &z) in traces.iter_mut().zip(&primary.values) {
+                trace.subheader.z = z as f32;
+            }
+        }
+        if traces.len() > 1 {
+            for index in 1..traces.len() {
+                traces[index - 1].subheader.next_z = traces[index].subheader.z;
+            }
+            header.flags = header.flags.with_uneven_z(true);
+        }
+
+        let mut log = String::new();
+        writeln!(log, "laser_wavenumber={laser_wavenumber}").unwrap();
+        if let Some(map) = block(b"WMAP") {
+            let axis = |offset: usize, read: fn(&[u8], usize) -> miette::Result<String>| {
+                (0..3)
+                    .map(|index| read(map, offset + 4 * index))
+                    .collect::<miette::Result<Vec<_>>>()
+                    .map(|values| values.join(", "))
+            };
+            let float = |map: &[u8], offset| f32_at(map, offset).map(|value| value.to_string());
+            let integer = |map: &[u8], offset| u32_at(map, offset).map(|value| value.to_string());
+            writeln!(log, "map_start={}", axis(24, float)?).unwrap();
+            writeln!(log, "map_step={}", axis(36, float)?).unwrap();
+            writeln!(log, "map_size={}", axis(48, integer)?).unwrap();
+        }
+        if let Some(y_list) = block(b"YLST") {
+            let y_unit = unit_type(u32_at(y_list, 20)?);
+            writeln!(log, "y_list_units={}", y_unit.label()).unwrap();
+            for index in 0..y_points {
+                let y = f32_at(y_list, 24 + 4 * index)?;
+                writeln!(log, "y_list[{index}]={y}").unwrap();
+            }
+        }
+        for origin in origins.iter().filter(|origin| !origin.primary) {
+            for (index, value) in origin.values.iter().enumerate() {
+                writeln!(log, "{}[{index}]={value}", origin.name).unwrap();
+            }
+        }
+
+        let mut parsed = assemble(header, traces, None);
+        parsed.log = Some(LogBlock::new(&log));
+        Ok(parsed)
+    }
+}
+
+impl ReadSPC for WdfReader {
+    type Error = miette::Report;
+    fn read_spc<R: Read>(&self, reader: &mut R) -> Result<ParsedSPC, Self::Error> {
+        let mut source = Vec::new();
+        reader.read_to_end(&mut source).into_diagnostic()?;
+        self.read(&source)
+    }
+}
+
+// The origin lists of the ORGN block. Each is a data type, units, a 16 byte name and a value for
+// every spectrum the file has room for, stored as doubles except for times, which are FILETIMEs.
+// Only the values of the `count` collected spectra are kept
+fn read_origins(block: &[u8], capacity: usize, count: usize) -> miette::Result<Vec<Origin>> {
+    let stride = capacity
+        .checked_mul(8)
+        .and_then(|bytes| bytes.checked_add(24))
+        .ok_or_else(|| miette::miette!("the ORGN lists of {capacity} spectra are too long"))?;
+    let mut origins = Vec::new();
+    let mut offset = 20usize;
+    for _ in 0..u32_at(block, 16)? {
+        let data_type = u32_at(block, offset)?;
+        // A list starting near the end of the address space saturates, and reads as truncated
+        let unit = u32_at(block, offset.saturating_add(4))?;
+        let name = text_at(block, offset.saturating_add(8), 16);
+        let values = (0..count)
+            .map(|index| u64_at(block, offset.saturating_add(24 + 8 * index)))
+            .collect::<miette::Result<Vec<_>>>()?;
+        let values = if data_type & !ORIGIN_PRIMARY == ORIGIN_TIME {
+            // Times are kept as seconds after the first spectrum
+            let start = values.first().copied().unwrap_or_default();
+            values
+                .iter()
+                .map(|&time| (time as i64 - start as i64) as f64 / FILETIME_TICKS)
+                .collect()
+        } else {
+            values.into_iter().map(f64::from_bits).collect()
+        };
+        origins.push(Origin {
+            primary: data_type & ORIGIN_PRIMARY != 0,
+            unit,
+            name,
+            values,
+        });
+        offset = offset
+            .checked_add(stride)
+            .ok_or_else(|| miette::miette!("the ORGN lists of {capacity} spectra are too long"))?;
+    }
+    Ok(origins)
+}
+
+#[cfg(test)]
+mod test {
+    use super::WdfReader;
+    use crate::{parse, write::WriteSPC, xzwType, Header, SpcWriter};
+
+    fn block(name: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut block = name.to_vec();
+        block.extend_from_slice(&0u32.to_le_bytes());
+        block.extend_from_slice(&(16 + contents.len() as u64).to_le_bytes());
+        block.extend_from_slice(contents);
+        block
+    }
+
+    #[test]
+    fn map_spectra_become_traces_with_their_coordinates_in_the_log() {
+        let mut file_header = vec![0; 496];
+        // Room for three spectra, of which two were collected
+        file_header[44..48].copy_from_slice(&3u32.to_le_bytes());
+        file_header[48..56].copy_from_slice(&3u64.to_le_bytes());
+        // A detector of two rows
+        file_header[68..72].copy_from_slice(&2u32.to_le_bytes());
+        file_header[56..64].copy_from_slice(&2u64.to_le_bytes());
+        file_header[140..144].copy_from_slice(&15798.0f32.to_le_bytes());
+        file_header[224..227].copy_from_slice(b"map");
+
+        let mut x_list = [1u32, 1].map(u32::to_le_bytes).concat();
+        for x in [100.0f32, 200.0, 300.0] {
+            x_list.extend_from_slice(&x.to_le_bytes());
+        }
+        let mut y_list = [1u32, 5].map(u32::to_le_bytes).concat();
+        for y in [10.0f32, 10.5] {
+            y_list.extend_from_slice(&y.to_le_bytes());
+        }
+        let data = [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0]
+            .iter()
+            .flat_map(|y| y.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        // A primary list of times one second apart, and the x-coordinates of the stage, each with
+        // an entry for the spectrum that was never collected
+        let mut origins = 2u32.to_le_bytes().to_vec();
+        origins.extend_from_slice(&(11u32 | 1 << 31).to_le_bytes());
+        origins.extend_from_slice(&12u32.to_le_bytes());
+        origins.extend_from_slice(b"Time\0\0\0\0\0\0\0\0\0\0\0\0");
+        for time in [5_000_000u64, 15_000_000, 0] {
+            origins.extend_from_slice(&time.to_le_bytes());
+        }
+        origins.extend_from_slice(&3u32.to_le_bytes());
+        origins.extend_from_slice(&5u32.to_le_bytes());
+        origins.extend_from_slice(b"X\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        for x in [-2.5f64, 2.5, 0.0] {
+            origins.extend_from_slice(&x.to_le_bytes());
+        }
+
+        let source = [
+            block(b"WDF1", &file_header),
+            block(b"DATA", &data),
+            block(b"XLST", &x_list),
+            block(b"YLST", &y_list),
+            block(b"ORGN", &origins),
+        ]
+        .concat();
+
+        let parsed = WdfReader::new().read(&source).unwrap();
+        let Header::New(header) = parsed.header() else {
+            panic!("expected a new-format header");
+        };
+        assert!(matches!(header.x_unit_type(), xzwType::RamanShift));
+        assert!(matches!(header.z_unit_type(), xzwType::Seconds));
+        assert_eq!(header.memo().to_string(), "map");
+
+        let traces = parsed.traces();
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[1].x(), [100.0, 200.0, 300.0]);
+        assert_eq!(traces[1].y(), [4.0, 5.0, 6.0]);
+        assert_eq!(traces[1].z(), 1.0);
+
+        // The log is kept when the file is written as SPC
+        let mut written = Vec::new();
+        SpcWriter::new().write_spc(&mut written, &parsed).unwrap();
+        let log = parse(&written).unwrap().log().unwrap().entries();
+        assert!(log.contains(&("laser_wavenumber".to_owned(), "15798".to_owned())));
+        assert!(log.contains(&("X[1]".to_owned(), "2.5".to_owned())));
+        assert!(!log.iter().any(|(key, _)| key == "X[2]"));
+        assert!(log.contains(&("y_list_units".to_owned(), "Micrometers (um)".to_owned())));
+        assert!(log.contains(&("y_list[1]".to_owned(), "10.5".to_owned())));
+
+        // Sizes too large to address are errors rather than overflows
+        let huge = |capacity: u64, count: u64| {
+            let mut huge = file_header.clone();
+            huge[48..56].copy_from_slice(&capacity.to_le_bytes());
+            huge[56..64].copy_from_slice(&count.to_le_bytes());
+            let source = [
+                block(b"WDF1", &huge),
+                block(b"DATA", &data),
+                block(b"XLST", &x_list),
+                block(b"ORGN", &origins),
+            ]
+            .concat();
+            WdfReader::new().read(&source)
+        };
+        assert!(huge(u64::MAX, u64::MAX / 4).is_err());
+        assert!(huge(u64::MAX, 2).is_err());
+        assert!(huge(2, 3).is_err());
+        // The second origin list would start three bytes before the end of the address space
+        assert!(huge((1 << 61) - 6, 0).is_err());
+        let mut source = block(b"WDF1", &file_header);
+        source.extend_from_slice(b"DATA\0\0\0\0");
+        source.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(WdfReader::new().read(&source).is_err());
+    }
+}
@@ -0,0 +1,444 @@
+//! Reading individual subfiles from a seekable source, without loading the whole file.
+//!
+//! Only the header is read when the stream is opened. When an XYXY file has a directory, a
+//! requested subfile is read by seeking straight to the position given in its directory entry.
+//! Otherwise the subheaders of the preceding subfiles are read to find where it starts, and the
+//! positions found are remembered for later requests.
+
+use std::{
+    borrow::Cow,
+    io::{Read, Seek, SeekFrom},
+};
+
+use miette::IntoDiagnostic;
+use zerocopy::{BigEndian, ByteOrder, LittleEndian, TryFromBytes};
+
+use crate::{
+    block::{Directory, LexedDirectory, Subfile, YMode},
+    detect::{detect_galactic, FileKind},
+    header::{DataShape, Header, LexedSubheader},
+    lex::{SPCReader, Version},
+    parse::{Parse, ParseOptions, TryParse, TryParseWith},
+    trace::Trace,
+};
+
+/// An SPC file read one subfile at a time from a seekable source
+pub struct SpcStream<R> {
+    reader: R,
+    header: Header,
+    big_endian: bool,
+    version: Version,
+    shape: DataShape,
+    y_mode: YMode,
+    number_points: usize,
+    number_of_subfiles: Option<usize>,
+    // The x-values shared by every subfile, for evenly spaced and XYY files
+    shared_x: Option<Vec<f64>>,
+    directory: Option<Vec<Directory>>,
+    // The start of each subfile found so far, from the start of the file
+    offsets: Vec<u64>,
+}
+
+// The parts of the lexed header needed to find and decode subfiles
+struct Layout {
+    header: Header,
+    shape: DataShape,
+    y_mode: YMode,
+    number_points: usize,
+    number_of_subfiles: Option<usize>,
+}
+
+fn read_layout<'data, E: ByteOrder + 'data>(
+    mut reader: SPCReader<'data, E>,
+    options: &ParseOptions,
+) -> miette::Result<Layout> {
+    let header = reader.lex_header()?;
+    Ok(Layout {
+        header: header.try_parse_with(options)?,
+        shape: header.data_shape()?,
+        y_mode: header.y_mode(),
+        number_points: header.number_points(),
+        number_of_subfiles: header.number_of_subfiles(),
+    })
+}
+
+fn read_directory<E: ByteOrder>(source: &[u8]) -> miette::Result<Vec<Directory>> {
+    source
+        .chunks_exact(size_of::<LexedDirectory<E>>())
+        .map(|entry| {
+            LexedDirectory::<E>::try_ref_from_bytes(entry)
+                .map(Parse::parse)
+                .map_err(|e| miette::miette!("invalid directory entry: {e}"))
+        })
+        .collect()
+}
+
+// The size in bytes of the subfile starting with the given subheader
+fn subfile_size<E: ByteOrder>(
+    subheader: &[u8],
+    xyxy: bool,
+    y_mode: &YMode,
+    number_points: usize,
+) -> miette::Result<usize> {
+    let subheader = LexedSubheader::<E>::try_ref_from_bytes(subheader)
+        .map_err(|e| miette::miette!("invalid subheader: {e}"))?;
+    let mode = y_mode
+        .for_subfile(subheader.float_data_expected())
+        .ok_or_else(|| miette::miette!("subfile does not match the data type in the header"))?;
+    let (number_points, bytes_per_point) = if xyxy {
+        (subheader.number_of_points(), 4 + mode.bytes_per_point())
+    } else {
+        (number_points, mode.bytes_per_point())
+    };
+    number_points
+        .checked_mul(bytes_per_point)
+        .and_then(|bytes| bytes.checked_add(size_of::<LexedSubheader<E>>()))
+        .ok_or_else(|| miette::miette!("a subfile of {number_points} points is too large to read"))
+}
+
+// Decode a subfile into its x-values, if it stores its own, and the trace data
+fn read_subfile<E: ByteOrder>(
+    source: &[u8],
+    version: Version,
+    xyxy: bool,
+    y_mode: &YMode,
+    number_points: usize,
+) -> miette::Result<(Option<Vec<f64>>, Subfile)> {
+    let mut reader = SPCReader::<E>::fragment(source, version);
+    if xyxy {
+        let (x, subfile) = reader.lex_xyxy_subfile(y_mode.clone())?;
+        let x = x.parse().iter().map(|&x| x as f64).collect();
+        Ok((Some(x), subfile.try_parse()?))
+    } else {
+        let subfile = reader.lex_subfile(y_mode.clone(), number_points)?;
+        Ok((None, subfile.try_parse()?))
+    }
+}
+
+impl<R: Read + Seek> SpcStream<R> {
+    /// Open a stream, reading the header and, for XYXY files, the directory
+    pub fn new(reader: R) -> miette::Result<Self> {
+        Self::with_options(reader, &ParseOptions::default())
+    }
+
+    /// Open a stream, interpreting the header with the given options
+    pub fn with_options(mut reader: R, options: &ParseOptions) -> miette::Result<Self> {
+        let mut start = [0; 8];
+        reader.seek(SeekFrom::Start(0)).into_diagnostic()?;
+        reader.read_exact(&mut start).into_diagnostic()?;
+        let (big_endian, version, header_len) = match detect_galactic(&start)? {
+            FileKind::NewBigEndian => (true, Version::New, 512),
+            FileKind::Old => (false, Version::Old, 224),
+            _ => (false, Version::New, 512),
+        };
+
+        let mut source = vec![0; header_len];
+        reader.seek(SeekFrom::Start(0)).into_diagnostic()?;
+        reader.read_exact(&mut source).into_diagnostic()?;
+        let layout = if big_endian {
+            read_layout(SPCReader::big_endian(&source), options)?
+        } else {
+            read_layout(SPCReader::little_endian(&source), options)?
+        };
+
+        let mut stream = Self {
+            reader,
+            header: layout.header,
+            big_endian,
+            version,
+            shape: layout.shape,
+            y_mode: layout.y_mode,
+            number_points: layout.number_points,
+            number_of_subfiles: layout.number_of_subfiles,
+            shared_x: None,
+            directory: None,
+            offsets: vec![header_len as u64],
+        };
+
+        match stream.shape {
+            DataShape::Y | DataShape::YY => {
+                stream.shared_x = Some(stream.header.x_points());
+            }
+            DataShape::XY | DataShape::XYY => {
+                let len = stream.number_points.checked_mul(4).ok_or_else(|| {
+                    miette::miette!("{} x-values are too many to read", stream.number_points)
+                })?;
+                let source = stream.read_at(header_len as u64, len)?;
+                stream.shared_x = Some(
+                    source
+                        .chunks_exact(4)
+                        .map(|x| {
+                            let x = x.try_into().unwrap();
+                            if big_endian {
+                                f32::from_be_bytes(x) as f64
+                            } else {
+                                f32::from_le_bytes(x) as f64
+                            }
+                        })
+                        .collect(),
+                );
+                stream.offsets[0] += source.len() as u64;
+            }
+            // In an XYXY file the number of points field holds the position of the directory, or
+            // zero if there is no directory
+            DataShape::XYXY => stream.directory = stream.read_directory()?,
+        }
+        Ok(stream)
+    }
+
+    /// The parsed header of the file
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// The number of subfiles in the file. Old-format multifiles do not record this
+    pub fn number_of_subfiles(&self) -> Option<usize> {
+        self.number_of_subfiles
+    }
+
+    /// The directory of an XYXY file, or `None` if the file does not have one
+    pub fn directory(&self) -> Option<&[Directory]> {
+        self.directory.as_deref()
+    }
+
+    /// Read the subfile at `index`.
+    ///
+    /// The z-value of the returned trace is the one stored in its subheader, as the z-axis of an
+    /// evenly spaced file can only be reconstructed from the whole file.
+    pub fn subfile(&mut self, index: usize) -> miette::Result<Trace<'static>> {
+        if let Some(number_of_subfiles) = self.number_of_subfiles {
+            if index >= number_of_subfiles {
+                miette::bail!("subfile {index} requested, but the file has {number_of_subfiles}");
+            }
+        }
+
+        let xyxy = matches!(self.shape, DataShape::XYXY);
+        let (offset, size) = match self.directory.as_ref().and_then(|d| d.get(index)) {
+            Some(entry) => (entry.position() as u64, entry.size() as usize),
+            None => {
+                let offset = self.find_subfile(index)?;
+                let subheader = self.read_at(offset, size_of::<LexedSubheader<BigEndian>>())?;
+                (offset, self.subfile_size(&subheader, xyxy)?)
+            }
+        };
+
+        let source = self.read_at(offset, size)?;
+        let (x, subfile) = if self.big_endian {
+            read_subfile::<BigEndian>(
+                &source,
+                self.version,
+                xyxy,
+                &self.y_mode,
+                self.number_points,
+            )?
+        } else {
+            read_subfile::<LittleEndian>(
+                &source,
+                self.version,
+                xyxy,
+                &self.y_mode,
+                self.number_points,
+            )?
+        };
+
+        let x = x.or_else(|| self.shared_x.clone()).unwrap_or_default();
+        let y = subfile.data.decode(self.header.exponent_y());
+        let z = subfile.subheader.z() as f64;
+        let time = self
+            .directory
+            .as_ref()
+            .and_then(|directory| directory.get(index))
+            .map(|entry| entry.time() as f64);
+        Ok(Trace::new(index, x, y, z, Cow::Owned(subfile.subheader)).with_time(time))
+    }
+
+    // Read `len` bytes at `offset`, checking they lie within the source before allocating, as the
+    // lengths come from fields of the file
+    fn read_at(&mut self, offset: u64, len: usize) -> miette::Result<Vec<u8>> {
+        let end = self.reader.seek(SeekFrom::End(0)).into_diagnostic()?;
+        if (len as u64)
+            .checked_add(offset)
+            .is_none_or(|last| last > end)
+        {
+            miette::bail!("{len} bytes at {offset:#x} run past the end of the {end} byte file");
+        }
+        self.reader
+            .seek(SeekFrom::Start(offset))
+            .into_diagnostic()?;
+        let mut source = Vec::new();
+        (&mut self.reader)
+            .take(len as u64)
+            .read_to_end(&mut source)
+            .into_diagnostic()
+            .map_err(|e| e.wrap_err(format!("reading {len} bytes at {offset:#x} failed")))?;
+        if source.len() != len {
+            miette::bail!(
+                "only {} of {len} bytes at {offset:#x} could be read",
+                source.len()
+            );
+        }
+        Ok(source)
+    }
+
+    fn subfile_size(&self, subheader: &[u8], xyxy: bool) -> miette::Result<usize> {
+        if self.big_endian {
+            subfile_size::<BigEndian>(subheader, xyxy, &self.y_mode, self.number_points)
+        } else {
+            subfile_size::<LittleEndian>(subheader, xyxy, &self.y_mode, self.number_points)
+        }
+    }
+
+    // Find the start of a subfile by reading the subheaders of those before it
+    fn find_subfile(&mut self, index: usize) -> miette::Result<u64> {
+        let xyxy = matches!(self.shape, DataShape::XYXY);
+        while self.offsets.len() <= index {
+            let offset = *self.offsets.last().unwrap();
+            let subheader = self.read_at(offset, size_of::<LexedSubheader<BigEndian>>())?;
+            let size = self.subfile_size(&subheader, xyxy)?;
+            self.offsets.push(offset + size as u64);
+        }
+        Ok(self.offsets[index])
+    }
+
+    fn read_directory(&mut self) -> miette::Result<Option<Vec<Directory>>> {
+        let (position, number_of_subfiles) = match (self.number_points, self.number_of_subfiles) {
+            (0, _) | (_, None) => return Ok(None),
+            (position, Some(number_of_subfiles)) => (position as u64, number_of_subfiles),
+        };
+        let len = self.reader.seek(SeekFrom::End(0)).into_diagnostic()?;
+
+        let size = number_of_subfiles
+            .checked_mul(size_of::<LexedDirectory<BigEndian>>())
+            .ok_or_else(|| {
+                miette::miette!("a directory of {number_of_subfiles} entries is too large to read")
+            })?;
+        let source = self.read_at(position, size)?;
+        let directory = if self.big_endian {
+            read_directory::<BigEndian>(&source)?
+        } else {
+            read_directory::<LittleEndian>(&source)?
+        };
+
+        for (index, entry) in directory.iter().enumerate() {
+            if (entry.size() as usize) < size_of::<LexedSubheader<BigEndian>>()
+                || entry.position() as u64 + entry.size() as u64 > len
+            {
+                miette::bail!(
+                    "directory entry {index} describes {} bytes at {:#x}, which is not a subfile",
+                    entry.size(),
+                    entry.position()
+                );
+            }
+        }
+        Ok(Some(directory))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::SpcStream;
+    use crate::{parse, write::WriteSPC, CsvReader, SpcVersion, SpcWriter};
+
+    // A new-format little-endian XYXY file with three float subfiles of `index + 1` points each,
+    // followed by a directory giving each subfile a time of `10 * index`
+    fn xyxy_file_with_directory() -> Vec<u8> {
+        let mut source = vec![0; 512];
+        // TMULTI, TXYXYS and TXVALS
+        source[0] = 0xc4;
+        source[1] = 0x4b;
+        source[24..28].copy_from_slice(&3u32.to_le_bytes());
+
+        let mut directory = Vec::new();
+        for index in 0..3u32 {
+            let position = source.len() as u32;
+            let mut subheader = [0; 32];
+            subheader[1] = 0x80;
+            subheader[4..8].copy_from_slice(&(index as f32).to_le_bytes());
+            subheader[16..20].copy_from_slice(&(index + 1).to_le_bytes());
+            source.extend_from_slice(&subheader);
+            for point in 0..=index {
+                source.extend_from_slice(&(point as f32).to_le_bytes());
+            }
+            for point in 0..=index {
+                source.extend_from_slice(&((index * 100 + point) as f32).to_le_bytes());
+            }
+
+            directory.extend_from_slice(&position.to_le_bytes());
+            directory.extend_from_slice(&(source.len() as u32 - position).to_le_bytes());
+            directory.extend_from_slice(&(10. * index as f32).to_le_bytes());
+        }
+        // In an XYXY file the number of points holds the position of the directory
+        let position = source.len() as u32;
+        source[4..8].copy_from_slice(&position.to_le_bytes());
+        source.extend_from_slice(&directory);
+        source
+    }
+
+    #[test]
+    fn subfiles_are_read_through_the_directory() {
+        let source = xyxy_file_with_directory();
+        let mut stream = SpcStream::new(Cursor::new(&source)).unwrap();
+
+        let directory = stream.directory().unwrap();
+        assert_eq!(directory.len(), 3);
+        assert_eq!(directory[2].time(), 20.);
+
+        let trace = stream.subfile(2).unwrap();
+        assert_eq!(trace.x(), [0., 1., 2.]);
+        assert_eq!(trace.y(), [200., 201., 202.]);
+        assert_eq!(trace.z(), 2.);
+        assert_eq!(trace.time(), Some(20.));
+
+        let parsed = parse(&source).unwrap();
+        assert_eq!(parsed.subfile_times().unwrap(), [0., 10., 20.]);
+        assert_eq!(parsed.traces()[1].y(), stream.subfile(1).unwrap().y());
+    }
+
+    #[test]
+    fn directory_which_does_not_match_the_subfiles_is_rejected() {
+        let mut source = xyxy_file_with_directory();
+        // Point the second directory entry at the first subfile
+        let entry = source.len() - 24;
+        source[entry..entry + 4].copy_from_slice(&512u32.to_le_bytes());
+
+        assert!(parse(&source).is_err());
+    }
+
+    #[test]
+    fn truncated_directory_is_rejected() {
+        let mut source = xyxy_file_with_directory();
+        source.truncate(source.len() - 12);
+
+        assert!(SpcStream::new(Cursor::new(&source)).is_err());
+    }
+
+    #[test]
+    fn directory_larger_than_the_file_is_rejected_before_it_is_read() {
+        let mut source = vec![0; 600];
+        source[0] = 0xc4;
+        source[1] = 0x4b;
+        source[4..8].copy_from_slice(&512u32.to_le_bytes());
+        source[24..28].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+
+        assert!(SpcStream::new(Cursor::new(&source)).is_err());
+    }
+
+    #[test]
+    fn integer_subfiles_in_a_float_file_are_rejected() {
+        let parsed = CsvReader::new().read("x,y_0,y_1\n1,2,-4\n2,4,8\n").unwrap();
+        let mut source = Vec::new();
+        SpcWriter::new()
+            .with_version(SpcVersion::Old)
+            .write_spc(&mut source, &parsed)
+            .unwrap();
+        // The old header declares float data, so the subheaders must too. Declare integers in the
+        // subheader of the second subfile, which follows the 224 byte header and first subfile
+        source[224 + 40 + 1] = 0;
+
+        let mut stream = SpcStream::new(Cursor::new(&source)).unwrap();
+        assert!(stream.subfile(1).is_err());
+        assert!(parse(&source).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! Decoding of the fixed-width text fields stored in SPC files.
+//!
+//! The SPC specification does not say how text is encoded, and in practice it depends on the
+//! locale of the software which wrote the file. GRAMS installations on Windows usually write
+//! Windows-1252, while Japanese instruments write Shift-JIS.
+
+use std::fmt;
+
+/// The character encoding used to decode text fields
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TextEncoding {
+    /// Decode as UTF-8 if the field is valid UTF-8, falling back to Windows-1252 if it is not.
+    ///
+    /// Windows-1252 assigns a character to every byte, so this never fails.
+    #[default]
+    Auto,
+    /// UTF-8, replacing invalid sequences with U+FFFD
+    Utf8,
+    /// Windows-1252, the default code page of western European Windows installations
+    Windows1252,
+    /// ISO-8859-1, where each byte is the unicode code point of the same value
+    Latin1,
+    /// Shift-JIS, used by Japanese instruments. Invalid sequences are replaced with U+FFFD
+    ShiftJis,
+}
+
+impl TextEncoding {
+    /// Resolve [`TextEncoding::Auto`] to a concrete encoding for the given bytes
+    fn resolve(self, bytes: &[u8]) -> Self {
+        match self {
+            Self::Auto if ::std::str::from_utf8(bytes).is_ok() => Self::Utf8,
+            Self::Auto => Self::Windows1252,
+            encoding => encoding,
+        }
+    }
+
+    pub(crate) fn decode(self, bytes: &[u8]) -> String {
+        match self.resolve(bytes) {
+            Self::Auto => unreachable!("auto encoding is always resolved"),
+            Self::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Self::Windows1252 => encoding_rs::WINDOWS_1252
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+            Self::Latin1 => bytes.iter().map(|&b| b as char).collect(),
+            Self::ShiftJis => encoding_rs::SHIFT_JIS
+                .decode_without_bom_handling(bytes)
+                .0
+                .into_owned(),
+        }
+    }
+}
+
+/// A text field read from an SPC file.
+///
+/// The raw bytes of the field are kept alongside the decoded text, so a field decoded with the
+/// wrong encoding can be decoded again, and the field can be written back out unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Text {
+    raw: Vec<u8>,
+    decoded: String,
+    encoding: TextEncoding,
+}
+
+impl Text {
+    /// Decode a null-terminated field. Anything after the first null byte is ignored, and leading
+    /// or trailing whitespace is trimmed.
+    pub(crate) fn decode(raw: &[u8], encoding: TextEncoding) -> Self {
+        let end = raw.iter().position(|&b| b == 0).unwrap_or(raw.len());
+        let encoding = encoding.resolve(&raw[..end]);
+        Self {
+            raw: raw.to_owned(),
+            decoded: encoding.decode(&raw[..end]).trim().to_owned(),
+            encoding,
+        }
+    }
+
+    // A field holding the given text, stored as UTF-8
+    pub(crate) fn new(text: &str) -> Self {
+        Self::decode(text.as_bytes(), TextEncoding::Utf8)
+    }
+
+    // The raw bytes of the field, padded with nulls or truncated to fill a field of `N` bytes
+    pub(crate) fn to_field<const N: usize>(&self) -> [u8; N] {
+        let mut field = [0; N];
+        let len = self.raw.len().min(N);
+        field[..len].copy_from_slice(&self.raw[..len]);
+        field
+    }
+
+    /// The decoded text
+    pub fn as_str(&self) -> &str {
+        &self.decoded
+    }
+
+    /// The raw bytes of the field, including any null padding
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The encoding which was used to decode the field
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Decode the field again with a different encoding
+    pub fn decode_as(&self, encoding: TextEncoding) -> Self {
+        Self::decode(&self.raw, encoding)
+    }
+
+    /// Decode each null-separated segment of the field.
+    ///
+    /// Some fields, such as the custom axis labels, store several strings separated by nulls.
+    /// Trailing empty segments are dropped.
+    pub fn segments(&self) -> Vec<String> {
+        let mut segments = self
+            .raw
+            .split(|&b| b == 0)
+            .map(|segment| self.encoding.decode(segment).trim().to_owned())
+            .collect::<Vec<_>>();
+        while segments.last().is_some_and(String::is_empty) {
+            segments.pop();
+        }
+        segments
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.decoded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Text, TextEncoding};
+
+    #[test]
+    fn windows_1252_memo_decodes_without_panicking() {
+        // "25°C, 10µm" as written by a GRAMS install using code page 1252
+        let raw = b"25\xb0C, 10\xb5m\0\0\0\0";
+
+        let text = Text::decode(raw, TextEncoding::Auto);
+        assert_eq!(text.as_str(), "25°C, 10µm");
+        assert_eq!(text.encoding(), TextEncoding::Windows1252);
+        assert_eq!(text.raw(), raw);
+
+        let text = text.decode_as(TextEncoding::Latin1);
+        assert_eq!(text.as_str(), "25°C, 10µm");
+
+        let text = text.decode_as(TextEncoding::Utf8);
+        assert_eq!(text.as_str(), "25\u{fffd}C, 10\u{fffd}m");
+    }
+
+    #[test]
+    fn shift_jis_text_decodes() {
+        // "分光" (spectroscopy) in Shift-JIS
+        let raw = b"\x95\xaa\x8c\xf5\0";
+        let text = Text::decode(raw, TextEncoding::ShiftJis);
+        assert_eq!(text.as_str(), "分光");
+    }
+
+    #[test]
+    fn axis_labels_are_split_on_nulls() {
+        let raw = b"Wavenumber\0Absorbance\0Time\0\0\0\0";
+        let text = Text::decode(raw, TextEncoding::Auto);
+        assert_eq!(text.as_str(), "Wavenumber");
+        assert_eq!(text.segments(), ["Wavenumber", "Absorbance", "Time"]);
+    }
+}
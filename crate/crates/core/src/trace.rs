@@ -0,0 +1,189 @@
+//! A uniform view of the traces in an SPC file, independent of how the data block is laid out.
+//!
+//! Every subfile in an SPC file becomes one [`Trace`], carrying its x-values, decoded y-values and
+//! the subheader which preceded it in the file.
+//!
+//! Files with w-planes store a four-dimensional dataset: the subfiles are split evenly into
+//! `w_planes` consecutive groups, each of which holds a series of traces along z. These groups are
+//! exposed as [`Plane`]s, so a trace can be addressed by its (w, z) index.
+
+use std::borrow::Cow;
+
+use crate::header::Subheader;
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum PlaneError {
+    #[error("{subfiles} subfiles cannot be split evenly into {planes} w-planes")]
+    UnevenPlanes { subfiles: usize, planes: usize },
+    #[error("the traces of w-plane {plane} do not share a w-level")]
+    #[diagnostic(help(
+        "give the w-plane increment in the header, or split the subfiles by w-level"
+    ))]
+    MixedLevels { plane: usize },
+}
+
+// The number of subfiles in each plane, given the number of w-planes declared in the header
+pub(crate) fn subfiles_per_plane(subfiles: usize, planes: usize) -> Result<usize, PlaneError> {
+    match planes {
+        0 => Ok(subfiles),
+        planes if subfiles.is_multiple_of(planes) => Ok(subfiles / planes),
+        planes => Err(PlaneError::UnevenPlanes { subfiles, planes }),
+    }
+}
+
+/// A single trace read from an SPC file
+#[derive(Clone, Debug)]
+pub struct Trace<'spc> {
+    index: usize,
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: f64,
+    time: Option<f64>,
+    subheader: Cow<'spc, Subheader>,
+}
+
+impl<'spc> Trace<'spc> {
+    pub(crate) fn new(
+        index: usize,
+        x: Vec<f64>,
+        y: Vec<f64>,
+        z: f64,
+        subheader: Cow<'spc, Subheader>,
+    ) -> Self {
+        Self {
+            index,
+            x,
+            y,
+            z,
+            time: None,
+            subheader,
+        }
+    }
+
+    pub(crate) fn with_time(mut self, time: Option<f64>) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// The position of the trace in the file, where 0 refers to the first
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The x-values of the trace
+    pub fn x(&self) -> &[f64] {
+        &self.x
+    }
+
+    /// The y-values of the trace, scaled by the exponent if stored as integers
+    pub fn y(&self) -> &[f64] {
+        &self.y
+    }
+
+    /// The z-value of the trace. For evenly spaced multifiles this is implied by the z-increment,
+    /// so it may differ from the z-value in the subheader
+    pub fn z(&self) -> f64 {
+        self.z
+    }
+
+    /// The time of the trace from the directory of an XYXY file, or `None` if the file has no
+    /// directory
+    pub fn time(&self) -> Option<f64> {
+        self.time
+    }
+
+    /// The subheader of the trace, holding its z-value, flags, noise level and scan count
+    pub fn subheader(&self) -> &Subheader {
+        &self.subheader
+    }
+
+    /// The (x, y) pairs of the trace
+    pub fn points(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.x.iter().copied().zip(self.y.iter().copied())
+    }
+}
+
+/// A w-plane, holding the traces which share a w-value
+#[derive(Clone, Debug)]
+pub struct Plane<'spc> {
+    index: usize,
+    w: Option<f64>,
+    traces: Vec<Trace<'spc>>,
+}
+
+impl<'spc> Plane<'spc> {
+    pub(crate) fn new(index: usize, w: Option<f64>, traces: Vec<Trace<'spc>>) -> Self {
+        Self { index, w, traces }
+    }
+
+    /// The position of the plane in the file, where 0 refers to the first
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The w-value of the plane, or `None` if the file does not have w-planes
+    pub fn w(&self) -> Option<f64> {
+        self.w
+    }
+
+    /// The traces in the plane, in the order they are stored
+    pub fn traces(&self) -> &[Trace<'spc>] {
+        &self.traces
+    }
+
+    /// The trace at position `z_index` within the plane
+    pub fn trace(&self, z_index: usize) -> Option<&Trace<'spc>> {
+        self.traces.get(z_index)
+    }
+
+    pub fn into_traces(self) -> Vec<Trace<'spc>> {
+        self.traces
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{subfiles_per_plane, Plane};
+    use crate::{block::Block, CsvReader, Header, ParsedSPC};
+
+    #[test]
+    fn subfiles_are_split_evenly_into_planes() {
+        assert_eq!(subfiles_per_plane(12, 0).unwrap(), 12);
+        assert_eq!(subfiles_per_plane(12, 3).unwrap(), 4);
+        assert!(subfiles_per_plane(12, 5).is_err());
+    }
+
+    #[test]
+    fn planes_take_their_w_from_the_subheaders_or_the_increment() {
+        let mut spc = CsvReader::new()
+            .read("x,y_0,y_1,y_2,y_3\n1,2,3,4,5\n")
+            .unwrap();
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.w_planes = 2;
+        let Block::XYY { ys, .. } = &mut spc.block else {
+            unreachable!()
+        };
+        for (subfile, w) in ys.iter_mut().zip([1., 1., 2., 2.]) {
+            subfile.subheader.w_level = w;
+        }
+        let w = |spc: &ParsedSPC| {
+            spc.planes()
+                .map(|planes| planes.iter().map(Plane::w).collect::<Vec<_>>())
+        };
+        assert_eq!(w(&spc).unwrap(), [Some(1.), Some(2.)]);
+
+        // Planes must not mix w-levels, unless the increment gives the w-values
+        let Block::XYY { ys, .. } = &mut spc.block else {
+            unreachable!()
+        };
+        ys[1].subheader.w_level = 3.;
+        assert!(w(&spc).is_err());
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.w_plane_increment = 0.5;
+        assert_eq!(w(&spc).unwrap(), [Some(1.), Some(1.5)]);
+    }
+}
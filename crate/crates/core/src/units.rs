@@ -6,7 +6,7 @@
 /// flag in [`FlagParameters`] must be set when fexpr is non-zero. When TCGRAM is set, a general
 /// chromatagraph is specified by a zero field
 #[derive(Copy, Clone, Debug)]
-pub(crate) enum InstrumentTechnique {
+pub enum InstrumentTechnique {
     /// A general SPC file, which could be anything at all
     GeneralSPC = 0x00,
     /// A gas chromatogram
@@ -22,7 +22,7 @@ pub(crate) enum InstrumentTechnique {
     /// A near-infrared spectrum
     NIRSpectrum = 0x05,
     /// A UV-Visible spectrum
-    UVVISSpectrum = 0x06,
+    UVVISSpectrum = 0x07,
     /// An X-ray diffraction spectrum
     XRayDiffractionSpectrum = 0x08,
     /// A mass-spectrum, which can be single, GC-MS, continuum, centroid or time-of-flight
@@ -63,11 +63,18 @@ impl InstrumentTechnique {
             v => Err(InstrumentTechniqueCreationError(v)),
         }
     }
+
+    /// Look up a technique by its name, such as `RamanSpectrum`, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=u8::MAX)
+            .filter_map(|code| Self::new(code).ok())
+            .find(|technique| format!("{technique:?}").eq_ignore_ascii_case(name.trim()))
+    }
 }
 
 /// The [`xzwType`] represents all the possible settings for the fxtype, fztype and fwtype
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub(crate) enum xzwType {
+pub enum xzwType {
     // Arbitrary
     Arbitrary = 0,
     /// Wavenumber (cm-1)
@@ -177,12 +184,62 @@ impl xzwType {
             v => Err(xzwTypeCreationError(v)),
         }
     }
+
+    /// Look up a unit by its name, such as `Wavenumber`, or by its label, such as
+    /// `Wavenumber (cm-1)`, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        (0..=u8::MAX)
+            .filter_map(|code| Self::new(code).ok())
+            .find(|unit| {
+                format!("{unit:?}").eq_ignore_ascii_case(name)
+                    || unit.label().eq_ignore_ascii_case(name)
+            })
+    }
+
+    /// The label GRAMS displays for the unit
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Arbitrary => "Arbitrary",
+            Self::Wavenumber => "Wavenumber (cm-1)",
+            Self::Micrometers => "Micrometers (um)",
+            Self::Nanometers => "Nanometers (nm)",
+            Self::Seconds => "Seconds",
+            Self::Minutes => "Minutes",
+            Self::Hertz => "Hertz (Hz)",
+            Self::Kilohertz => "Kilohertz (KHz)",
+            Self::MegaHertz => "Megahertz (MHz)",
+            Self::Mass => "Mass (M/z)",
+            Self::PartsPerMillion => "Parts per million (PPM)",
+            Self::Days => "Days",
+            Self::Years => "Years",
+            Self::RamanShift => "Raman shift (cm-1)",
+            Self::ElectronVolt => "Electron volts (eV)",
+            Self::Unknown => "Unknown",
+            Self::DiodeNumber => "Diode number",
+            Self::Channel => "Channel",
+            Self::Degrees => "Degrees",
+            Self::TemperatureF => "Temperature (F)",
+            Self::TemperatureC => "Temperature (C)",
+            Self::TemperatureK => "Temperature (K)",
+            Self::DataPoints => "Data points",
+            Self::Milliseconds => "Milliseconds (mS)",
+            Self::Microseconds => "Microseconds (uS)",
+            Self::Nanoseconds => "Nanoseconds (nS)",
+            Self::GigaHertz => "Gigahertz (GHz)",
+            Self::Centimeters => "Centimeters (cm)",
+            Self::Meters => "Meters (m)",
+            Self::Millimeters => "Millimeters (mm)",
+            Self::Hours => "Hours",
+            Self::DoubleInterferogram => "Double interferogram",
+        }
+    }
 }
 
 /// The [`yType`] represents all the possible settings for the fytype. Note that all the first 127
 /// values exhibit positive peaks, while values 129 or greater are expected to exhibit valleys
 #[derive(Copy, Clone, Debug)]
-pub(crate) enum yType {
+pub enum yType {
     /// Arbitrary intensity
     ArbitraryIntensity = 0,
     /// Interferogram
@@ -279,4 +336,50 @@ impl yType {
             v => Err(yTypeCreationError(v)),
         }
     }
+
+    /// Look up a unit by its name, such as `Wavenumber`, or by its label, such as
+    /// `Wavenumber (cm-1)`, ignoring case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        (0..=u8::MAX)
+            .filter_map(|code| Self::new(code).ok())
+            .find(|unit| {
+                format!("{unit:?}").eq_ignore_ascii_case(name)
+                    || unit.label().eq_ignore_ascii_case(name)
+            })
+    }
+
+    /// The label GRAMS displays for the unit
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::ArbitraryIntensity => "Arbitrary intensity",
+            Self::Interferogram => "Interferogram",
+            Self::Absorbance => "Absorbance",
+            Self::KubelkaMonk => "Kubelka-Monk",
+            Self::Counts => "Counts",
+            Self::Volts => "Volts",
+            Self::Degrees => "Degrees",
+            Self::Milliamps => "Milliamps",
+            Self::Millimeters => "Millimeters",
+            Self::Millivolts => "Millivolts",
+            Self::LogInvR => "Log(1/R)",
+            Self::Percent => "Percent",
+            Self::Intensity => "Intensity",
+            Self::RelativeIntensity => "Relative intensity",
+            Self::Energy => "Energy",
+            Self::Decibel => "Decibel",
+            Self::TemperatureF => "Temperature (F)",
+            Self::TemperatureC => "Temperature (C)",
+            Self::TemperatureK => "Temperature (K)",
+            Self::IndexOfRefraction => "Index of refraction [N]",
+            Self::ExtinctionCoeff => "Extinction coefficient [K]",
+            Self::Real => "Real",
+            Self::Imaginary => "Imaginary",
+            Self::Complex => "Complex",
+            Self::Transmission => "Transmission",
+            Self::Reflectance => "Reflectance",
+            Self::ArbitraryOrSingleBeamWithValleyPeaks => "Arbitrary or single beam with valley peaks",
+            Self::Emission => "Emission",
+        }
+    }
 }
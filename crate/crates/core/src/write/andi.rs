@@ -0,0 +1,217 @@
+//! Writing chromatograms as ANDI (AIA) chromatography files, as described by ASTM E1947.
+//!
+//! ANDI files are netCDF classic files. The detector signal is stored in `ordinate_values`, and
+//! the retention times in seconds are described by `actual_delay_time` and
+//! `actual_sampling_interval`. They are also stored point by point in `raw_data_retention`, for
+//! chromatograms which were not sampled uniformly. The header fills the global attributes, such as
+//! `experiment_title` from the memo and `injection_date_time_stamp` from the collection time.
+
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+use miette::IntoDiagnostic;
+
+use crate::{xzwType, Header, InstrumentTechnique, ParsedSPC};
+
+use super::{
+    netcdf::{NetCdf, Values},
+    seconds, WriteSPC,
+};
+
+/// Writes one trace of a chromatogram as an ANDI chromatography file
+#[derive(Clone, Debug, Default)]
+pub struct AndiWriter {
+    trace: usize,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum AndiError {
+    #[error("ANDI chromatography files hold chromatograms, but this is a {0:?}")]
+    #[diagnostic(help("only gas, general and HPLC chromatograms can be written"))]
+    NotAChromatogram(InstrumentTechnique),
+    #[error("old-format files do not record the instrument technique, so are not chromatograms")]
+    OldFormat,
+    #[error("the x-axis of a chromatogram is time, but the x units are {0:?}")]
+    NotTime(xzwType),
+    #[error("the file has no trace {0}")]
+    NoTrace(usize),
+}
+
+impl AndiWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the index of the trace written. ANDI files hold a single chromatogram, so only one
+    /// trace of a multifile is written, the first by default
+    pub fn with_trace(mut self, trace: usize) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    fn file(&self, spc: &ParsedSPC) -> Result<NetCdf, AndiError> {
+        let Header::New(header) = &spc.header else {
+            return Err(AndiError::OldFormat);
+        };
+        let technique = header.instrument_technique();
+        if !matches!(
+            technique,
+            InstrumentTechnique::GasChromatogram
+                | InstrumentTechnique::GeneralChromatogram
+                | InstrumentTechnique::HPLCChromatogram
+        ) {
+            return Err(AndiError::NotAChromatogram(technique));
+        }
+        let unit = header.x_unit_type();
+        let scale = seconds(unit).ok_or(AndiError::NotTime(unit))?;
+        let traces = spc.traces();
+        let trace = traces
+            .get(self.trace)
+            .ok_or(AndiError::NoTrace(self.trace))?;
+
+        let retention = trace.x().iter().map(|x| x * scale).collect::<Vec<_>>();
+        let first = retention.first().copied().unwrap_or_default();
+        let last = retention.last().copied().unwrap_or_default();
+        let interval = if retention.len() > 1 {
+            (last - first) / (retention.len() - 1) as f64
+        } else {
+            0.0
+        };
+        let uniform = retention
+            .windows(2)
+            .all(|pair| ((pair[1] - pair[0]) - interval).abs() <= interval.abs() * 1e-6);
+        let (minimum, maximum) = trace
+            .y()
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &y| {
+                (min.min(y), max.max(y))
+            });
+
+        let mut file = NetCdf::new();
+        let points = file.add_dimension("point_number", retention.len());
+
+        let text = |value: &str| Values::Char(value.to_owned());
+        let [_, y_label, _] = spc.header.axis_labels();
+        file.add_attribute("dataset_completeness", text("C1+C2"));
+        file.add_attribute("aia_template_revision", text("1.0"));
+        file.add_attribute("netcdf_revision", text("2.3"));
+        file.add_attribute("languages", text("English only"));
+        if let Some(datetime) = header.datetime() {
+            file.add_attribute("dataset_date_time_stamp", text(&timestamp(datetime)));
+            file.add_attribute("injection_date_time_stamp", text(&timestamp(datetime)));
+        }
+        file.add_attribute("experiment_title", text(&header.memo().to_string()));
+        file.add_attribute(
+            "detector_name",
+            text(&header.source_instrument_description().to_string()),
+        );
+        file.add_attribute(
+            "detection_method_name",
+            text(&header.method_file().to_string()),
+        );
+        file.add_attribute("detector_unit", text(&y_label));
+        file.add_attribute("retention_unit", text("Seconds"));
+        file.add_attribute("source_file_format", text("Galactic SPC"));
+
+        let scalar = |value: f64| Values::Float(vec![value as f32]);
+        file.add_variable("detector_maximum_value", &[], Vec::new(), scalar(maximum));
+        file.add_variable("detector_minimum_value", &[], Vec::new(), scalar(minimum));
+        file.add_variable("actual_run_time_length", &[], Vec::new(), scalar(last));
+        file.add_variable("actual_delay_time", &[], Vec::new(), scalar(first));
+        file.add_variable(
+            "actual_sampling_interval",
+            &[],
+            Vec::new(),
+            scalar(interval),
+        );
+        file.add_variable(
+            "ordinate_values",
+            &[points],
+            vec![(
+                "uniform_sampling_flag".to_owned(),
+                text(if uniform { "Y" } else { "N" }),
+            )],
+            Values::Float(trace.y().iter().map(|&y| y as f32).collect()),
+        );
+        file.add_variable(
+            "raw_data_retention",
+            &[points],
+            Vec::new(),
+            Values::Float(retention.iter().map(|&x| x as f32).collect()),
+        );
+        Ok(file)
+    }
+}
+
+impl WriteSPC for AndiWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        writer
+            .write_all(&self.file(spc)?.encode())
+            .into_diagnostic()
+    }
+}
+
+// Timestamps are written as `YYYYMMDDhhmmss+zzzz`
+fn timestamp(datetime: DateTime<Utc>) -> String {
+    datetime.format("%Y%m%d%H%M%S%z").to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AndiError, AndiWriter};
+    use crate::{write::WriteSPC, xzwType, CsvReader, Header, InstrumentTechnique, ParsedSPC};
+
+    fn chromatogram(technique: InstrumentTechnique, unit: xzwType) -> ParsedSPC {
+        let mut spc = CsvReader::new()
+            .read("# memo: run 7\nx,y\n0.5,1\n1,4\n1.5,2\n")
+            .unwrap();
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.instrument_technique = technique;
+        header.x_unit_type = unit;
+        spc
+    }
+
+    // The bytes following the name of a netCDF attribute or variable
+    fn after<'a>(file: &'a [u8], name: &str) -> &'a [u8] {
+        let at = file
+            .windows(name.len())
+            .position(|window| window == name.as_bytes())
+            .unwrap();
+        &file[at + name.len().next_multiple_of(4)..]
+    }
+
+    #[test]
+    fn chromatograms_are_written_with_retention_times_in_seconds() {
+        let spc = chromatogram(InstrumentTechnique::HPLCChromatogram, xzwType::Minutes);
+        let mut file = Vec::new();
+        AndiWriter::new().write_spc(&mut file, &spc).unwrap();
+        assert!(file.starts_with(b"CDF\x01"));
+
+        // The title is a character attribute, nc_type 2, of five characters
+        assert_eq!(
+            &after(&file, "experiment_title")[..13],
+            b"\0\0\0\x02\0\0\0\x05run 7"
+        );
+        // The sampling interval is a scalar float, nc_type 5, which points to its value
+        let interval = after(&file, "actual_sampling_interval");
+        assert_eq!(&interval[12..16], [0, 0, 0, 5]);
+        let offset = u32::from_be_bytes(interval[20..24].try_into().unwrap()) as usize;
+        assert_eq!(&file[offset..offset + 4], 30.0f32.to_be_bytes());
+        // The retention times are the last variable, in seconds
+        assert_eq!(&file[file.len() - 4..], 90.0f32.to_be_bytes());
+
+        let spectrum = chromatogram(InstrumentTechnique::FTIRFTNIRFTRaman, xzwType::Minutes);
+        assert!(matches!(
+            AndiWriter::new().file(&spectrum),
+            Err(AndiError::NotAChromatogram(_))
+        ));
+        let wavenumbers = chromatogram(InstrumentTechnique::GasChromatogram, xzwType::Wavenumber);
+        assert!(matches!(
+            AndiWriter::new().file(&wavenumbers),
+            Err(AndiError::NotTime(_))
+        ));
+    }
+}
@@ -0,0 +1,236 @@
+//! Writing the traces of a file as an Arrow table, stored as Parquet or Arrow IPC.
+//!
+//! The header and the entries of the log are kept in the key/value metadata of the schema, with
+//! the header fields as keys such as `x_units`, and the log entries prefixed with `log.`.
+
+use std::{collections::HashMap, io::Write, sync::Arc};
+
+use arrow::{
+    array::{ArrayRef, Float64Array, UInt32Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    ipc::writer::FileWriter,
+    record_batch::RecordBatch,
+};
+use miette::IntoDiagnostic;
+
+use crate::{block::Block, metadata::ParsedMetadata, ParsedSPC};
+
+use super::WriteSPC;
+
+/// The file format written by the [`ArrowWriter`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ArrowFormat {
+    #[default]
+    Parquet,
+    /// The Arrow IPC file format, also known as Feather
+    Ipc,
+}
+
+impl ArrowFormat {
+    /// The usual file extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Ipc => "arrow",
+        }
+    }
+}
+
+/// The arrangement of the columns written by the [`ArrowWriter`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ArrowLayout {
+    /// One row per point, with columns `trace_index`, `z`, `w`, `x` and `y`. The `w` column is
+    /// null for files without w-planes
+    #[default]
+    Tidy,
+    /// One column `y_<index>` per trace. Traces which share their x-values have a single `x`
+    /// column, and the traces of XYXY files each have their own `x_<index>` column, padded with
+    /// nulls to the length of the longest trace
+    Wide,
+}
+
+/// Writes the traces of a file as an Arrow table, in Parquet or Arrow IPC format
+#[derive(Clone, Debug, Default)]
+pub struct ArrowWriter {
+    format: ArrowFormat,
+    layout: ArrowLayout,
+}
+
+impl ArrowWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the file format
+    pub fn with_format(mut self, format: ArrowFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Set the arrangement of the columns
+    pub fn with_layout(mut self, layout: ArrowLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// The file format written
+    pub fn format(&self) -> ArrowFormat {
+        self.format
+    }
+
+    // The schema and the record batches of the table, with one batch per trace in the tidy layout
+    fn batches(&self, spc: &ParsedSPC) -> miette::Result<(SchemaRef, Vec<RecordBatch>)> {
+        let float = |name: &str| Field::new(name, DataType::Float64, true);
+        let metadata = ParsedMetadata::new(spc)
+            .entries()?
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        match self.layout {
+            ArrowLayout::Tidy => {
+                let schema = Arc::new(
+                    Schema::new(vec![
+                        Field::new("trace_index", DataType::UInt32, false),
+                        float("z"),
+                        float("w"),
+                        float("x"),
+                        float("y"),
+                    ])
+                    .with_metadata(metadata),
+                );
+
+                let planes = spc.planes()?;
+                let mut batches = Vec::new();
+                for plane in planes {
+                    for trace in plane.traces() {
+                        let len = trace.x().len();
+                        let columns: Vec<ArrayRef> = vec![
+                            Arc::new(UInt32Array::from(vec![trace.index() as u32; len])),
+                            Arc::new(Float64Array::from(vec![trace.z(); len])),
+                            Arc::new(Float64Array::from(vec![plane.w(); len])),
+                            Arc::new(Float64Array::from(trace.x().to_vec())),
+                            Arc::new(Float64Array::from(trace.y().to_vec())),
+                        ];
+                        batches
+                            .push(RecordBatch::try_new(schema.clone(), columns).into_diagnostic()?);
+                    }
+                }
+                Ok((schema, batches))
+            }
+            ArrowLayout::Wide => {
+                let traces = spc.traces();
+                let mut fields = Vec::new();
+                let mut columns = Vec::<Vec<Option<f64>>>::new();
+                if let Block::XYXY { .. } = spc.block {
+                    for trace in &traces {
+                        fields.push(float(&format!("x_{}", trace.index())));
+                        columns.push(trace.x().iter().copied().map(Some).collect());
+                        fields.push(float(&format!("y_{}", trace.index())));
+                        columns.push(trace.y().iter().copied().map(Some).collect());
+                    }
+                } else {
+                    let x = traces.first().map(|trace| trace.x()).unwrap_or_default();
+                    fields.push(float("x"));
+                    columns.push(x.iter().copied().map(Some).collect());
+                    for trace in &traces {
+                        fields.push(float(&format!("y_{}", trace.index())));
+                        columns.push(trace.y().iter().copied().map(Some).collect());
+                    }
+                }
+
+                let len = columns.iter().map(Vec::len).max().unwrap_or_default();
+                let columns = columns
+                    .into_iter()
+                    .map(|mut column| {
+                        column.resize(len, None);
+                        Arc::new(Float64Array::from(column)) as ArrayRef
+                    })
+                    .collect();
+                let schema = Arc::new(Schema::new(fields).with_metadata(metadata));
+                let batch = RecordBatch::try_new(schema.clone(), columns).into_diagnostic()?;
+                Ok((schema, vec![batch]))
+            }
+        }
+    }
+}
+
+impl WriteSPC for ArrowWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        let (schema, batches) = self.batches(spc)?;
+        match self.format {
+            ArrowFormat::Parquet => {
+                // The Parquet writer requires a sink which can be sent between threads
+                let mut buffer = Vec::new();
+                let mut parquet = parquet::arrow::ArrowWriter::try_new(&mut buffer, schema, None)
+                    .into_diagnostic()?;
+                for batch in &batches {
+                    parquet.write(batch).into_diagnostic()?;
+                }
+                parquet.close().into_diagnostic()?;
+                writer.write_all(&buffer).into_diagnostic()
+            }
+            ArrowFormat::Ipc => {
+                let mut ipc = FileWriter::try_new(writer, &schema).into_diagnostic()?;
+                for batch in &batches {
+                    ipc.write(batch).into_diagnostic()?;
+                }
+                ipc.finish().into_diagnostic()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use arrow::{array::AsArray, datatypes::Float64Type, ipc::reader::FileReader};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::{ArrowFormat, ArrowLayout, ArrowWriter};
+    use crate::{fixtures::multifile, write::WriteSPC, Header};
+
+    #[test]
+    fn tables_are_written_in_both_layouts_and_formats() {
+        let spc = multifile();
+
+        let mut ipc = Vec::new();
+        ArrowWriter::new()
+            .with_format(ArrowFormat::Ipc)
+            .write_spc(&mut ipc, &spc)
+            .unwrap();
+        let reader = FileReader::try_new(Cursor::new(ipc), None).unwrap();
+        assert_eq!(reader.schema().metadata()["memo"], "kinetics");
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 2);
+        let y = batches[1].column_by_name("y").unwrap();
+        assert_eq!(y.as_primitive::<Float64Type>().values(), &[-4.0, 8.0, 0.0]);
+
+        let mut parquet = Vec::new();
+        ArrowWriter::new()
+            .with_layout(ArrowLayout::Wide)
+            .write_spc(&mut parquet, &spc)
+            .unwrap();
+        let builder =
+            ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(parquet)).unwrap();
+        assert_eq!(builder.schema().metadata()["memo"], "kinetics");
+        let batch = builder.build().unwrap().next().unwrap().unwrap();
+        let names = batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["x", "y_0", "y_1"]);
+        let y = batch.column_by_name("y_1").unwrap();
+        assert_eq!(y.as_primitive::<Float64Type>().values(), &[-4.0, 8.0, 0.0]);
+
+        // Subfiles which cannot be split into the w-planes of the header are an error
+        let mut spc = spc;
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.w_planes = 3;
+        assert!(ArrowWriter::new().write_spc(&mut Vec::new(), &spc).is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Writing mapped multifiles as ENVI hyperspectral cubes.
+//!
+//! Each trace is one pixel of the map, and each point of the shared x-axis is one band. The cube
+//! is written as raw little-endian doubles, with a separate `.hdr` text header giving the map
+//! dimensions, the interleave, the x-value of each band as its `wavelength`, and the axis units.
+//!
+//! Pixels are filled line by line in the order the traces are stored. Unless the geometry of the
+//! map is given, files with w-planes are taken to have one line per w-plane and one sample per
+//! trace in each plane. Otherwise the geometry is inferred from the z-values: runs of equal z are
+//! taken as lines, as are repeats of a cycle of z-values, and files with neither are a single line.
+
+use std::io::Write;
+
+use miette::IntoDiagnostic;
+
+use crate::{
+    block::Block,
+    trace::{subfiles_per_plane, Trace},
+    xzwType, ParsedSPC,
+};
+
+use super::WriteSPC;
+
+// The ENVI data type of 64-bit floats
+const DATA_TYPE_DOUBLE: u8 = 5;
+
+/// The order of the values of the cube in the binary file
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum EnviInterleave {
+    /// Band sequential: one whole image per band
+    #[default]
+    Bsq,
+    /// Band interleaved by line: each line of the map holds every band in turn
+    Bil,
+    /// Band interleaved by pixel: each pixel holds its whole spectrum
+    Bip,
+}
+
+impl EnviInterleave {
+    /// The extension of the binary file, which is also the name of the interleave in the header
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Bsq => "bsq",
+            Self::Bil => "bil",
+            Self::Bip => "bip",
+        }
+    }
+}
+
+/// Writes the traces of a mapped multifile as an ENVI cube and its header
+#[derive(Clone, Debug, Default)]
+pub struct EnviWriter {
+    interleave: EnviInterleave,
+    geometry: Option<(usize, usize)>,
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum EnviError {
+    #[error("the traces of an XYXY file have their own x-values, so cannot share bands")]
+    Xyxy,
+    #[error("{traces} traces cannot fill a map of {lines} lines by {samples} samples")]
+    #[diagnostic(help("give the number of lines and samples of the map"))]
+    Geometry {
+        lines: usize,
+        samples: usize,
+        traces: usize,
+    },
+}
+
+// The traces of a file arranged as a map
+struct Cube<'spc> {
+    lines: usize,
+    samples: usize,
+    traces: Vec<Trace<'spc>>,
+}
+
+impl EnviWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the order of the values in the binary file, band sequential by default
+    pub fn with_interleave(mut self, interleave: EnviInterleave) -> Self {
+        self.interleave = interleave;
+        self
+    }
+
+    /// Set the number of lines and samples of the map, rather than inferring them from the
+    /// w-planes or z-values
+    pub fn with_geometry(mut self, lines: usize, samples: usize) -> Self {
+        self.geometry = Some((lines, samples));
+        self
+    }
+
+    pub fn interleave(&self) -> EnviInterleave {
+        self.interleave
+    }
+
+    fn cube<'spc>(&self, spc: &'spc ParsedSPC) -> miette::Result<Cube<'spc>> {
+        if let Block::XYXY { .. } = spc.block {
+            return Err(EnviError::Xyxy.into());
+        }
+        let traces = spc.traces();
+        let (lines, samples) = match self.geometry {
+            Some(geometry) => geometry,
+            None => match spc.header.w_planes() {
+                0 => z_geometry(&traces.iter().map(Trace::z).collect::<Vec<_>>()),
+                planes => (planes, subfiles_per_plane(traces.len(), planes)?),
+            },
+        };
+        if lines.checked_mul(samples) != Some(traces.len()) {
+            return Err(EnviError::Geometry {
+                lines,
+                samples,
+                traces: traces.len(),
+            }
+            .into());
+        }
+        Ok(Cube {
+            lines,
+            samples,
+            traces,
+        })
+    }
+
+    /// Write the `.hdr` text header describing the cube written by [`WriteSPC::write_spc`]
+    pub fn write_header<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> miette::Result<()> {
+        let cube = self.cube(spc)?;
+        let x = cube
+            .traces
+            .first()
+            .map(|trace| trace.x())
+            .unwrap_or_default();
+        let [x_label, y_label, _] = spc.header.axis_labels();
+        let wavelength_units = match spc.header.x_unit_type() {
+            xzwType::Wavenumber => "Wavenumber",
+            xzwType::Micrometers => "Micrometers",
+            xzwType::Nanometers => "Nanometers",
+            _ => "Unknown",
+        };
+        // Braces delimit lists in the header, so cannot appear in the description
+        let description = spc.header.memo().to_string().replace(['{', '}'], "");
+        let wavelength = x.iter().map(f64::to_string).collect::<Vec<_>>();
+
+        let header = format!(
+            "ENVI
+description = {{{description}}}
+samples = {samples}
+lines = {lines}
+bands = {bands}
+header offset = 0
+file type = ENVI Standard
+data type = {DATA_TYPE_DOUBLE}
+interleave = {interleave}
+byte order = 0
+wavelength units = {wavelength_units}
+z plot titles = {{{x_label}, {y_label}}}
+wavelength = {{{wavelength}}}
+",
+            samples = cube.samples,
+            lines = cube.lines,
+            bands = x.len(),
+            interleave = self.interleave.extension(),
+            wavelength = wavelength.join(", "),
+        );
+        writer.write_all(header.as_bytes()).into_diagnostic()
+    }
+}
+
+// The lines and samples of a map without w-planes, from the z-values of its traces. The map is
+// split into lines at each change of z when every run of equal z-values has the same length, such
+// as the y-coordinates of a raster, or at each repeat of the first z-value when the z-values
+// cycle, such as the x-coordinates. Otherwise it is a single line
+fn z_geometry(z: &[f64]) -> (usize, usize) {
+    let traces = z.len();
+    let run = z.iter().take_while(|&&value| value == z[0]).count();
+    if run > 1 && run < traces && traces.is_multiple_of(run) {
+        let runs = z.chunks(run).collect::<Vec<_>>();
+        let constant = runs
+            .iter()
+            .all(|line| line.iter().all(|&value| value == line[0]));
+        let distinct = runs.windows(2).all(|pair| pair[0][0] != pair[1][0]);
+        if constant && distinct {
+            return (traces / run, run);
+        }
+    }
+    if let Some(period) = z.iter().skip(1).position(|&value| value == z[0]) {
+        let period = period + 1;
+        let cycles =
+            traces.is_multiple_of(period) && (period..traces).all(|ii| z[ii] == z[ii - period]);
+        if period > 1 && cycles {
+            return (traces / period, period);
+        }
+    }
+    (1, traces)
+}
+
+impl WriteSPC for EnviWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        let Cube {
+            lines,
+            samples,
+            traces,
+        } = self.cube(spc)?;
+        let bands = traces.first().map(|trace| trace.y().len()).unwrap_or(0);
+        let value = |line: usize, sample: usize, band: usize| {
+            traces[line * samples + sample].y()[band].to_le_bytes()
+        };
+
+        let mut cube = Vec::with_capacity(lines * samples * bands * 8);
+        match self.interleave {
+            EnviInterleave::Bsq => {
+                for band in 0..bands {
+                    for line in 0..lines {
+                        for sample in 0..samples {
+                            cube.extend(value(line, sample, band));
+                        }
+                    }
+                }
+            }
+            EnviInterleave::Bil => {
+                for line in 0..lines {
+                    for band in 0..bands {
+                        for sample in 0..samples {
+                            cube.extend(value(line, sample, band));
+                        }
+                    }
+                }
+            }
+            EnviInterleave::Bip => {
+                for line in 0..lines {
+                    for sample in 0..samples {
+                        for band in 0..bands {
+                            cube.extend(value(line, sample, band));
+                        }
+                    }
+                }
+            }
+        }
+        writer.write_all(&cube).into_diagnostic()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EnviInterleave, EnviWriter};
+    use crate::{block::Block, write::WriteSPC, CsvReader, Header};
+
+    fn values(cube: &[u8]) -> Vec<f64> {
+        cube.chunks_exact(8)
+            .map(|value| f64::from_le_bytes(value.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn maps_are_written_in_every_interleave() {
+        // Six pixels of two bands, with the value 10 * pixel + band
+        let spc = CsvReader::new()
+            .read("# memo: map\nx,y_0,y_1,y_2,y_3,y_4,y_5\n500,0,10,20,30,40,50\n600,1,11,21,31,41,51\n")
+            .unwrap();
+
+        let writer = EnviWriter::new().with_geometry(2, 3);
+        let mut header = Vec::new();
+        writer.write_header(&mut header, &spc).unwrap();
+        let header = String::from_utf8(header).unwrap();
+        assert!(header.starts_with("ENVI\ndescription = {map}\n"));
+        assert!(header.contains("samples = 3\nlines = 2\nbands = 2\n"));
+        assert!(header.contains("interleave = bsq\n"));
+        assert!(header.contains("wavelength = {500, 600}\n"));
+
+        let mut cube = Vec::new();
+        writer.write_spc(&mut cube, &spc).unwrap();
+        assert_eq!(
+            values(&cube),
+            [0., 10., 20., 30., 40., 50., 1., 11., 21., 31., 41., 51.]
+        );
+        let mut cube = Vec::new();
+        let bil = writer.clone().with_interleave(EnviInterleave::Bil);
+        bil.write_spc(&mut cube, &spc).unwrap();
+        assert_eq!(
+            values(&cube),
+            [0., 10., 20., 1., 11., 21., 30., 40., 50., 31., 41., 51.]
+        );
+        let mut cube = Vec::new();
+        let bip = writer.with_interleave(EnviInterleave::Bip);
+        bip.write_spc(&mut cube, &spc).unwrap();
+        assert_eq!(
+            values(&cube),
+            [0., 1., 10., 11., 20., 21., 30., 31., 40., 41., 50., 51.]
+        );
+
+        // Without w-planes the map is a single line, and otherwise each plane is a line
+        let mut header = Vec::new();
+        EnviWriter::new().write_header(&mut header, &spc).unwrap();
+        assert!(String::from_utf8(header)
+            .unwrap()
+            .contains("samples = 6\nlines = 1\n"));
+        let mut planes = spc.clone();
+        let Header::New(header) = &mut planes.header else {
+            unreachable!()
+        };
+        header.w_planes = 2;
+        let mut header = Vec::new();
+        EnviWriter::new()
+            .write_header(&mut header, &planes)
+            .unwrap();
+        assert!(String::from_utf8(header)
+            .unwrap()
+            .contains("samples = 3\nlines = 2\n"));
+
+        // Without w-planes, runs of equal z-values or a repeating cycle of them are lines
+        for z in [[0., 0., 0., 5., 5., 5.], [1., 2., 3., 1., 2., 3.]] {
+            let mut raster = spc.clone();
+            let Header::New(header) = &mut raster.header else {
+                unreachable!()
+            };
+            header.flags = header.flags.with_uneven_z(true);
+            let Block::YY(ys) = &mut raster.block else {
+                unreachable!()
+            };
+            for (subfile, z) in ys.iter_mut().zip(z) {
+                subfile.subheader.z = z as f32;
+            }
+            let mut header = Vec::new();
+            EnviWriter::new()
+                .write_header(&mut header, &raster)
+                .unwrap();
+            assert!(String::from_utf8(header)
+                .unwrap()
+                .contains("samples = 3\nlines = 2\n"));
+        }
+
+        // The geometry must hold every trace
+        assert!(EnviWriter::new()
+            .with_geometry(2, 2)
+            .write_spc(&mut Vec::new(), &spc)
+            .is_err());
+        assert!(EnviWriter::new()
+            .with_geometry(usize::MAX, 2)
+            .write_spc(&mut Vec::new(), &spc)
+            .is_err());
+    }
+}
@@ -0,0 +1,344 @@
+//! Writing the traces of a file as a MATLAB Level 5 MAT-file.
+//!
+//! The file holds a single struct, named `spc` unless another name is given, with the fields:
+//!
+//! - `x`: the x-values shared by every trace, as a row vector
+//! - `y`: the y-values, with one row per trace for multifiles and a single row otherwise
+//! - `z`: the z-value of each trace, as a row vector
+//! - `x_units`, `y_units` and `z_units`: the axis labels, as strings
+//! - `header`: a struct holding the fields of the header
+//!
+//! The traces of XYXY files can have different lengths, so `x` and `y` are then cell arrays with
+//! one row vector per trace.
+
+use std::io::Write;
+
+use chrono::Utc;
+use miette::IntoDiagnostic;
+use serde_json::Value;
+
+use crate::{block::Block, metadata::ParsedMetadata, ParsedSPC};
+
+use super::WriteSPC;
+
+// The data types of the elements written
+const MI_INT8: u32 = 1;
+const MI_UINT8: u32 = 2;
+const MI_UINT16: u32 = 4;
+const MI_INT32: u32 = 5;
+const MI_UINT32: u32 = 6;
+const MI_DOUBLE: u32 = 9;
+const MI_MATRIX: u32 = 14;
+
+// The classes of the arrays written
+const MX_CELL: u32 = 1;
+const MX_STRUCT: u32 = 2;
+const MX_CHAR: u32 = 4;
+const MX_DOUBLE: u32 = 6;
+const MX_UINT8: u32 = 9;
+
+// The array flag marking an unsigned byte array as logical
+const LOGICAL: u32 = 0x0200;
+
+// Field names are stored in fixed-width slots, and MATLAB allows up to 63 characters
+const FIELD_NAME_LENGTH: usize = 64;
+
+/// Writes the traces and header of a file as a MATLAB Level 5 MAT-file
+#[derive(Clone, Debug)]
+pub struct MatWriter {
+    variable_name: String,
+}
+
+impl Default for MatWriter {
+    fn default() -> Self {
+        Self {
+            variable_name: "spc".to_owned(),
+        }
+    }
+}
+
+// An array as stored in the file
+#[derive(Clone, Debug)]
+enum MatValue {
+    // Dimensions are rows then columns, and values are stored column by column
+    Double { dims: [usize; 2], values: Vec<f64> },
+    Char(String),
+    Logical(bool),
+    Cell(Vec<MatValue>),
+    Struct(Vec<(String, MatValue)>),
+}
+
+impl MatValue {
+    fn row(values: &[f64]) -> Self {
+        Self::Double {
+            dims: [1, values.len()],
+            values: values.to_vec(),
+        }
+    }
+
+    // A header value, with objects as structs and lists as cell arrays
+    fn from_json(value: Value) -> Self {
+        match value {
+            Value::Null => Self::Double {
+                dims: [0, 0],
+                values: Vec::new(),
+            },
+            Value::Bool(value) => Self::Logical(value),
+            Value::Number(number) => Self::row(&[number.as_f64().unwrap_or(f64::NAN)]),
+            Value::String(value) => Self::Char(value),
+            Value::Array(values) => Self::Cell(values.into_iter().map(Self::from_json).collect()),
+            Value::Object(fields) => Self::Struct(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, Self::from_json(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    // The array as a matrix element, with its name
+    fn encode(&self, name: &str) -> Vec<u8> {
+        let (class, dims, body) = match self {
+            Self::Double { dims, values } => (
+                MX_DOUBLE,
+                *dims,
+                element(
+                    MI_DOUBLE,
+                    &values
+                        .iter()
+                        .flat_map(|value| value.to_le_bytes())
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            Self::Char(value) => {
+                let units = value.encode_utf16().collect::<Vec<_>>();
+                (
+                    MX_CHAR,
+                    [1, units.len()],
+                    element(
+                        MI_UINT16,
+                        &units
+                            .iter()
+                            .flat_map(|unit| unit.to_le_bytes())
+                            .collect::<Vec<_>>(),
+                    ),
+                )
+            }
+            Self::Logical(value) => (
+                MX_UINT8 | LOGICAL,
+                [1, 1],
+                element(MI_UINT8, &[u8::from(*value)]),
+            ),
+            Self::Cell(values) => (
+                MX_CELL,
+                [1, values.len()],
+                values.iter().flat_map(|value| value.encode("")).collect(),
+            ),
+            Self::Struct(fields) => {
+                let mut names = Vec::new();
+                for (name, _) in fields {
+                    let mut slot = field_name(name).into_bytes();
+                    slot.resize(FIELD_NAME_LENGTH, 0);
+                    names.extend(slot);
+                }
+                let mut body = element(MI_INT32, &(FIELD_NAME_LENGTH as i32).to_le_bytes());
+                body.extend(element(MI_INT8, &names));
+                for (_, value) in fields {
+                    body.extend(value.encode(""));
+                }
+                (MX_STRUCT, [1, 1], body)
+            }
+        };
+
+        let mut matrix = element(MI_UINT32, &[class.to_le_bytes(), [0; 4]].concat());
+        matrix.extend(element(
+            MI_INT32,
+            &dims
+                .iter()
+                .flat_map(|&dim| (dim as i32).to_le_bytes())
+                .collect::<Vec<_>>(),
+        ));
+        matrix.extend(element(MI_INT8, name.as_bytes()));
+        matrix.extend(body);
+        element(MI_MATRIX, &matrix)
+    }
+}
+
+impl MatWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the name of the struct in the workspace, `spc` by default
+    pub fn with_variable_name(mut self, variable_name: &str) -> Self {
+        self.variable_name = field_name(variable_name);
+        self
+    }
+
+    // The struct holding the traces and header
+    fn value(&self, spc: &ParsedSPC) -> miette::Result<MatValue> {
+        let traces = spc.traces();
+        let (x, y) = match spc.block {
+            Block::XYXY { .. } => (
+                MatValue::Cell(
+                    traces
+                        .iter()
+                        .map(|trace| MatValue::row(trace.x()))
+                        .collect(),
+                ),
+                MatValue::Cell(
+                    traces
+                        .iter()
+                        .map(|trace| MatValue::row(trace.y()))
+                        .collect(),
+                ),
+            ),
+            _ => {
+                let x = traces.first().map(|trace| trace.x()).unwrap_or_default();
+                // Each column holds one point of every trace
+                let y = (0..x.len())
+                    .flat_map(|point| traces.iter().map(move |trace| trace.y()[point]))
+                    .collect();
+                (
+                    MatValue::row(x),
+                    MatValue::Double {
+                        dims: [traces.len(), x.len()],
+                        values: y,
+                    },
+                )
+            }
+        };
+        let z = traces.iter().map(|trace| trace.z()).collect::<Vec<_>>();
+
+        let [x_units, y_units, z_units] = spc.header.axis_labels();
+        let header = ParsedMetadata::new(spc).header_value()?;
+        Ok(MatValue::Struct(vec![
+            ("x".to_owned(), x),
+            ("y".to_owned(), y),
+            ("z".to_owned(), MatValue::row(&z)),
+            ("x_units".to_owned(), MatValue::Char(x_units)),
+            ("y_units".to_owned(), MatValue::Char(y_units)),
+            ("z_units".to_owned(), MatValue::Char(z_units)),
+            ("header".to_owned(), MatValue::from_json(header)),
+        ]))
+    }
+}
+
+impl WriteSPC for MatWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        // The descriptive text is padded with spaces, followed by an unused subsystem data
+        // offset, the version and the endian indicator
+        let mut header = format!(
+            "MATLAB 5.0 MAT-file, Platform: spc-rs, Created on: {}",
+            Utc::now().format("%a %b %e %H:%M:%S %Y")
+        )
+        .into_bytes();
+        header.resize(116, b' ');
+        header.extend([0; 8]);
+        header.extend(0x0100u16.to_le_bytes());
+        header.extend(b"IM");
+
+        writer.write_all(&header).into_diagnostic()?;
+        writer
+            .write_all(&self.value(spc)?.encode(&self.variable_name))
+            .into_diagnostic()
+    }
+}
+
+// A data element: its type, the number of bytes of data, and the data padded to 8 bytes
+fn element(data_type: u32, data: &[u8]) -> Vec<u8> {
+    let mut element = Vec::with_capacity(8 + data.len().next_multiple_of(8));
+    element.extend(data_type.to_le_bytes());
+    element.extend((data.len() as u32).to_le_bytes());
+    element.extend(data);
+    element.resize(8 + data.len().next_multiple_of(8), 0);
+    element
+}
+
+// A valid MATLAB identifier: letters, digits and underscores, starting with a letter
+fn field_name(name: &str) -> String {
+    let mut field = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    if !field.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        field.insert(0, 'f');
+    }
+    field.truncate(FIELD_NAME_LENGTH - 1);
+    field
+}
+
+#[cfg(test)]
+mod test {
+    use super::MatWriter;
+    use crate::{
+        fixtures::{multifile, xyxy},
+        write::WriteSPC,
+    };
+
+    // The class, dimensions, name and remaining sub-elements of the matrix element at the start of
+    // the bytes, and the bytes after it
+    fn matrix(bytes: &[u8]) -> (u8, Vec<i32>, String, &[u8], &[u8]) {
+        let word = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+        assert_eq!(word(0), 14);
+        let end = 8 + word(4);
+        let class = bytes[16];
+        let dims_len = word(28);
+        let dims = bytes[32..32 + dims_len]
+            .chunks_exact(4)
+            .map(|dim| i32::from_le_bytes(dim.try_into().unwrap()))
+            .collect();
+        let name_at = 32 + dims_len.next_multiple_of(8);
+        let name_len = word(name_at + 4);
+        let name = String::from_utf8(bytes[name_at + 8..name_at + 8 + name_len].to_vec()).unwrap();
+        let body = name_at + 8 + name_len.next_multiple_of(8);
+        (class, dims, name, &bytes[body..end], &bytes[end..])
+    }
+
+    #[test]
+    fn traces_are_written_as_a_struct_with_a_cell_array_for_xyxy_files() {
+        let spc = multifile();
+        let mut file = Vec::new();
+        MatWriter::new().write_spc(&mut file, &spc).unwrap();
+        assert!(file.starts_with(b"MATLAB 5.0 MAT-file"));
+        assert_eq!(&file[124..128], [0x00, 0x01, b'I', b'M']);
+
+        let (class, dims, name, body, rest) = matrix(&file[128..]);
+        assert_eq!((class, dims, name.as_str()), (2, vec![1, 1], "spc"));
+        assert!(rest.is_empty());
+        let names = body[24..24 + 7 * 64]
+            .chunks_exact(64)
+            .map(|name| {
+                String::from_utf8_lossy(name)
+                    .trim_end_matches('\0')
+                    .to_owned()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            ["x", "y", "z", "x_units", "y_units", "z_units", "header"]
+        );
+
+        // The traces are rows, so the second value is the first point of the second trace
+        let (_, x, _, _, rest) = matrix(&body[24 + 7 * 64..]);
+        assert_eq!(x, [1, 3]);
+        let (class, dims, _, y, _) = matrix(rest);
+        assert_eq!((class, dims), (6, vec![2, 3]));
+        assert_eq!(&y[16..24], (-4.0f64).to_le_bytes());
+
+        let xyxy = xyxy();
+        let mut file = Vec::new();
+        MatWriter::new()
+            .with_variable_name("2 traces")
+            .write_spc(&mut file, &xyxy)
+            .unwrap();
+        let (_, _, name, body, _) = matrix(&file[128..]);
+        assert_eq!(name, "f2_traces");
+        let (class, dims, _, cells, _) = matrix(&body[24 + 7 * 64..]);
+        assert_eq!((class, dims), (1, vec![1, 2]));
+        let (_, first, _, _, rest) = matrix(cells);
+        let (_, second, _, _, _) = matrix(rest);
+        assert_eq!((first, second), (vec![1, 2], vec![1, 1]));
+    }
+}
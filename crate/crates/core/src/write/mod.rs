@@ -1,102 +1,470 @@
 use std::io::Write;
 
 use csv::WriterBuilder;
-use serde::Serialize;
 
-use crate::{block::Block, ParsedSPC};
+use crate::{block::Block, trace::Plane, xzwType, ParsedSPC};
+
+mod andi;
+#[cfg(feature = "arrow")]
+mod columnar;
+mod envi;
+mod mat;
+mod mzml;
+mod netcdf;
+mod npz;
+mod spc;
+mod xlsx;
+
+pub use andi::AndiWriter;
+#[cfg(feature = "arrow")]
+pub use columnar::{ArrowFormat, ArrowLayout, ArrowWriter};
+pub use envi::{EnviInterleave, EnviWriter};
+pub use mat::MatWriter;
+pub use mzml::MzmlWriter;
+pub use npz::NpzWriter;
+pub use spc::{SpcVersion, SpcWriter};
+pub use xlsx::XlsxWriter;
 
 pub trait WriteSPC {
     type Error;
     fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error>;
 }
 
-pub(crate) struct CsvWriter;
+// The number of seconds in one unit of time, or `None` if the unit is not a time
+fn seconds(unit: xzwType) -> Option<f64> {
+    match unit {
+        xzwType::Nanoseconds => Some(1e-9),
+        xzwType::Microseconds => Some(1e-6),
+        xzwType::Milliseconds => Some(1e-3),
+        xzwType::Seconds => Some(1.0),
+        xzwType::Minutes => Some(60.0),
+        xzwType::Hours => Some(3600.0),
+        _ => None,
+    }
+}
 
-impl WriteSPC for CsvWriter {
-    type Error = csv::Error;
-    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
-        let mut writer = WriterBuilder::new()
-            .has_headers(true)
-            .comment(Some(b'#'))
-            .from_writer(writer);
-
-        #[derive(Debug, Serialize)]
-        struct Record {
-            x: f64,
-            y: f64,
+/// The arrangement of the table written by the [`CsvWriter`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CsvLayout {
+    /// A layout which depends on the shape of the data. Files with a shared x-axis are written
+    /// with one column per trace, and XYXY files as one block of rows per trace
+    #[default]
+    Wide,
+    /// One row per point, with columns `trace_index`, `z`, `w`, `x` and `y`, for every shape of
+    /// data. The `w` column is empty for files without w-planes
+    Tidy,
+}
+
+/// The character separating the fields of each row
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Delimiter {
+    #[default]
+    Comma,
+    Tab,
+    Semicolon,
+}
+
+impl Delimiter {
+    pub(crate) fn byte(self) -> u8 {
+        match self {
+            Self::Comma => b',',
+            Self::Tab => b'\t',
+            Self::Semicolon => b';',
+        }
+    }
+}
+
+/// How floating point values are written
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FloatFormat {
+    /// The shortest representation which reads back as the same value. Values which are exact in
+    /// single precision, as SPC files store most values, are written as the shortest
+    /// representation which reads back as the same single precision value
+    #[default]
+    Shortest,
+    /// A fixed number of digits after the decimal point
+    Precision(usize),
+}
+
+/// Writes the data in an SPC file as CSV
+#[derive(Clone, Debug)]
+pub struct CsvWriter {
+    layout: CsvLayout,
+    subheader_columns: bool,
+    delimiter: Delimiter,
+    comment: u8,
+    decimal_comma: bool,
+    float_format: FloatFormat,
+    unit_column_names: bool,
+    preamble: bool,
+}
+
+impl Default for CsvWriter {
+    fn default() -> Self {
+        Self {
+            layout: CsvLayout::default(),
+            subheader_columns: false,
+            delimiter: Delimiter::default(),
+            comment: b'#',
+            decimal_comma: false,
+            float_format: FloatFormat::default(),
+            unit_column_names: false,
+            preamble: false,
         }
+    }
+}
+
+impl CsvWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the arrangement of the table
+    pub fn with_layout(mut self, layout: CsvLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Add the subheader fields of each trace as extra columns in the tidy layout: `flags`,
+    /// `exponent`, `next_z`, `noise`, `scans`, `w_level` and `time`. The wide layout has no room
+    /// for them, so this is ignored there
+    pub fn with_subheader_columns(mut self, subheader_columns: bool) -> Self {
+        self.subheader_columns = subheader_columns;
+        self
+    }
+
+    /// Set the character separating the fields of each row
+    pub fn with_delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Set the character starting comment lines, such as the preamble and the z markers in the
+    /// wide layout of XYXY files
+    pub fn with_comment(mut self, comment: u8) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Write a comma rather than a point before the fractional part of each value, as expected by
+    /// spreadsheet software in many European locales. Values are quoted if the delimiter is also
+    /// a comma, so this is usually combined with [`Delimiter::Semicolon`]
+    pub fn with_decimal_comma(mut self, decimal_comma: bool) -> Self {
+        self.decimal_comma = decimal_comma;
+        self
+    }
+
+    /// Set how floating point values are written
+    pub fn with_float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Name the x, y and z columns after the axis labels in the header, see
+    /// [`Header::axis_labels`](crate::Header::axis_labels), rather than `x`, `y` and `z`
+    pub fn with_unit_column_names(mut self, unit_column_names: bool) -> Self {
+        self.unit_column_names = unit_column_names;
+        self
+    }
+
+    /// Start the output with the decoded header, written as comment lines
+    pub fn with_preamble(mut self, preamble: bool) -> Self {
+        self.preamble = preamble;
+        self
+    }
 
-        let exponent = spc.header.exponent_y();
+    fn float(&self, value: f64) -> String {
+        let formatted = match self.float_format {
+            FloatFormat::Shortest if value as f32 as f64 == value => format!("{:?}", value as f32),
+            FloatFormat::Shortest => format!("{value:?}"),
+            FloatFormat::Precision(precision) => format!("{value:.precision$}"),
+        };
+        if self.decimal_comma {
+            formatted.replace('.', ",")
+        } else {
+            formatted
+        }
+    }
 
-        println!("exponent: {:x?}", exponent);
+    fn optional_float(&self, value: Option<f64>) -> String {
+        value.map(|value| self.float(value)).unwrap_or_default()
+    }
 
-        if exponent == -128 {
-            panic!("no impl for float data storage");
+    // The names of the x, y and z columns
+    fn axis_names(&self, spc: &ParsedSPC) -> [String; 3] {
+        if self.unit_column_names {
+            spc.header.axis_labels()
+        } else {
+            ["x".to_owned(), "y".to_owned(), "z".to_owned()]
         }
+    }
+
+    fn csv_writer<W: Write>(&self, writer: W) -> csv::Writer<W> {
+        WriterBuilder::new()
+            .delimiter(self.delimiter.byte())
+            .comment(Some(self.comment))
+            .flexible(true)
+            .from_writer(writer)
+    }
+
+    // Comment lines are written directly to the output, so they are not quoted as a field
+    fn write_comment<W: Write>(&self, writer: &mut W, comment: &str) -> Result<(), csv::Error> {
+        writeln!(writer, "{} {comment}", self.comment as char)?;
+        Ok(())
+    }
+
+    fn write_wide<W: Write>(&self, output: &mut W, spc: &ParsedSPC) -> Result<(), csv::Error> {
+        let [x_name, y_name, z_name] = self.axis_names(spc);
+        let mut writer = self.csv_writer(&mut *output);
+        // For all shapes the y-values are scaled by the exponent in the header, the exponent in
+        // the subheader is ignored
+        let traces = spc.traces();
 
         match &spc.block {
-            // For Y-data the exponent in the subheader is ignored, and that in the header is used
-            // instead
-            Block::Y(block) => {
-                let x = spc.header.x_points();
-                let y = block.data.decode(exponent);
-                for (x, y) in x.into_iter().zip(y) {
-                    let record = Record { x, y };
-                    writer.serialize(record)?;
+            Block::Y(_) | Block::XY { .. } => {
+                writer.write_record([&x_name, &y_name])?;
+                for (x, y) in traces[0].points() {
+                    writer.write_record([self.float(x), self.float(y)])?;
+                }
+            }
+            // Evenly spaced and XYY multifiles share one x-axis, so each trace is a column
+            Block::YY(_) | Block::XYY { .. } => {
+                let x = traces.first().map(|trace| trace.x()).unwrap_or_default();
+                writer.write_record(
+                    std::iter::once(x_name.clone()).chain(
+                        traces
+                            .iter()
+                            .map(|trace| format!("{y_name}_{}", trace.index())),
+                    ),
+                )?;
+                for (ii, x) in x.iter().enumerate() {
+                    writer.write_record(
+                        std::iter::once(self.float(*x)).chain(
+                            traces
+                                .iter()
+                                .map(|trace| self.optional_float(trace.y().get(ii).copied())),
+                        ),
+                    )?;
                 }
             }
-            // For XY-data the exponent in the subheader is ignored, and that in the header is used
-            // instead
-            Block::XY { x, y } => {
-                let y = y.data.decode(exponent);
-                for (x, y) in x.iter().zip(y) {
-                    let record = Record { x: (*x).into(), y };
-                    writer.serialize(record)?;
+            Block::XYXY { .. } => {
+                writer.write_record([&x_name, &y_name])?;
+                writer.flush()?;
+                drop(writer);
+                for trace in &traces {
+                    // The directory holds the time of each subfile, for GC-MS style files
+                    let z = self.float(trace.subheader().z() as f64);
+                    match trace.time() {
+                        Some(time) => self.write_comment(
+                            output,
+                            &format!("{z_name} = {z}, time = {}", self.float(time)),
+                        )?,
+                        None => self.write_comment(output, &format!("{z_name} = {z}"))?,
+                    }
+                    let mut writer = self.csv_writer(&mut *output);
+                    for (x, y) in trace.points() {
+                        writer.write_record([self.float(x), self.float(y)])?;
+                    }
+                    writer.flush()?;
                 }
+                return Ok(());
             }
-            // For YY-data is the exponent in the subheader ignored, and that in the header used
-            // instead? It's not clear again...
-            Block::YY(ys) => {
-                let x = spc.header.x_points();
-                let ys: Vec<_> = ys.iter().map(|each| each.data.decode(exponent)).collect();
+        }
+        writer.flush()?;
+        Ok(())
+    }
 
-                assert!(ys.iter().all(|each| each.len() == x.len()));
+    fn write_tidy<W: Write>(&self, output: &mut W, spc: &ParsedSPC) -> Result<(), csv::Error> {
+        let [x_name, y_name, z_name] = self.axis_names(spc);
+        let mut writer = self.csv_writer(output);
+        let mut names = vec!["trace_index", &z_name, "w", &x_name, &y_name];
+        if self.subheader_columns {
+            names.extend([
+                "flags", "exponent", "next_z", "noise", "scans", "w_level", "time",
+            ]);
+        }
+        writer.write_record(&names)?;
 
-                for (ii, x) in x.iter().enumerate() {
-                    let record: Vec<f64> = std::iter::once(*x)
-                        .chain(ys.iter().map(|each| each[ii]))
-                        .collect::<Vec<_>>();
+        // A file whose subfiles cannot be split into its w-planes is written without w-values
+        let planes = spc
+            .planes()
+            .unwrap_or_else(|_| vec![Plane::new(0, None, spc.traces())]);
 
-                    writer.serialize(record)?;
+        for plane in planes {
+            let w = self.optional_float(plane.w());
+            for trace in plane.traces() {
+                let index = trace.index().to_string();
+                let z = self.float(trace.z());
+                let subheader = trace.subheader();
+                let subheader_fields = if self.subheader_columns {
+                    vec![
+                        subheader.flags().bits().to_string(),
+                        subheader
+                            .exponent_y()
+                            .map(|exponent| exponent.to_string())
+                            .unwrap_or_default(),
+                        self.float(subheader.next_z() as f64),
+                        self.optional_float(subheader.noise().map(f64::from)),
+                        subheader.scans().to_string(),
+                        self.float(subheader.w_level() as f64),
+                        self.optional_float(trace.time()),
+                    ]
+                } else {
+                    Vec::new()
+                };
+
+                for (x, y) in trace.points() {
+                    writer.write_record(
+                        [&index, &z, &w, &self.float(x), &self.float(y)]
+                            .into_iter()
+                            .chain(&subheader_fields),
+                    )?;
                 }
             }
-            Block::XYY { x, ys } => {
-                let ys: Vec<_> = ys.iter().map(|each| each.data.decode(exponent)).collect();
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
 
-                assert!(ys.iter().all(|each| each.len() == x.len()));
+impl WriteSPC for CsvWriter {
+    type Error = csv::Error;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        if self.preamble {
+            for line in spc.header.to_string().lines() {
+                self.write_comment(writer, line)?;
+            }
+        }
 
-                for (ii, x) in x.iter().enumerate() {
-                    let record: Vec<f64> = std::iter::once(*x as f64)
-                        .chain(ys.iter().map(|each| each[ii]))
-                        .collect::<Vec<_>>();
+        match self.layout {
+            CsvLayout::Wide => self.write_wide(writer, spc),
+            CsvLayout::Tidy => self.write_tidy(writer, spc),
+        }
+    }
+}
 
-                    writer.serialize(record)?;
-                }
-            }
-            Block::XYXY { data, directory } => {
-                for (x, y) in data {
-                    let z = y.subheader.z;
-                    writer.write_record(&[format!("# z = {z}")])?;
-                    let y = y.data.decode(exponent);
-                    for (x, y) in x.iter().zip(y) {
-                        let record = Record { x: (*x).into(), y };
-                        writer.serialize(record)?;
-                    }
-                }
+#[cfg(test)]
+mod test {
+    use super::{CsvLayout, CsvWriter, Delimiter, FloatFormat, WriteSPC};
+    use crate::{parse, CsvReader};
+
+    // A new-format little-endian YY file with two subfiles of two float points, at z = 5 and 6
+    fn yy_file() -> Vec<u8> {
+        let mut source = vec![0; 512];
+        // TMULTI and TORDRD
+        source[0] = 0x14;
+        source[1] = 0x4b;
+        source[4..8].copy_from_slice(&2u32.to_le_bytes());
+        source[8..16].copy_from_slice(&100f64.to_le_bytes());
+        source[16..24].copy_from_slice(&200f64.to_le_bytes());
+        source[24..28].copy_from_slice(&2u32.to_le_bytes());
+
+        for (index, z) in [5f32, 6.].into_iter().enumerate() {
+            let mut subheader = [0; 32];
+            subheader[1] = 0x80;
+            subheader[4..8].copy_from_slice(&z.to_le_bytes());
+            subheader[20..24].copy_from_slice(&(index as u32 + 1).to_le_bytes());
+            source.extend_from_slice(&subheader);
+            for y in [1f32, 2.] {
+                source.extend_from_slice(&(y + 10. * index as f32).to_le_bytes());
             }
         }
+        source
+    }
 
-        writer.flush()?;
-        Ok(())
+    fn write(writer: &CsvWriter, source: &[u8]) -> String {
+        let parsed = parse(source).unwrap();
+        let mut sink = Vec::new();
+        writer.write_spc(&mut sink, &parsed).unwrap();
+        String::from_utf8(sink).unwrap()
+    }
+
+    #[test]
+    fn tidy_layout_writes_one_row_per_point() {
+        let writer = CsvWriter::new().with_layout(CsvLayout::Tidy);
+        assert_eq!(
+            write(&writer, &yy_file()),
+            "trace_index,z,w,x,y\n\
+             0,5.0,,100.0,1.0\n\
+             0,5.0,,200.0,2.0\n\
+             1,6.0,,100.0,11.0\n\
+             1,6.0,,200.0,12.0\n"
+        );
+    }
+
+    #[test]
+    fn tidy_layout_can_include_subheader_columns() {
+        let writer = CsvWriter::new()
+            .with_layout(CsvLayout::Tidy)
+            .with_subheader_columns(true);
+        let output = write(&writer, &yy_file());
+        let mut lines = output.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "trace_index,z,w,x,y,flags,exponent,next_z,noise,scans,w_level,time"
+        );
+        assert_eq!(lines.nth(2).unwrap(), "1,6.0,,100.0,11.0,0,,0.0,,2,0.0,");
+    }
+
+    #[test]
+    fn xyxy_markers_are_written_as_unquoted_comments() {
+        let parsed = CsvReader::new()
+            .read("x,y\n# z = 5, time = 1.5\n1,2\n# z = 6, time = 3\n3,4\n")
+            .unwrap();
+        let mut sink = Vec::new();
+        CsvWriter::new().write_spc(&mut sink, &parsed).unwrap();
+        assert_eq!(
+            String::from_utf8(sink).unwrap(),
+            "x,y\n\
+             # z = 5.0, time = 1.5\n\
+             1.0,2.0\n\
+             # z = 6.0, time = 3.0\n\
+             3.0,4.0\n"
+        );
+
+        // The markers follow the number format of the table, and are read back with it
+        let writer = CsvWriter::new()
+            .with_delimiter(Delimiter::Semicolon)
+            .with_decimal_comma(true)
+            .with_float_format(FloatFormat::Precision(2));
+        let mut sink = Vec::new();
+        writer.write_spc(&mut sink, &parsed).unwrap();
+        let output = String::from_utf8(sink).unwrap();
+        assert!(output.contains("\n# z = 5,00, time = 1,50\n"));
+
+        let read = CsvReader::new()
+            .with_delimiter(Delimiter::Semicolon)
+            .with_decimal_comma(true)
+            .read(&output)
+            .unwrap();
+        let markers = read
+            .traces()
+            .iter()
+            .map(|trace| (trace.subheader().z(), trace.time()))
+            .collect::<Vec<_>>();
+        assert_eq!(markers, [(5.0, Some(1.5)), (6.0, Some(3.0))]);
+    }
+
+    #[test]
+    fn wide_layout_honours_the_formatting_options() {
+        let writer = CsvWriter::new()
+            .with_delimiter(Delimiter::Semicolon)
+            .with_decimal_comma(true)
+            .with_float_format(FloatFormat::Precision(2))
+            .with_unit_column_names(true)
+            .with_preamble(true);
+        let output = write(&writer, &yy_file());
+
+        let (preamble, table): (Vec<_>, Vec<_>) =
+            output.lines().partition(|line| line.starts_with('#'));
+        assert!(preamble.contains(&"# number of subfiles: 2"));
+        assert_eq!(
+            table,
+            [
+                "Arbitrary;Arbitrary intensity_0;Arbitrary intensity_1",
+                "100,00;1,00;11,00",
+                "200,00;2,00;12,00",
+            ]
+        );
     }
 }
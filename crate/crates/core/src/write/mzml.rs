@@ -0,0 +1,353 @@
+//! Writing mass spectra as mzML 1.1, optionally wrapped in an indexedmzML document.
+//!
+//! Each trace becomes a spectrum, with its x-values as the m/z array and its y-values as the
+//! intensity array, both as base64-encoded little-endian doubles. The scan start time is the time
+//! from the directory of an XYXY file, such as a GC-MS run, or the z-value otherwise. Times in
+//! minutes are written in minutes and any other time in seconds. XYXY files hold peak lists, so
+//! their spectra are marked as centroided, and the spectra of other files as profiles.
+
+use std::{
+    fmt::{Display, Write as _},
+    io::Write,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use miette::IntoDiagnostic;
+
+use crate::{block::Block, xzwType, Header, InstrumentTechnique, ParsedSPC};
+
+use super::{seconds, WriteSPC};
+
+// A unit, named by its ontology, accession and name
+type Unit = (&'static str, &'static str, &'static str);
+
+const MZ: Unit = ("MS", "MS:1000040", "m/z");
+const COUNTS: Unit = ("MS", "MS:1000131", "number of detector counts");
+
+/// Writes the traces of a mass spectrum file as the spectra of an mzML document
+#[derive(Clone, Debug)]
+pub struct MzmlWriter {
+    indexed: bool,
+    ms_level: u8,
+}
+
+impl Default for MzmlWriter {
+    fn default() -> Self {
+        Self {
+            indexed: false,
+            ms_level: 1,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum MzmlError {
+    #[error("mzML holds mass spectra, but this is a {0:?}")]
+    NotAMassSpectrum(InstrumentTechnique),
+    #[error("old-format files do not record the instrument technique, so are not mass spectra")]
+    OldFormat,
+}
+
+impl MzmlWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wrap the document in an indexedmzML element, with the offset of each spectrum and a SHA-1
+    /// checksum of the file
+    pub fn with_index(mut self, indexed: bool) -> Self {
+        self.indexed = indexed;
+        self
+    }
+
+    /// Set the MS level of every spectrum, 1 by default
+    pub fn with_ms_level(mut self, ms_level: u8) -> Self {
+        self.ms_level = ms_level;
+        self
+    }
+
+    // The document, with the id and byte offset of each spectrum
+    fn document(&self, spc: &ParsedSPC) -> Result<(String, Vec<(String, usize)>), MzmlError> {
+        let Header::New(header) = &spc.header else {
+            return Err(MzmlError::OldFormat);
+        };
+        if !matches!(
+            header.instrument_technique(),
+            InstrumentTechnique::MassSpectrum
+        ) {
+            return Err(MzmlError::NotAMassSpectrum(header.instrument_technique()));
+        }
+
+        let root = if self.indexed {
+            concat!(
+                r#"<indexedmzML xmlns="http://psi.hupo.org/ms/mzml" "#,
+                r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" "#,
+                r#"xsi:schemaLocation="http://psi.hupo.org/ms/mzml "#,
+                r#"http://psidev.info/files/ms/mzML/xsd/mzML1.1.2_idx.xsd">"#,
+                "\n",
+                r#"<mzML xmlns="http://psi.hupo.org/ms/mzml" version="1.1.0">"#,
+            )
+        } else {
+            concat!(
+                r#"<mzML xmlns="http://psi.hupo.org/ms/mzml" "#,
+                r#"xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" "#,
+                r#"xsi:schemaLocation="http://psi.hupo.org/ms/mzml "#,
+                r#"http://psidev.info/files/ms/mzML/xsd/mzML1.1.0.xsd" version="1.1.0">"#,
+            )
+        };
+        let start = header
+            .datetime()
+            .map(|datetime| {
+                format!(
+                    r#" startTimeStamp="{}""#,
+                    datetime.format("%Y-%m-%dT%H:%M:%SZ")
+                )
+            })
+            .unwrap_or_default();
+        let traces = spc.traces();
+        let mut xml = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+{root}
+  <cvList count="2">
+    <cv id="MS" fullName="Proteomics Standards Initiative Mass Spectrometry Ontology" URI="https://raw.githubusercontent.com/HUPO-PSI/psi-ms-CV/master/psi-ms.obo"/>
+    <cv id="UO" fullName="Unit Ontology" URI="http://ontologies.berkeleybop.org/uo.obo"/>
+  </cvList>
+  <fileDescription>
+    <fileContent>
+      {spectrum_type}
+    </fileContent>
+  </fileDescription>
+  <softwareList count="1">
+    <software id="spc-rs" version="{version}">
+      {software}
+    </software>
+  </softwareList>
+  <instrumentConfigurationList count="1">
+    <instrumentConfiguration id="IC1">
+      {model}
+    </instrumentConfiguration>
+  </instrumentConfigurationList>
+  <dataProcessingList count="1">
+    <dataProcessing id="spc_conversion">
+      <processingMethod order="0" softwareRef="spc-rs">
+        {conversion}
+      </processingMethod>
+    </dataProcessing>
+  </dataProcessingList>
+  <run id="run1" defaultInstrumentConfigurationRef="IC1"{start}>
+    <spectrumList count="{count}" defaultDataProcessingRef="spc_conversion">
+"#,
+            spectrum_type = self.spectrum_type(),
+            version = env!("CARGO_PKG_VERSION"),
+            software = cv_param("MS:1000799", "custom unreleased software tool", "spc-rs"),
+            model = cv_param("MS:1000031", "instrument model", ""),
+            conversion = cv_param("MS:1000544", "Conversion to mzML", ""),
+            count = traces.len(),
+        );
+
+        // Times are kept in minutes if they are given in minutes, and otherwise written in seconds
+        let (scale, time_unit) = match header.z_unit_type() {
+            xzwType::Minutes => (1.0, ("UO", "UO:0000031", "minute")),
+            unit => (seconds(unit).unwrap_or(1.0), ("UO", "UO:0000010", "second")),
+        };
+        let representation = if let Block::XYXY { .. } = spc.block {
+            cv_param("MS:1000127", "centroid spectrum", "")
+        } else {
+            cv_param("MS:1000128", "profile spectrum", "")
+        };
+
+        let mut offsets = Vec::new();
+        for (index, trace) in traces.iter().enumerate() {
+            let id = format!("scan={}", index + 1);
+            xml.push_str("      ");
+            offsets.push((id.clone(), xml.len()));
+
+            let mut params = vec![
+                cv_param("MS:1000511", "ms level", &self.ms_level.to_string()),
+                self.spectrum_type(),
+                representation.clone(),
+                cv_param(
+                    "MS:1000285",
+                    "total ion current",
+                    &trace.y().iter().sum::<f64>().to_string(),
+                ),
+            ];
+            let base_peak = trace
+                .points()
+                .reduce(|base, point| if point.1 > base.1 { point } else { base });
+            if let Some((mz, intensity)) = base_peak {
+                params.push(unit_param("MS:1000504", "base peak m/z", mz, MZ));
+                params.push(unit_param(
+                    "MS:1000505",
+                    "base peak intensity",
+                    intensity,
+                    COUNTS,
+                ));
+            }
+            let lowest = trace.x().iter().copied().reduce(f64::min);
+            let highest = trace.x().iter().copied().reduce(f64::max);
+            if let (Some(lowest), Some(highest)) = (lowest, highest) {
+                params.push(unit_param("MS:1000528", "lowest observed m/z", lowest, MZ));
+                params.push(unit_param(
+                    "MS:1000527",
+                    "highest observed m/z",
+                    highest,
+                    MZ,
+                ));
+            }
+
+            let time = trace.time().unwrap_or(trace.z()) * scale;
+            write!(
+                xml,
+                r#"<spectrum index="{index}" id="{id}" defaultArrayLength="{points}">
+        {params}
+        <scanList count="1">
+          {combination}
+          <scan>
+            {time}
+          </scan>
+        </scanList>
+        <binaryDataArrayList count="2">
+{mz}{intensity}        </binaryDataArrayList>
+      </spectrum>
+"#,
+                points = trace.x().len(),
+                params = params.join("\n        "),
+                combination = cv_param("MS:1000795", "no combination", ""),
+                time = unit_param("MS:1000016", "scan start time", time, time_unit),
+                mz = binary_array(trace.x(), "MS:1000514", "m/z array", MZ),
+                intensity = binary_array(trace.y(), "MS:1000515", "intensity array", COUNTS),
+            )
+            .unwrap();
+        }
+        xml.push_str("    </spectrumList>\n  </run>\n</mzML>\n");
+        Ok((xml, offsets))
+    }
+
+    fn spectrum_type(&self) -> String {
+        if self.ms_level == 1 {
+            cv_param("MS:1000579", "MS1 spectrum", "")
+        } else {
+            cv_param("MS:1000580", "MSn spectrum", "")
+        }
+    }
+}
+
+impl WriteSPC for MzmlWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        let (mut xml, offsets) = self.document(spc)?;
+        if self.indexed {
+            let index_offset = xml.len();
+            writeln!(xml, r#"<indexList count="1">"#).unwrap();
+            writeln!(xml, r#"  <index name="spectrum">"#).unwrap();
+            for (id, offset) in offsets {
+                writeln!(xml, r#"    <offset idRef="{id}">{offset}</offset>"#).unwrap();
+            }
+            writeln!(xml, "  </index>").unwrap();
+            writeln!(xml, "</indexList>").unwrap();
+            writeln!(xml, "<indexListOffset>{index_offset}</indexListOffset>").unwrap();
+            // The checksum covers the file up to and including the opening tag of the checksum
+            xml.push_str("<fileChecksum>");
+            let checksum = sha1_smol::Sha1::from(&xml).digest().to_string();
+            writeln!(xml, "{checksum}</fileChecksum>").unwrap();
+            writeln!(xml, "</indexedmzML>").unwrap();
+        }
+        writer.write_all(xml.as_bytes()).into_diagnostic()
+    }
+}
+
+fn cv_param(accession: &str, name: &str, value: &str) -> String {
+    format!(r#"<cvParam cvRef="MS" accession="{accession}" name="{name}" value="{value}"/>"#)
+}
+
+// A parameter with a value in the given unit, named by its ontology, accession and name
+fn unit_param(accession: &str, name: &str, value: impl Display, unit: Unit) -> String {
+    let (cv, unit_accession, unit_name) = unit;
+    format!(
+        r#"<cvParam cvRef="MS" accession="{accession}" name="{name}" value="{value}" unitCvRef="{cv}" unitAccession="{unit_accession}" unitName="{unit_name}"/>"#
+    )
+}
+
+// The values as little-endian doubles in base64, without compression
+fn binary_array(values: &[f64], accession: &str, name: &str, unit: Unit) -> String {
+    let bytes = values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect::<Vec<_>>();
+    let encoded = STANDARD.encode(bytes);
+    format!(
+        r#"          <binaryDataArray encodedLength="{len}">
+            {float}
+            {compression}
+            {kind}
+            <binary>{encoded}</binary>
+          </binaryDataArray>
+"#,
+        len = encoded.len(),
+        float = cv_param("MS:1000523", "64-bit float", ""),
+        compression = cv_param("MS:1000576", "no compression", ""),
+        kind = unit_param(accession, name, "", unit),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use super::MzmlWriter;
+    use crate::{write::WriteSPC, xzwType, CsvReader, Header, InstrumentTechnique};
+
+    #[test]
+    fn gc_ms_runs_are_written_as_indexed_centroid_spectra() {
+        // Two peak lists of a run in minutes, as z = 1.5 and z = 2
+        let mut spc = CsvReader::new()
+            .read("x,y\n# z = 1.5\n41,120\n43.5,980\n57,300\n# z = 2\n44,60\n")
+            .unwrap();
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.instrument_technique = InstrumentTechnique::MassSpectrum;
+        header.z_unit_type = xzwType::Minutes;
+
+        let mut file = Vec::new();
+        MzmlWriter::new()
+            .with_index(true)
+            .write_spc(&mut file, &spc)
+            .unwrap();
+        let xml = String::from_utf8(file).unwrap();
+
+        assert_eq!(xml.matches("<spectrum ").count(), 2);
+        assert!(xml.contains(r#"accession="MS:1000127" name="centroid spectrum""#));
+        assert!(xml.contains(r#"name="base peak m/z" value="43.5""#));
+        assert!(xml.contains(
+            r#"name="scan start time" value="1.5" unitCvRef="UO" unitAccession="UO:0000031""#
+        ));
+        let binary = xml.split("<binary>").nth(1).unwrap();
+        let binary = STANDARD
+            .decode(&binary[..binary.find('<').unwrap()])
+            .unwrap();
+        assert_eq!(&binary[8..16], 43.5f64.to_le_bytes());
+
+        // Every offset in the index points at its spectrum, and the checksum covers the file up
+        // to the checksum itself
+        let offset = xml.split(r#"<offset idRef="scan=2">"#).nth(1).unwrap();
+        let offset = offset[..offset.find('<').unwrap()]
+            .parse::<usize>()
+            .unwrap();
+        assert!(xml[offset..].starts_with(r#"<spectrum index="1" id="scan=2""#));
+        let (covered, checksum) = xml.split_once("<fileChecksum>").unwrap();
+        assert_eq!(
+            sha1_smol::Sha1::from(format!("{covered}<fileChecksum>"))
+                .digest()
+                .to_string(),
+            checksum[..40]
+        );
+
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.instrument_technique = InstrumentTechnique::FTIRFTNIRFTRaman;
+        assert!(MzmlWriter::new().write_spc(&mut Vec::new(), &spc).is_err());
+    }
+}
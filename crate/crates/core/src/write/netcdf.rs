@@ -0,0 +1,212 @@
+//! A writer for the netCDF classic format, the container of ANDI files.
+//!
+//! Only what the ANDI templates need is supported: fixed-size variables of characters and floats,
+//! with no record dimension. Every number is stored big-endian, and every name, attribute and
+//! variable is padded to a multiple of four bytes.
+
+// The tags of the lists in the header
+const NC_DIMENSION: u32 = 0x0a;
+const NC_VARIABLE: u32 = 0x0b;
+const NC_ATTRIBUTE: u32 = 0x0c;
+
+/// The values of an attribute or a variable
+#[derive(Clone, Debug)]
+pub(crate) enum Values {
+    Char(String),
+    Float(Vec<f32>),
+}
+
+impl Values {
+    fn nc_type(&self) -> u32 {
+        match self {
+            Self::Char(_) => 2,
+            Self::Float(_) => 5,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Char(value) => value.len(),
+            Self::Float(values) => values.len(),
+        }
+    }
+
+    // The values, padded to a multiple of four bytes
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = match self {
+            Self::Char(value) => value.as_bytes().to_vec(),
+            Self::Float(values) => values.iter().flat_map(|v| v.to_be_bytes()).collect(),
+        };
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Variable {
+    name: String,
+    dimensions: Vec<usize>,
+    attributes: Vec<(String, Values)>,
+    values: Values,
+}
+
+/// A netCDF classic file, built up in memory and encoded at once
+#[derive(Clone, Debug, Default)]
+pub(crate) struct NetCdf {
+    dimensions: Vec<(String, usize)>,
+    attributes: Vec<(String, Values)>,
+    variables: Vec<Variable>,
+}
+
+impl NetCdf {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a dimension, returning its id
+    pub(crate) fn add_dimension(&mut self, name: &str, len: usize) -> usize {
+        self.dimensions.push((name.to_owned(), len));
+        self.dimensions.len() - 1
+    }
+
+    /// Add a global attribute
+    pub(crate) fn add_attribute(&mut self, name: &str, values: Values) {
+        self.attributes.push((name.to_owned(), values));
+    }
+
+    /// Add a variable over the given dimensions, with the values in row-major order
+    pub(crate) fn add_variable(
+        &mut self,
+        name: &str,
+        dimensions: &[usize],
+        attributes: Vec<(String, Values)>,
+        values: Values,
+    ) {
+        self.variables.push(Variable {
+            name: name.to_owned(),
+            dimensions: dimensions.to_vec(),
+            attributes,
+            values,
+        });
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        // The header holds the offset of the data of each variable, so it is encoded once to find
+        // its length. The offsets are fixed-width, so this length does not change
+        let data = self
+            .variables
+            .iter()
+            .map(|variable| variable.values.encode())
+            .collect::<Vec<_>>();
+        let mut begin = self.header(&vec![0; data.len()]).len();
+        let mut offsets = Vec::new();
+        for values in &data {
+            offsets.push(begin as u32);
+            begin += values.len();
+        }
+
+        let mut file = self.header(&offsets);
+        for values in data {
+            file.extend(values);
+        }
+        file
+    }
+
+    fn header(&self, offsets: &[u32]) -> Vec<u8> {
+        // The magic number of the classic format, and the number of records
+        let mut header = b"CDF\x01".to_vec();
+        header.extend(0u32.to_be_bytes());
+
+        list(&mut header, NC_DIMENSION, self.dimensions.len());
+        for (name, len) in &self.dimensions {
+            header.extend(encode_name(name));
+            header.extend((*len as u32).to_be_bytes());
+        }
+
+        attributes(&mut header, &self.attributes);
+
+        list(&mut header, NC_VARIABLE, self.variables.len());
+        for (variable, offset) in self.variables.iter().zip(offsets) {
+            header.extend(encode_name(&variable.name));
+            header.extend((variable.dimensions.len() as u32).to_be_bytes());
+            for &dimension in &variable.dimensions {
+                header.extend((dimension as u32).to_be_bytes());
+            }
+            attributes(&mut header, &variable.attributes);
+            header.extend(variable.values.nc_type().to_be_bytes());
+            header.extend((variable.values.encode().len() as u32).to_be_bytes());
+            header.extend(offset.to_be_bytes());
+        }
+        header
+    }
+}
+
+// The tag and length of a list, or two zeros if the list is empty
+fn list(header: &mut Vec<u8>, tag: u32, len: usize) {
+    let tag = if len == 0 { 0 } else { tag };
+    header.extend(tag.to_be_bytes());
+    header.extend((len as u32).to_be_bytes());
+}
+
+fn attributes(header: &mut Vec<u8>, attributes: &[(String, Values)]) {
+    list(header, NC_ATTRIBUTE, attributes.len());
+    for (name, values) in attributes {
+        header.extend(encode_name(name));
+        header.extend(values.nc_type().to_be_bytes());
+        header.extend((values.len() as u32).to_be_bytes());
+        header.extend(values.encode());
+    }
+}
+
+fn encode_name(name: &str) -> Vec<u8> {
+    let mut bytes = (name.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(Values::Char(name.to_owned()).encode());
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NetCdf, Values};
+
+    #[test]
+    fn files_are_encoded_in_the_classic_format() {
+        let mut file = NetCdf::new();
+        let points = file.add_dimension("n", 2);
+        file.add_attribute("title", Values::Char("abcde".to_owned()));
+        file.add_variable("s", &[], Vec::new(), Values::Float(vec![0.5]));
+        file.add_variable(
+            "v",
+            &[points],
+            vec![("unit".to_owned(), Values::Char("s".to_owned()))],
+            Values::Float(vec![1.0, -2.0]),
+        );
+
+        let mut expected = b"CDF\x01\0\0\0\0".to_vec();
+        // One dimension `n` of length 2
+        expected.extend([
+            0, 0, 0, 0x0a, 0, 0, 0, 1, 0, 0, 0, 1, b'n', 0, 0, 0, 0, 0, 0, 2,
+        ]);
+        // One global attribute `title`, five characters long
+        expected.extend([0, 0, 0, 0x0c, 0, 0, 0, 1, 0, 0, 0, 5]);
+        expected.extend(b"title\0\0\0");
+        expected.extend([0, 0, 0, 2, 0, 0, 0, 5]);
+        expected.extend(b"abcde\0\0\0");
+        // A scalar float `s`, without attributes, whose data starts at byte 160
+        expected.extend([
+            0, 0, 0, 0x0b, 0, 0, 0, 2, 0, 0, 0, 1, b's', 0, 0, 0, 0, 0, 0, 0,
+        ]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 4, 0, 0, 0, 160]);
+        // A float `v` over `n`, with the attribute `unit`, whose data starts at byte 164
+        expected.extend([0, 0, 0, 1, b'v', 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        expected.extend([0, 0, 0, 0x0c, 0, 0, 0, 1, 0, 0, 0, 4]);
+        expected.extend(b"unit");
+        expected.extend([0, 0, 0, 2, 0, 0, 0, 1, b's', 0, 0, 0]);
+        expected.extend([0, 0, 0, 5, 0, 0, 0, 8, 0, 0, 0, 164]);
+        assert_eq!(expected.len(), 160);
+        expected.extend(0.5f32.to_be_bytes());
+        expected.extend(1.0f32.to_be_bytes());
+        expected.extend((-2.0f32).to_be_bytes());
+
+        assert_eq!(file.encode(), expected);
+    }
+}
@@ -0,0 +1,229 @@
+//! Writing the traces of a file as a NumPy `.npz` archive.
+//!
+//! The archive holds one `.npy` array per name, as written by `numpy.savez`:
+//!
+//! - `x`: the x-values shared by every trace
+//! - `y`: the y-values, with one row per trace for multifiles and a single row otherwise
+//! - `x_<index>` and `y_<index>`: the values of each trace of an XYXY file, which can have
+//!   different lengths
+//! - `z`: the z-value of each trace
+//! - `metadata`: the header, flags and log entries as a JSON string
+
+use std::io::{Cursor, Write};
+
+use miette::IntoDiagnostic;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{block::Block, metadata::ParsedMetadata, ParsedSPC};
+
+use super::WriteSPC;
+
+// The magic string and the version of the `.npy` format written
+const NPY_MAGIC: &[u8; 8] = b"\x93NUMPY\x01\x00";
+
+/// Writes the traces of a file as a NumPy `.npz` archive
+#[derive(Clone, Debug, Default)]
+pub struct NpzWriter {
+    compressed: bool,
+}
+
+impl NpzWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deflate each array, as `numpy.savez_compressed` does
+    pub fn with_compression(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    // Each array in the archive, with its name
+    fn arrays(&self, spc: &ParsedSPC) -> miette::Result<Vec<(String, Vec<u8>)>> {
+        let traces = spc.traces();
+        let mut arrays = Vec::new();
+        match spc.block {
+            Block::XYXY { .. } => {
+                for trace in &traces {
+                    let len = trace.x().len();
+                    arrays.push((
+                        format!("x_{}", trace.index()),
+                        npy("<f8", &[len], &floats(trace.x())),
+                    ));
+                    arrays.push((
+                        format!("y_{}", trace.index()),
+                        npy("<f8", &[len], &floats(trace.y())),
+                    ));
+                }
+            }
+            _ => {
+                let x = traces.first().map(|trace| trace.x()).unwrap_or_default();
+                arrays.push(("x".to_owned(), npy("<f8", &[x.len()], &floats(x))));
+                let y = traces
+                    .iter()
+                    .flat_map(|trace| trace.y())
+                    .copied()
+                    .collect::<Vec<_>>();
+                let shape = match spc.block {
+                    Block::YY(_) | Block::XYY { .. } => vec![traces.len(), x.len()],
+                    _ => vec![x.len()],
+                };
+                arrays.push(("y".to_owned(), npy("<f8", &shape, &floats(&y))));
+            }
+        }
+
+        let z = traces.iter().map(|trace| trace.z()).collect::<Vec<_>>();
+        arrays.push(("z".to_owned(), npy("<f8", &[z.len()], &floats(&z))));
+
+        // NumPy strings are fixed-width UTF-32, so the single string is as wide as it is long
+        let metadata = ParsedMetadata::new(spc).to_json()?;
+        let chars = metadata
+            .chars()
+            .flat_map(|c| u32::from(c).to_le_bytes())
+            .collect::<Vec<_>>();
+        arrays.push((
+            "metadata".to_owned(),
+            npy(&format!("<U{}", chars.len() / 4), &[], &chars),
+        ));
+        Ok(arrays)
+    }
+}
+
+impl WriteSPC for NpzWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        let options = SimpleFileOptions::default().compression_method(if self.compressed {
+            CompressionMethod::Deflated
+        } else {
+            CompressionMethod::Stored
+        });
+
+        // The zip writer seeks back to each local header, so the archive is built in memory
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, array) in self.arrays(spc)? {
+            archive
+                .start_file(format!("{name}.npy"), options)
+                .into_diagnostic()?;
+            archive.write_all(&array).into_diagnostic()?;
+        }
+        let archive = archive.finish().into_diagnostic()?;
+        writer.write_all(archive.get_ref()).into_diagnostic()
+    }
+}
+
+fn floats(values: &[f64]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+// An array in the `.npy` format, with its header padded so the data starts on a multiple of 64
+// bytes
+fn npy(descr: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let shape = match shape {
+        [len] => format!("({len},)"),
+        shape => format!(
+            "({})",
+            shape
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+    // The magic string and the length of the header come first, and the header ends in a newline
+    let padded = (NPY_MAGIC.len() + 2 + header.len() + 1).div_ceil(64) * 64;
+    header.extend(std::iter::repeat_n(
+        ' ',
+        padded - NPY_MAGIC.len() - 2 - header.len() - 1,
+    ));
+    header.push('\n');
+
+    let mut array = NPY_MAGIC.to_vec();
+    array.extend((header.len() as u16).to_le_bytes());
+    array.extend(header.as_bytes());
+    array.extend(data);
+    array
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::NpzWriter;
+    use crate::{
+        fixtures::{multifile, xyxy},
+        write::WriteSPC,
+    };
+
+    // The header and data of each array in the archive
+    fn unpack(archive: Vec<u8>) -> Vec<(String, String, Vec<u8>)> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len())
+            .map(|index| {
+                let mut file = archive.by_index(index).unwrap();
+                let mut npy = Vec::new();
+                file.read_to_end(&mut npy).unwrap();
+                assert!(npy.starts_with(b"\x93NUMPY\x01\x00"));
+                let len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+                assert_eq!((10 + len) % 64, 0);
+                let header = String::from_utf8(npy[10..10 + len].to_vec()).unwrap();
+                (file.name().to_owned(), header, npy[10 + len..].to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn multifiles_are_written_as_two_dimensional_arrays_and_xyxy_files_as_many() {
+        let spc = multifile();
+        let mut archive = Vec::new();
+        NpzWriter::new().write_spc(&mut archive, &spc).unwrap();
+        let arrays = unpack(archive);
+
+        let names = arrays
+            .iter()
+            .map(|(name, ..)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["x.npy", "y.npy", "z.npy", "metadata.npy"]);
+        let (_, header, data) = &arrays[1];
+        assert!(header.contains("'descr': '<f8'"));
+        assert!(header.contains("'shape': (2, 3)"));
+        assert_eq!(&data[24..32], (-4.0f64).to_le_bytes());
+
+        let (_, header, data) = &arrays[3];
+        let metadata = data
+            .chunks_exact(4)
+            .map(|c| char::from_u32(u32::from_le_bytes(c.try_into().unwrap())).unwrap())
+            .collect::<String>();
+        assert!(header.contains(&format!("'descr': '<U{}'", metadata.chars().count())));
+        assert!(header.contains("'shape': ()"));
+        assert!(metadata.contains(r#""memo":"kinetics""#));
+
+        let xyxy = xyxy();
+        let mut archive = Vec::new();
+        NpzWriter::new()
+            .with_compression(true)
+            .write_spc(&mut archive, &xyxy)
+            .unwrap();
+        let arrays = unpack(archive);
+        let names = arrays
+            .iter()
+            .map(|(name, ..)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "x_0.npy",
+                "y_0.npy",
+                "x_1.npy",
+                "y_1.npy",
+                "z.npy",
+                "metadata.npy"
+            ]
+        );
+        assert!(arrays[0].1.contains("'shape': (2,)"));
+        assert!(arrays[2].1.contains("'shape': (1,)"));
+    }
+}
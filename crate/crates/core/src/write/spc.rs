@@ -0,0 +1,522 @@
+use std::io::Write;
+
+use miette::IntoDiagnostic;
+use zerocopy::{
+    byteorder::{F32, I16, I32, U16},
+    BigEndian, ByteOrder, IntoBytes, LittleEndian,
+};
+
+use crate::{
+    block::{Block, Directory, Subfile, XData, YData},
+    header::{DataShape, Header, LexedNewFormatHeader, LexedOldFormatHeader, NewFormatHeader},
+    lex::Version,
+    OldFormatHeader, ParsedSPC,
+};
+
+use super::WriteSPC;
+
+/// The version of the SPC format written, which also fixes the byte order
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SpcVersion {
+    /// The new format with little-endian data, version 0x4b
+    #[default]
+    NewLittleEndian,
+    /// The new format with big-endian data, version 0x4c
+    NewBigEndian,
+    /// The old format used before GRAMS/32 4.0, version 0x4d, which is always little-endian
+    Old,
+}
+
+impl SpcVersion {
+    /// The version byte stored in the file
+    pub fn code(self) -> u8 {
+        match self {
+            Self::NewLittleEndian => 0x4b,
+            Self::NewBigEndian => 0x4c,
+            Self::Old => 0x4d,
+        }
+    }
+
+    /// The version with the given version byte, if it is one
+    pub fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0x4b => Some(Self::NewLittleEndian),
+            0x4c => Some(Self::NewBigEndian),
+            0x4d => Some(Self::Old),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+pub(crate) enum SpcWriteError {
+    #[error("the old format has no x-values per subfile, so cannot hold XYXY files")]
+    #[diagnostic(help("write the file in the new format, as 0x4b or 0x4c"))]
+    XyxyInOldFormat,
+    #[error("subfile {index} holds {points} points, but the first holds {expected}")]
+    #[diagnostic(help("only XYXY files can hold subfiles of different lengths"))]
+    UnequalSubfiles {
+        index: usize,
+        points: usize,
+        expected: usize,
+    },
+}
+
+/// Writes a parsed file back out as an SPC file, by default in the new little-endian format
+/// (version 0x4b)
+///
+/// The data is written as it is stored, so integer y-values keep their exponent and float
+/// y-values are written as 32-bit floats. Everything which depends on the layout of the file is
+/// recomputed: the shape flags, the number of points and subfiles, the XYXY directory and the
+/// offset of the log block.
+///
+/// Files can be converted between versions. Anything the target version cannot hold, such as the
+/// log block of a file written in the old format, is dropped and reported by
+/// [`SpcWriter::losses`].
+#[derive(Clone, Debug, Default)]
+pub struct SpcWriter {
+    version: SpcVersion,
+}
+
+impl SpcWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the version of the file written
+    pub fn with_version(mut self, version: SpcVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn version(&self) -> SpcVersion {
+        self.version
+    }
+
+    /// A description of each part of the file which the version written cannot hold, and so is
+    /// dropped
+    pub fn losses(&self, spc: &ParsedSPC) -> Vec<String> {
+        let mut losses = Vec::new();
+        match (&spc.header, self.version) {
+            (Header::New(header), SpcVersion::Old) => {
+                if spc.log.is_some() {
+                    losses.push("the log block, as the old format has no log".to_owned());
+                }
+                if header.w_planes != 0 {
+                    losses.push(format!(
+                        "the grouping of the subfiles into {} w-planes",
+                        header.w_planes
+                    ));
+                }
+                if header.z_sub_increment != 0.0 {
+                    losses.push(
+                        "the z-increment, so z-values are only those stored in the subheaders"
+                            .to_owned(),
+                    );
+                }
+                if header.z_unit_type as u8 > 0x0f {
+                    losses.push(format!(
+                        "the z units {:?}, which are written as arbitrary",
+                        header.z_unit_type
+                    ));
+                }
+                if header.resolution_description.raw().len() > 8
+                    && header.resolution_description.raw()[8..]
+                        .iter()
+                        .any(|&b| b != 0)
+                {
+                    losses.push(
+                        "the end of the resolution description, which is cut to 8 bytes".to_owned(),
+                    );
+                }
+
+                let empty = NewFormatHeader::empty();
+                let fields = [
+                    (
+                        "instrument technique",
+                        header.instrument_technique as u8 != empty.instrument_technique as u8,
+                    ),
+                    (
+                        "posting disposition",
+                        header.posting_disposition != empty.posting_disposition,
+                    ),
+                    (
+                        "source instrument",
+                        !header.source_instrument_description.as_str().is_empty(),
+                    ),
+                    ("modifications", header.modified_flag != empty.modified_flag),
+                    (
+                        "processing code",
+                        header.processing_code != empty.processing_code,
+                    ),
+                    ("calibration level", header.calibration_level().is_some()),
+                    (
+                        "sub-method sample injection number",
+                        header.sub_method_sample_injection_number != 0,
+                    ),
+                    ("concentration factor", header.concentration_factor != 0.0),
+                    ("method file", !header.method_file.as_str().is_empty()),
+                    ("w units", header.w_axis_units != empty.w_axis_units),
+                ];
+                let fields = fields
+                    .into_iter()
+                    .filter_map(|(name, set)| set.then_some(name))
+                    .collect::<Vec<_>>();
+                if !fields.is_empty() {
+                    losses.push(format!(
+                        "the {}, which the old format does not store",
+                        fields.join(", ")
+                    ));
+                }
+            }
+            (Header::Old(header), SpcVersion::NewLittleEndian | SpcVersion::NewBigEndian)
+                if header.scans != 0 =>
+            {
+                losses.push(format!(
+                    "the number of scans, {}, which the new format does not store",
+                    header.scans
+                ));
+            }
+            _ => {}
+        }
+        losses
+    }
+}
+
+impl WriteSPC for SpcWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        for loss in self.losses(spc) {
+            log::warn!("dropping {loss}");
+        }
+        let bytes = match self.version {
+            SpcVersion::NewLittleEndian => encode::<LittleEndian>(spc, 0x4b)?,
+            SpcVersion::NewBigEndian => encode::<BigEndian>(spc, 0x4c)?,
+            SpcVersion::Old => encode_old(spc)?,
+        };
+        writer.write_all(&bytes).into_diagnostic()
+    }
+}
+
+// Lay out the whole file in the new format, in byte order `E`
+fn encode<E: ByteOrder>(spc: &ParsedSPC, file_version: u8) -> miette::Result<Vec<u8>> {
+    let mut header = match &spc.header {
+        Header::New(header) => header.clone(),
+        Header::Old(header) => NewFormatHeader::from_old(header),
+    };
+    let header_size = size_of::<LexedNewFormatHeader<E>>();
+
+    let mut body = Vec::new();
+    let (shape, number_points) =
+        write_block::<E>(&mut body, &spc.block, header_size, Version::New)?;
+    header.number_points = number_points;
+    header.flags = header.flags.with_shape(shape);
+    header.file_version = file_version;
+    header.spectra = spc.block.subfiles().len() as u32;
+    header.log_offset = 0;
+    if let Some(log) = &spc.log {
+        header.log_offset = (header_size + body.len()) as u32;
+        body.extend_from_slice(&log.to_bytes::<E>());
+    }
+
+    let mut bytes = header.to_lexed::<E>().as_bytes().to_vec();
+    bytes.append(&mut body);
+    Ok(bytes)
+}
+
+// Lay out the whole file in the old format, which has no log and no count of the subfiles
+fn encode_old(spc: &ParsedSPC) -> miette::Result<Vec<u8>> {
+    if let Block::XYXY { .. } = spc.block {
+        return Err(SpcWriteError::XyxyInOldFormat.into());
+    }
+    let mut header = match &spc.header {
+        Header::New(header) => OldFormatHeader::from_new(header),
+        Header::Old(header) => header.clone(),
+    };
+    let header_size = size_of::<LexedOldFormatHeader<LittleEndian>>();
+
+    let mut body = Vec::new();
+    let (shape, number_points) =
+        write_block::<LittleEndian>(&mut body, &spc.block, header_size, Version::Old)?;
+    header.number_points = number_points as f32;
+    header.flags = header.flags.with_shape(shape);
+    header.version = 0x4d;
+
+    let mut bytes = header.to_lexed::<LittleEndian>().as_bytes().to_vec();
+    bytes.append(&mut body);
+    Ok(bytes)
+}
+
+// Write the subfiles following a header of `header_size` bytes, returning the shape of the data
+// and the number of points stored in the header
+fn write_block<E: ByteOrder>(
+    body: &mut Vec<u8>,
+    block: &Block,
+    header_size: usize,
+    version: Version,
+) -> Result<(DataShape, u32), SpcWriteError> {
+    let shape = match block {
+        Block::Y(subfile) => {
+            write_subfile::<E>(body, subfile, None, version);
+            DataShape::Y
+        }
+        Block::YY(subfiles) => {
+            for subfile in subfiles {
+                write_subfile::<E>(body, subfile, None, version);
+            }
+            DataShape::YY
+        }
+        Block::XY { x, y } => {
+            write_x::<E>(body, x);
+            write_subfile::<E>(body, y, None, version);
+            DataShape::XY
+        }
+        Block::XYY { x, ys } => {
+            write_x::<E>(body, x);
+            for subfile in ys {
+                write_subfile::<E>(body, subfile, None, version);
+            }
+            DataShape::XYY
+        }
+        Block::XYXY { data, directory } => {
+            let mut entries = Vec::new();
+            for (index, (x, y)) in data.iter().enumerate() {
+                let position = header_size + body.len();
+                write_subfile::<E>(body, y, Some(x), version);
+                let time = directory
+                    .as_ref()
+                    .and_then(|directory| directory.get(index))
+                    .map_or(0.0, Directory::time);
+                entries.push(Directory::new(
+                    position as u32,
+                    (header_size + body.len() - position) as u32,
+                    time,
+                ));
+            }
+
+            // The number of points of an XYXY file is replaced by the offset of the directory, or
+            // zero if there is no directory
+            let mut directory_offset = 0;
+            if directory.is_some() {
+                directory_offset = (header_size + body.len()) as u32;
+                for entry in &entries {
+                    body.extend_from_slice(entry.to_lexed::<E>().as_bytes());
+                }
+            }
+            return Ok((DataShape::XYXY, directory_offset));
+        }
+    };
+
+    // Every subfile of the other shapes has the number of points stored in the header
+    let subfiles = block.subfiles();
+    let expected = subfiles
+        .first()
+        .map_or(0, |(_, subfile)| subfile.data.len());
+    for (index, (_, subfile)) in subfiles.iter().enumerate() {
+        if subfile.data.len() != expected {
+            return Err(SpcWriteError::UnequalSubfiles {
+                index,
+                points: subfile.data.len(),
+                expected,
+            });
+        }
+    }
+    Ok((shape, expected as u32))
+}
+
+fn write_x<E: ByteOrder>(output: &mut Vec<u8>, x: &XData) {
+    for &x in x.iter() {
+        output.extend_from_slice(F32::<E>::new(x).as_bytes());
+    }
+}
+
+// Write a subheader followed by the y-values, with the x-values in between for XYXY files
+fn write_subfile<E: ByteOrder>(
+    output: &mut Vec<u8>,
+    subfile: &Subfile,
+    x: Option<&XData>,
+    version: Version,
+) {
+    let mut subheader = subfile.subheader.clone();
+    // Float data is marked by the subheader exponent, and only XYXY files store the number of
+    // points in each subheader
+    if let YData::Float(_) = subfile.data {
+        subheader.exponent_y = -128;
+    }
+    if x.is_some() {
+        subheader.number_points = subfile.data.len() as u32;
+    }
+    output.extend_from_slice(subheader.to_lexed::<E>().as_bytes());
+
+    if let Some(x) = x {
+        write_x::<E>(output, x);
+    }
+    match &subfile.data {
+        YData::SixteenBitInteger(values) => {
+            for &y in values {
+                output.extend_from_slice(I16::<E>::new(y).as_bytes());
+            }
+        }
+        // The old format stores the most significant word of 32-bit integers first
+        YData::ThirtyTwoBitInteger(values) => match version {
+            Version::Old => {
+                for &y in values {
+                    output.extend_from_slice(I16::<E>::new((y >> 16) as i16).as_bytes());
+                    output.extend_from_slice(U16::<E>::new(y as u16).as_bytes());
+                }
+            }
+            Version::New => {
+                for &y in values {
+                    output.extend_from_slice(I32::<E>::new(y).as_bytes());
+                }
+            }
+        },
+        YData::Float(values) => {
+            for &y in values {
+                output.extend_from_slice(F32::<E>::new(y as f32).as_bytes());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{FixedOffset, Timelike};
+
+    use super::{SpcVersion, SpcWriter};
+    use crate::{
+        block::{Block, YData},
+        fixtures::{multifile, xyxy},
+        parse, parse_with_options,
+        write::WriteSPC,
+        Header, HeaderTimezone, InstrumentTechnique, ParseOptions, ParsedSPC,
+    };
+
+    fn write(spc: &ParsedSPC, version: SpcVersion) -> Vec<u8> {
+        let mut written = Vec::new();
+        SpcWriter::new()
+            .with_version(version)
+            .write_spc(&mut written, spc)
+            .unwrap();
+        written
+    }
+
+    #[test]
+    fn xyxy_file_with_a_directory_is_written_unchanged() {
+        // An XYXY file with two subfiles of one and two float points, and a directory
+        let mut source = vec![0; 512];
+        source[0] = 0xc4;
+        source[1] = 0x4b;
+        source[24..28].copy_from_slice(&2u32.to_le_bytes());
+        let mut directory = Vec::new();
+        for (index, points) in [1u32, 2].into_iter().enumerate() {
+            let position = source.len() as u32;
+            let mut subheader = [0; 32];
+            subheader[1] = 0x80;
+            subheader[4..8].copy_from_slice(&(index as f32).to_le_bytes());
+            subheader[16..20].copy_from_slice(&points.to_le_bytes());
+            source.extend_from_slice(&subheader);
+            for value in 0..2 * points {
+                source.extend_from_slice(&(value as f32).to_le_bytes());
+            }
+            directory.extend_from_slice(&position.to_le_bytes());
+            directory.extend_from_slice(&(source.len() as u32 - position).to_le_bytes());
+            directory.extend_from_slice(&(1.5 * index as f32).to_le_bytes());
+        }
+        let directory_offset = source.len() as u32;
+        source[4..8].copy_from_slice(&directory_offset.to_le_bytes());
+        source.extend_from_slice(&directory);
+
+        let mut written = Vec::new();
+        SpcWriter::new()
+            .write_spc(&mut written, &parse(&source).unwrap())
+            .unwrap();
+        assert_eq!(written, source);
+    }
+
+    #[test]
+    fn files_are_converted_between_versions_and_byte_orders() {
+        let mut spc = multifile();
+        // 32-bit integers scaled by one, whose low words do not fit in a signed 16-bit integer
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.exponent_y = 32;
+        header.instrument_technique = InstrumentTechnique::FTIRFTNIRFTRaman;
+        let Block::XYY { ys, .. } = &mut spc.block else {
+            unreachable!()
+        };
+        for (subfile, values) in ys.iter_mut().zip([[0x1_8000, -70000, 5], [-1, 0, 1]]) {
+            subfile.subheader.exponent_y = 32;
+            subfile.data = YData::ThirtyTwoBitInteger(values.to_vec());
+        }
+
+        let little = write(&spc, SpcVersion::NewLittleEndian);
+        let big = write(&parse(&little).unwrap(), SpcVersion::NewBigEndian);
+        assert_eq!(big[1], 0x4c);
+        assert_eq!(
+            write(&parse(&big).unwrap(), SpcVersion::NewLittleEndian),
+            little
+        );
+
+        // The old format has a 224-byte header, and stores the most significant word first
+        let old = SpcWriter::new().with_version(SpcVersion::Old);
+        assert_eq!(
+            old.losses(&spc),
+            ["the instrument technique, which the old format does not store"]
+        );
+        let written = write(&spc, SpcVersion::Old);
+        assert_eq!(written[1], 0x4d);
+        let first = 224 + 3 * 4 + 32;
+        assert_eq!(&written[first..first + 4], [0x01, 0x00, 0x00, 0x80]);
+        let converted = parse(&written).unwrap();
+        assert!(matches!(converted.header, Header::Old(_)));
+        let y = |spc: &ParsedSPC| {
+            spc.traces()
+                .iter()
+                .map(|trace| trace.y().to_vec())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(y(&converted), y(&spc));
+        assert_eq!(y(&converted)[0], [98304.0, -70000.0, 5.0]);
+
+        let xyxy = xyxy();
+        assert!(old.write_spc(&mut Vec::new(), &xyxy).is_err());
+
+        // Only XYXY files can hold subfiles of different lengths
+        let Block::XYY { ys, .. } = &mut spc.block else {
+            unreachable!()
+        };
+        ys[1].data = YData::ThirtyTwoBitInteger(vec![1, 2]);
+        assert!(SpcWriter::new().write_spc(&mut Vec::new(), &spc).is_err());
+    }
+
+    #[test]
+    fn collection_time_is_kept_as_stored_through_conversions() {
+        // Collected at 1994-08-26 16:45, and read as two hours ahead of UTC
+        let packed = (1994 << 20) | (8 << 16) | (26 << 11) | (16 << 6) | 45;
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let options = ParseOptions::new().with_timezone(HeaderTimezone::Fixed(offset));
+        let read = |source: &[u8]| {
+            let spc = parse_with_options(source, &options).unwrap();
+            let (packed, datetime) = match &spc.header {
+                Header::Old(header) => (header.packed_datetime(), header.datetime()),
+                Header::New(header) => (header.packed_datetime(), header.datetime()),
+            };
+            assert_eq!(datetime.unwrap().hour(), 14);
+            (spc, packed)
+        };
+
+        let mut spc = multifile();
+        let Header::New(header) = &mut spc.header else {
+            unreachable!()
+        };
+        header.packed_datetime = packed;
+        let (new, _) = read(&write(&spc, SpcVersion::NewLittleEndian));
+
+        let (old, new_to_old) = read(&write(&new, SpcVersion::Old));
+        let (_, old_to_old) = read(&write(&old, SpcVersion::Old));
+        let (_, old_to_new) = read(&write(&old, SpcVersion::NewBigEndian));
+        assert_eq!([new_to_old, old_to_old, old_to_new], [packed; 3]);
+    }
+}
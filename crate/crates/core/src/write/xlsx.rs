@@ -0,0 +1,164 @@
+//! Writing the traces of a file as an Excel workbook.
+//!
+//! The `Data` sheet holds one column of x-values followed by one column of y-values per trace,
+//! headed by the z-value of the trace, such as `z = 5`. The traces of XYXY files each have their
+//! own x-column. The `Metadata` sheet holds the decoded header and the log entries as key/value
+//! rows.
+//!
+//! Values are stored as numbers, so nothing depends on the locale of the reader.
+
+use std::io::Write;
+
+use miette::IntoDiagnostic;
+use rust_xlsxwriter::{Chart, ChartType, ColNum, Format, RowNum, Workbook};
+
+use crate::{block::Block, metadata::ParsedMetadata, ParsedSPC};
+
+use super::WriteSPC;
+
+const DATA_SHEET: &str = "Data";
+const METADATA_SHEET: &str = "Metadata";
+
+// Excel draws at most 255 series in one chart
+const MAX_CHART_SERIES: usize = 255;
+
+/// Writes the traces and header of a file as an Excel workbook
+#[derive(Clone, Debug, Default)]
+pub struct XlsxWriter {
+    chart: bool,
+}
+
+impl XlsxWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a line chart of the traces beside the data. Only the first 255 traces are drawn
+    pub fn with_chart(mut self, chart: bool) -> Self {
+        self.chart = chart;
+        self
+    }
+
+    fn workbook(
+        &self,
+        spc: &ParsedSPC,
+        entries: Vec<(String, String)>,
+    ) -> Result<Workbook, rust_xlsxwriter::XlsxError> {
+        let mut workbook = Workbook::new();
+        let bold = Format::new().set_bold();
+        let traces = spc.traces();
+        let [x_label, y_label, _] = spc.header.axis_labels();
+
+        // The x and y-columns of each trace, which are shared between traces unless the file is
+        // XYXY
+        let mut series = Vec::new();
+        let data = workbook.add_worksheet().set_name(DATA_SHEET)?;
+        data.set_freeze_panes(1, 0)?;
+        let xyxy = matches!(spc.block, Block::XYXY { .. });
+        let mut column: ColNum = 0;
+        for trace in &traces {
+            if xyxy || column == 0 {
+                data.write_string_with_format(0, column, &x_label, &bold)?;
+                for (row, &x) in trace.x().iter().enumerate() {
+                    data.write_number(row as RowNum + 1, column, x)?;
+                }
+                column += 1;
+            }
+            let x_column = if xyxy { column - 1 } else { 0 };
+
+            data.write_string_with_format(0, column, format!("z = {}", trace.z()), &bold)?;
+            for (row, &y) in trace.y().iter().enumerate() {
+                data.write_number(row as RowNum + 1, column, y)?;
+            }
+            series.push((x_column, column, trace.y().len() as RowNum));
+            column += 1;
+        }
+
+        if self.chart && !series.is_empty() {
+            let mut chart = Chart::new(ChartType::ScatterStraight);
+            for &(x_column, y_column, points) in series.iter().take(MAX_CHART_SERIES) {
+                chart
+                    .add_series()
+                    .set_name((DATA_SHEET, 0, y_column))
+                    .set_categories((DATA_SHEET, 1, x_column, points, x_column))
+                    .set_values((DATA_SHEET, 1, y_column, points, y_column));
+            }
+            chart.x_axis().set_name(x_label.as_str());
+            chart.y_axis().set_name(y_label.as_str());
+            data.insert_chart(1, column + 1, &chart)?;
+        }
+
+        let metadata = workbook.add_worksheet().set_name(METADATA_SHEET)?;
+        metadata.write_string_with_format(0, 0, "key", &bold)?;
+        metadata.write_string_with_format(0, 1, "value", &bold)?;
+        for (row, (key, value)) in entries.into_iter().enumerate() {
+            metadata.write_string(row as RowNum + 1, 0, key)?;
+            metadata.write_string(row as RowNum + 1, 1, value)?;
+        }
+        metadata.set_column_width(0, 30)?;
+
+        Ok(workbook)
+    }
+}
+
+impl WriteSPC for XlsxWriter {
+    type Error = miette::Report;
+    fn write_spc<W: Write>(&self, writer: &mut W, spc: &ParsedSPC) -> Result<(), Self::Error> {
+        let entries = ParsedMetadata::new(spc).entries()?;
+        let buffer = self
+            .workbook(spc, entries)
+            .and_then(|mut workbook| workbook.save_to_buffer())
+            .into_diagnostic()?;
+        writer.write_all(&buffer).into_diagnostic()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use super::XlsxWriter;
+    use crate::{
+        block::{Block, YData},
+        fixtures::multifile,
+        write::WriteSPC,
+    };
+
+    fn part(workbook: &[u8], name: &str) -> Option<String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(workbook)).unwrap();
+        let mut part = archive.by_name(name).ok()?;
+        let mut contents = String::new();
+        part.read_to_string(&mut contents).unwrap();
+        Some(contents)
+    }
+
+    #[test]
+    fn traces_are_columns_beside_a_metadata_sheet() {
+        let mut spc = multifile();
+        let Block::XYY { ys, .. } = &mut spc.block else {
+            unreachable!()
+        };
+        ys[1].data = YData::Float(vec![-4.0, 8.125, 0.0]);
+
+        let mut workbook = Vec::new();
+        XlsxWriter::new().write_spc(&mut workbook, &spc).unwrap();
+        let sheets = part(&workbook, "xl/workbook.xml").unwrap();
+        assert!(sheets.contains(r#"name="Data""#));
+        assert!(sheets.contains(r#"name="Metadata""#));
+        // The x-column is followed by a column for each trace, which stores numbers as numbers
+        let data = part(&workbook, "xl/worksheets/sheet1.xml").unwrap();
+        assert!(data.contains(r#"<c r="C3"><v>8.125</v></c>"#));
+        assert!(part(&workbook, "xl/charts/chart1.xml").is_none());
+        let strings = part(&workbook, "xl/sharedStrings.xml").unwrap();
+        assert!(strings.contains("memo") && strings.contains("kinetics"));
+
+        let mut workbook = Vec::new();
+        XlsxWriter::new()
+            .with_chart(true)
+            .write_spc(&mut workbook, &spc)
+            .unwrap();
+        let chart = part(&workbook, "xl/charts/chart1.xml").unwrap();
+        assert_eq!(chart.matches("<c:ser>").count(), 2);
+        assert!(chart.contains("Data!$C$2:$C$4"));
+    }
+}
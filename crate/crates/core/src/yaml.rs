@@ -0,0 +1,128 @@
+//! A small YAML emitter for the metadata, serializing through a JSON value.
+//!
+//! Maps and sequences are written in block style, two spaces per level, with empty ones written
+//! inline. Strings are left plain when they cannot be mistaken for another type or for YAML
+//! syntax, and are otherwise written as double quoted scalars, whose escapes are those of JSON.
+
+use std::fmt::Write;
+
+use miette::IntoDiagnostic;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Serialize a value as a YAML document
+pub(crate) fn to_string<T: Serialize>(value: &T) -> miette::Result<String> {
+    let value = serde_json::to_value(value).into_diagnostic()?;
+    let mut yaml = String::new();
+    match &value {
+        Value::Object(map) if !map.is_empty() => write_map(&mut yaml, map, 0),
+        Value::Array(values) if !values.is_empty() => write_sequence(&mut yaml, values, 0),
+        scalar => writeln!(yaml, "{}", inline(scalar)).unwrap(),
+    }
+    Ok(yaml)
+}
+
+fn write_map(yaml: &mut String, map: &serde_json::Map<String, Value>, indent: usize) {
+    for (key, value) in map {
+        // The first entry of a map in a sequence follows the dash of the item
+        if !yaml.ends_with("- ") {
+            yaml.push_str(&" ".repeat(indent));
+        }
+        write!(yaml, "{}:", string(key)).unwrap();
+        write_value(yaml, value, indent);
+    }
+}
+
+fn write_sequence(yaml: &mut String, values: &[Value], indent: usize) {
+    for value in values {
+        write!(yaml, "{}-", " ".repeat(indent)).unwrap();
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                yaml.push(' ');
+                write_map(yaml, map, indent + 2);
+            }
+            value => write_value(yaml, value, indent),
+        }
+    }
+}
+
+// Write the value of a map entry or sequence item after its key or dash
+fn write_value(yaml: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            yaml.push('\n');
+            write_map(yaml, map, indent + 2);
+        }
+        Value::Array(values) if !values.is_empty() => {
+            yaml.push('\n');
+            write_sequence(yaml, values, indent + 2);
+        }
+        scalar => writeln!(yaml, " {}", inline(scalar)).unwrap(),
+    }
+}
+
+// Scalars, and the empty collections, which are written inline
+fn inline(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::Bool(value) => value.to_string(),
+        Value::Number(value) => value.to_string(),
+        Value::String(value) => string(value),
+        Value::Array(_) => "[]".to_owned(),
+        Value::Object(_) => "{}".to_owned(),
+    }
+}
+
+fn string(value: &str) -> String {
+    let reserved = matches!(
+        value.to_ascii_lowercase().as_str(),
+        "" | "~" | "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+    );
+    let plain = !reserved
+        && value.starts_with(|c: char| c.is_ascii_alphabetic())
+        && !value.ends_with(' ')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '_' | '-' | '.' | '/'))
+        && value.parse::<f64>().is_err();
+    if plain {
+        value.to_owned()
+    } else {
+        Value::String(value.to_owned()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    #[test]
+    fn values_are_written_in_block_style() {
+        let value = json!({
+            "shape": "XYY",
+            "memo": "a: b\nc",
+            "flags": "true",
+            "points": [3, 3],
+            "range": null,
+            "log": {},
+            "subheaders": [{"index": 0, "z": 1.5}, {"index": 1, "z": 2.5}],
+        });
+        assert_eq!(
+            super::to_string(&value).unwrap(),
+            "shape: XYY
+memo: \"a: b\\nc\"
+flags: \"true\"
+points:
+  - 3
+  - 3
+range: null
+log: {}
+subheaders:
+  - index: 0
+    z: 1.5
+  - index: 1
+    z: 2.5
+"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Reconstruction of the z-axis of multifile data.
+//!
+//! Each subfile in a multifile has a z-value, such as a time or a temperature. Where it is stored
+//! depends on the file flags:
+//! - If TORDRD is clear the subfiles are evenly spaced in z. The first z-value is read from the
+//!   first subheader, and the rest are implied by the z-increment in the header. If the header
+//!   increment is zero, the increment is the difference between the `next_z` and `z` of the
+//!   first subheader.
+//! - If TORDRD is set the subfiles are ordered, but not evenly spaced, and the z-value of each
+//!   is read from its own subheader.
+//! - If TRANDM is set the z-values are read from each subheader and may be in any order.
+//!
+//! When the file has w-planes the z-axis restarts in each plane.
+
+use crate::header::{Header, Subheader};
+
+/// How the subfiles of a file are spaced along z, according to the file flags
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ZSpacing {
+    /// The z-values are evenly spaced by the given increment (TORDRD clear)
+    Even { increment: f64 },
+    /// The z-values are ordered, but not evenly spaced (TORDRD set)
+    Uneven,
+    /// The z-values are in a random order (TRANDM set)
+    Random,
+}
+
+/// The z-value of every subfile in a file
+#[derive(Clone, Debug)]
+pub struct ZAxis {
+    values: Vec<f64>,
+    spacing: ZSpacing,
+    per_plane: usize,
+    next_z_mismatches: Vec<usize>,
+}
+
+impl ZAxis {
+    pub(crate) fn new(header: &Header, subheaders: &[&Subheader], per_plane: usize) -> Self {
+        let flags = header.flags();
+        let per_plane = per_plane.max(1);
+
+        let spacing = if flags.z_values_are_random() {
+            ZSpacing::Random
+        } else if flags.z_values_are_uneven()
+            || !flags.multifile()
+            || (flags.xy() && flags.xyxy())
+        {
+            ZSpacing::Uneven
+        } else {
+            let increment = match (header.z_sub_increment(), subheaders.first()) {
+                (increment, _) if increment != 0.0 => increment as f64,
+                (_, Some(first)) => first.next_z() as f64 - first.z() as f64,
+                (_, None) => 0.0,
+            };
+            ZSpacing::Even { increment }
+        };
+
+        let values: Vec<f64> = match spacing {
+            ZSpacing::Even { increment } => {
+                let first = subheaders.first().map_or(0.0, |first| first.z() as f64);
+                (0..subheaders.len())
+                    .map(|index| first + (index % per_plane) as f64 * increment)
+                    .collect()
+            }
+            ZSpacing::Uneven | ZSpacing::Random => subheaders
+                .iter()
+                .map(|subheader| subheader.z() as f64)
+                .collect(),
+        };
+
+        // The next z of each subfile should match the z of the following subfile in its plane.
+        // Subheaders with both values zero are treated as unset.
+        let next_z_mismatches = subheaders
+            .iter()
+            .zip(values.iter().skip(1))
+            .enumerate()
+            .filter(|(index, _)| (index + 1) % per_plane != 0)
+            .filter(|(_, (subheader, _))| subheader.z() != 0.0 || subheader.next_z() != 0.0)
+            .filter(|(_, (subheader, next))| {
+                let next_z = subheader.next_z() as f64;
+                (next_z - **next).abs() > f32::EPSILON as f64 * next_z.abs().max(1.0)
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        Self {
+            values,
+            spacing,
+            per_plane,
+            next_z_mismatches,
+        }
+    }
+
+    /// The z-value of each subfile, in the order they are stored
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    /// The spacing declared by the file flags
+    pub fn spacing(&self) -> ZSpacing {
+        self.spacing
+    }
+
+    /// Whether the subfiles are randomly ordered, either because the file flags say so or
+    /// because the z-values in a plane are neither ascending nor descending
+    pub fn is_random(&self) -> bool {
+        self.spacing == ZSpacing::Random
+            || self.values.chunks(self.per_plane).any(|plane| {
+                let ascending = plane.windows(2).all(|pair| pair[0] <= pair[1]);
+                let descending = plane.windows(2).all(|pair| pair[0] >= pair[1]);
+                !(ascending || descending)
+            })
+    }
+
+    /// The indices of subfiles whose `next_z` does not match the z-value of the following subfile
+    pub fn next_z_mismatches(&self) -> &[usize] {
+        &self.next_z_mismatches
+    }
+
+    /// The indices of the subfiles, sorted by ascending z within each w-plane. Subfiles with equal
+    /// z-values keep the order they are stored in
+    pub fn sort_order(&self) -> Vec<usize> {
+        let mut order = (0..self.values.len()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| {
+            (a / self.per_plane)
+                .cmp(&(b / self.per_plane))
+                .then(self.values[a].total_cmp(&self.values[b]))
+        });
+        order
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ZSpacing;
+    use crate::parse;
+
+    // A new-format little-endian YY file with two float points per subfile. Each subheader
+    // stores the given (z, next z) pair
+    fn yy_file(flags: u8, z_increment: f32, z: &[(f32, f32)]) -> Vec<u8> {
+        let mut source = vec![0; 512];
+        source[0] = flags | 0x04;
+        source[1] = 0x4b;
+        source[4..8].copy_from_slice(&2u32.to_le_bytes());
+        source[8..16].copy_from_slice(&100f64.to_le_bytes());
+        source[16..24].copy_from_slice(&200f64.to_le_bytes());
+        source[24..28].copy_from_slice(&(z.len() as u32).to_le_bytes());
+        source[312..316].copy_from_slice(&z_increment.to_le_bytes());
+
+        for (index, (z, next_z)) in z.iter().enumerate() {
+            let mut subheader = [0; 32];
+            subheader[1] = 0x80;
+            subheader[2..4].copy_from_slice(&(index as u16).to_le_bytes());
+            subheader[4..8].copy_from_slice(&z.to_le_bytes());
+            subheader[8..12].copy_from_slice(&next_z.to_le_bytes());
+            source.extend_from_slice(&subheader);
+            for y in [1f32, 2.] {
+                source.extend_from_slice(&y.to_le_bytes());
+            }
+        }
+        source
+    }
+
+    #[test]
+    fn evenly_spaced_z_is_implied_by_the_increment() {
+        let source = yy_file(0, 0.5, &[(1.0, 1.5), (0.0, 0.0), (0.0, 0.0)]);
+        let z_axis = parse(&source).unwrap().z_axis();
+
+        assert_eq!(z_axis.spacing(), ZSpacing::Even { increment: 0.5 });
+        assert_eq!(z_axis.values(), [1.0, 1.5, 2.0]);
+        assert!(!z_axis.is_random());
+        assert!(z_axis.next_z_mismatches().is_empty());
+    }
+
+    #[test]
+    fn random_z_is_read_from_subheaders_and_sorted() {
+        // TRANDM
+        let source = yy_file(0x08, 0.0, &[(3.0, 1.0), (1.0, 5.0), (2.0, 9.0)]);
+        let parsed = parse(&source).unwrap();
+        let z_axis = parsed.z_axis();
+
+        assert_eq!(z_axis.spacing(), ZSpacing::Random);
+        assert_eq!(z_axis.values(), [3.0, 1.0, 2.0]);
+        assert!(z_axis.is_random());
+        assert_eq!(z_axis.next_z_mismatches(), [1]);
+        assert_eq!(z_axis.sort_order(), [1, 2, 0]);
+
+        let sorted = parsed
+            .traces_sorted_by_z()
+            .iter()
+            .map(|trace| (trace.index(), trace.z()))
+            .collect::<Vec<_>>();
+        assert_eq!(sorted, [(1, 1.0), (2, 2.0), (0, 3.0)]);
+    }
+}